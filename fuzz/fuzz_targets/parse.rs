@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Any input at all must come back as a value or a typed error; a panic
+// or stack overflow here is a bug
+fuzz_target!(|data : &[u8]| {
+  let input = String::from_utf8_lossy(data);
+  let _ = calc::eval(&input);
+});
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use interpreter::{EvalConfig, evaluate};
+use number::Number;
+use optimize::fold;
+use parser::AST;
+
+
+/**********************************************************************
+ * ANALYSIS
+ */
+
+/// The number of nodes on the longest path from the root down to a
+/// leaf, so a lone literal has depth 1.  Left-associative operators
+/// make trees that are deep on the left: `1-2-3` has depth 3.  This walks
+/// the tree without recursion, so it is safe on any tree.
+pub fn depth(a : &AST) -> usize {
+  let mut deepest = 0;
+  let mut nodes = vec![(a, 1)];
+  while let Some((node, d)) = nodes.pop() {
+    deepest = deepest.max(d);
+    nodes.extend(node.children().into_iter().map(|c| (c, d + 1)));
+  }
+  deepest
+}
+
+/// The number of nodes in the tree, counting every literal, variable,
+/// operator, and call.
+pub fn node_count(a : &AST) -> usize {
+  1 + a.children().into_iter().map(node_count).sum::<usize>()
+}
+
+/// Built-in functions that need not give the same value each call.  A
+/// tree that calls one is not constant, and its value is never reused.
+pub const IMPURE_FUNCTIONS : &[&str] = &["rand"];
+
+/// True if the tree's value depends only on its literals: it reads no
+/// variable, assigns none, and calls no function whose value can change
+/// from one call to the next.  Such a tree can be folded to a literal,
+/// unless evaluating it is an error.  This walks the tree without
+/// recursion, so it is safe on any tree.
+pub fn is_constant(a : &AST) -> bool {
+  is_pure(a) && all_nodes(a, |node| !matches!(*node, AST::Var(_)))
+}
+
+/// True if evaluating the tree twice in the same environment gives the
+/// same value and leaves the environment as it was: it assigns no
+/// variable and calls no function whose value can change from one call
+/// to the next.  Unlike `is_constant`, it may read variables.
+pub fn is_pure(a : &AST) -> bool {
+  all_nodes(a, |node| match *node {
+    AST::Assign(..) => false,
+    AST::Call(ref name, _) => !IMPURE_FUNCTIONS.contains(&name.as_str()),
+    _ => true
+  })
+}
+
+// Whether every node of the tree passes the test, walked without recursion
+fn all_nodes<F : Fn(&AST) -> bool>(a : &AST, test : F) -> bool {
+  let mut nodes = vec![a];
+  while let Some(node) = nodes.pop() {
+    if !test(node) {
+      return false;
+    }
+    nodes.extend(node.children());
+  }
+  true
+}
+
+// The value of a tree with no variables, if it has one
+fn constant<N : Number>(a : &AST) -> Option<N> {
+  evaluate(a, &mut HashMap::new(), &EvalConfig::default()).ok()
+}
+
+/// True if the trees mean the same thing as far as can be told without
+/// knowing any variables: either both evaluate in an empty environment
+/// to the same value, or they are identical once folded.  Values are
+/// compared as i32, or as f64 if either tree involves floats, as the
+/// REPL would evaluate them.
+pub fn equivalent(a : &AST, b : &AST) -> bool {
+  let same = if a.has_float() || b.has_float() {
+    constant::<f64>(a).zip(constant::<f64>(b)).map(|(x, y)| x == y)
+  } else {
+    constant::<i32>(a).zip(constant::<i32>(b)).map(|(x, y)| x == y)
+  };
+  same.unwrap_or(false) || fold(a.clone()) == fold(b.clone())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  fn num(n : &str) -> Box<AST> {
+    Box::new(AST::Num(n.to_owned()))
+  }
+
+  // The depth and node count of the parsed input
+  fn shape(input : &str) -> (usize, usize) {
+    let a = parse(input).unwrap();
+    (depth(&a), node_count(&a))
+  }
+
+  #[test]
+  fn depth_and_count_of_hand_built_trees() {
+    let a = AST::Num("1".to_owned());
+    assert_eq!((depth(&a), node_count(&a)), (1, 1));
+    let a = AST::Plus(num("1"), Box::new(AST::Times(num("2"), num("3"))));
+    assert_eq!((depth(&a), node_count(&a)), (3, 5));
+    let a = AST::Call("max".to_owned(), vec![]);
+    assert_eq!((depth(&a), node_count(&a)), (1, 1));
+    let a = AST::Sum(vec![*num("1"), *num("2"), AST::Neg(num("3"))]);
+    assert_eq!((depth(&a), node_count(&a)), (3, 5));
+  }
+
+  #[test]
+  fn depth_follows_associativity() {
+    assert_eq!(shape("1 + 2 * 3"), (3, 5));
+    assert_eq!(shape("(1 + 2) * 3"), (3, 5));
+    // Left-leaning chains grow deeper with each operand, as does ^
+    // leaning right
+    assert_eq!(shape("1 - 2 - 3 - 4"), (4, 7));
+    assert_eq!(shape("2 ^ 3 ^ 4 ^ 5"), (4, 7));
+    assert_eq!(shape("1 ? 2 : 3"), (2, 4));
+    assert_eq!(shape("x = -y!"), (4, 4));
+  }
+
+  #[test]
+  fn equivalent_trees_need_not_be_equal() {
+    let (a, b) = (parse("1 + 1").unwrap(), parse("2").unwrap());
+    assert!(equivalent(&a, &b));
+    assert_ne!(a, b);
+    assert!(equivalent(&parse("2 * 3").unwrap(), &parse("12 / 2").unwrap()));
+    assert!(equivalent(&parse("0.5 * 4").unwrap(), &parse("2").unwrap()));
+    assert!(!equivalent(&parse("1 + 1").unwrap(), &parse("3").unwrap()));
+    // With variables, only folding can show them the same
+    assert!(equivalent(&parse("x * (1 + 2)").unwrap(), &parse("x * 3").unwrap()));
+    assert!(!equivalent(&parse("x * 3").unwrap(), &parse("3 * x").unwrap()));
+    assert!(!equivalent(&parse("x").unwrap(), &parse("y").unwrap()));
+    // Two errors are not the same value
+    assert!(!equivalent(&parse("1 / 0").unwrap(), &parse("2 / 0").unwrap()));
+  }
+
+  #[test]
+  fn constant_trees_read_nothing_and_call_nothing_impure() {
+    let constant = |input : &str| is_constant(&parse(input).unwrap());
+    assert!(constant("1+2*3"));
+    assert!(constant("max(1, 2) + pi ? |-3|! : 4"));
+    // A failing tree is still constant
+    assert!(constant("1 / 0"));
+    assert!(!constant("x+1"));
+    assert!(!constant("x = 1"));
+    assert!(!constant("rand()"));
+    assert!(!constant("2 * (1 + rand(1, 3))"));
+    // Unlike is_pure, which allows reading
+    assert!(is_pure(&parse("x + 1").unwrap()));
+    assert!(!is_pure(&parse("x = 1").unwrap()));
+    assert!(!is_pure(&parse("rand()").unwrap()));
+  }
+}
@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Pow, Signed, ToPrimitive, Zero};
+
+use interpreter::{DivMode, EvalConfig, EvalError, evaluate_iterative};
+use number::{Number, next_random};
+use parser::AST;
+
+
+/**********************************************************************
+ * ARBITRARY PRECISION
+ */
+
+/// Evaluates the tree in a fresh environment with integers that never
+/// overflow.  Division floors, so `-7 / 2` is -4.
+pub fn evaluate_big(a : &AST) -> Result<BigInt, EvalError> {
+  let config = EvalConfig { div : DivMode::Floor, ..EvalConfig::default() };
+  evaluate_iterative(a, &mut HashMap::new(), &config)
+}
+
+// The most bits a power, shift, or factorial may need, about 315,000
+// decimal digits.  Anything that would need more is refused as too big
+// to compute in reasonable time and memory.
+const MAX_BITS : u64 = 1 << 20;
+
+// Nothing overflows, but a result that would need more than MAX_BITS is
+// refused, as Overflow or, for a shift, InvalidShift
+impl Number for BigInt {
+  fn from_f64(x : f64) -> Result<BigInt, EvalError> {
+    FromPrimitive::from_f64(x).ok_or(EvalError::Overflow)
+  }
+
+  fn from_bool(b : bool) -> BigInt {
+    BigInt::from(b as u8)
+  }
+
+  fn add(self, y : BigInt) -> Result<BigInt, EvalError> {
+    Ok(self + y)
+  }
+
+  fn sub(self, y : BigInt) -> Result<BigInt, EvalError> {
+    Ok(self - y)
+  }
+
+  fn mul(self, y : BigInt) -> Result<BigInt, EvalError> {
+    Ok(self * y)
+  }
+
+  fn div(self, y : BigInt) -> Result<BigInt, EvalError> {
+    if y.is_zero() {
+      return Err(EvalError::DivideByZero);
+    }
+    Ok(self / y)
+  }
+
+  fn div_floor(self, y : BigInt) -> Result<BigInt, EvalError> {
+    if y.is_zero() {
+      return Err(EvalError::DivideByZero);
+    }
+    Ok(Integer::div_floor(&self, &y))
+  }
+
+  fn div_ceil(self, y : BigInt) -> Result<BigInt, EvalError> {
+    if y.is_zero() {
+      return Err(EvalError::DivideByZero);
+    }
+    Ok(Integer::div_ceil(&self, &y))
+  }
+
+  fn rem(self, y : BigInt) -> Result<BigInt, EvalError> {
+    if y.is_zero() {
+      return Err(EvalError::DivideByZero);
+    }
+    Ok(self % y)
+  }
+
+  fn pow(self, e : BigInt) -> Result<BigInt, EvalError> {
+    if e.is_negative() {
+      return Err(EvalError::NegativeExponent);
+    }
+    // |self|^e has more than e times floor(log2 |self|) bits
+    let e = e.to_u32().ok_or(EvalError::Overflow)?;
+    if (self.bits().saturating_sub(1)).saturating_mul(e as u64) > MAX_BITS {
+      return Err(EvalError::Overflow);
+    }
+    Ok(Pow::pow(self, e))
+  }
+
+  fn neg(self) -> Result<BigInt, EvalError> {
+    Ok(-self)
+  }
+
+  fn factorial(self) -> Result<BigInt, EvalError> {
+    if self.is_negative() {
+      return Err(EvalError::DomainError);
+    }
+    // n! has about n log2 n bits
+    let n = self.to_u32().ok_or(EvalError::Overflow)?;
+    if n as f64 * (n as f64).log2() > MAX_BITS as f64 {
+      return Err(EvalError::Overflow);
+    }
+    Ok((2..=n).fold(BigInt::from(1), |p, i| p * i))
+  }
+
+  fn bitand(self, y : BigInt) -> Result<BigInt, EvalError> {
+    Ok(self & y)
+  }
+
+  fn bitor(self, y : BigInt) -> Result<BigInt, EvalError> {
+    Ok(self | y)
+  }
+
+  fn shl(self, y : BigInt) -> Result<BigInt, EvalError> {
+    let y = y.to_u32().filter(|&y| y as u64 <= MAX_BITS).ok_or(EvalError::InvalidShift)?;
+    Ok(self << y)
+  }
+
+  fn shr(self, y : BigInt) -> Result<BigInt, EvalError> {
+    let y = y.to_u32().ok_or(EvalError::InvalidShift)?;
+    Ok(self >> y)
+  }
+
+  fn call(name : &str, args : &[BigInt], _config : &EvalConfig) -> Result<BigInt, EvalError> {
+    match (name, args) {
+      ("sqrt", [x]) | ("isqrt", [x]) if x.is_negative() => Err(EvalError::DomainError),
+      ("sqrt", [x]) | ("isqrt", [x]) => Ok(x.sqrt()),
+      ("abs", [x]) => Ok(x.abs()),
+      ("signum", [x]) | ("sign", [x]) => Ok(x.signum()),
+      ("pow", [_, e]) if e.is_negative() => Err(EvalError::DomainError),
+      ("pow", [b, e]) => Number::pow(b.clone(), e.clone()),
+      ("emod", [_, y]) if y.is_zero() => Err(EvalError::DivideByZero),
+      ("emod", [x, y]) => Ok(x.mod_floor(&y.abs())),
+      ("floor", [x]) | ("ceil", [x]) | ("round", [x]) | ("trunc", [x]) => Ok(x.clone()),
+      ("min", [x, rest @ ..]) => Ok(rest.iter().fold(x, |a, b| a.min(b)).clone()),
+      ("max", [x, rest @ ..]) => Ok(rest.iter().fold(x, |a, b| a.max(b)).clone()),
+      ("clamp", [_, lo, hi]) if lo > hi => Err(EvalError::DomainError),
+      ("clamp", [x, lo, hi]) => Ok(x.clone().clamp(lo.clone(), hi.clone())),
+      ("gcd", [x, y]) => Ok(x.gcd(y)),
+      ("lcm", [x, y]) => Ok(x.lcm(y)),
+      // Below 2^31, as for i32
+      ("rand", []) => Ok(BigInt::from(next_random() >> 33)),
+      ("rand", [lo, hi]) if lo >= hi => Err(EvalError::DomainError),
+      ("rand", [lo, hi]) => Ok(lo + BigInt::from(next_random()).mod_floor(&(hi - lo))),
+      ("sqrt", _) | ("abs", _) | ("signum", _) | ("sign", _) | ("pow", _) | ("emod", _)
+      | ("min", _) | ("max", _) | ("clamp", _) | ("gcd", _) | ("lcm", _) | ("floor", _) | ("ceil", _) | ("round", _) | ("trunc", _)
+      | ("rand", _) | ("isqrt", _) =>
+        Err(EvalError::ArityMismatch(name.to_owned())),
+      _ => Err(EvalError::UnknownFunction(name.to_owned()))
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  fn big(input : &str) -> Result<BigInt, EvalError> {
+    evaluate_big(&parse(input).unwrap())
+  }
+
+  fn int(digits : &str) -> BigInt {
+    digits.parse().unwrap()
+  }
+
+  #[test]
+  fn never_overflows() {
+    assert_eq!(big("2 ^ 100"), Ok(int("1267650600228229401496703205376")));
+    assert_eq!(big("20!"), Ok(int("2432902008176640000")));
+    assert_eq!(big("30!"), Ok(int("265252859812191058636308480000000")));
+    assert_eq!(big("99999999999999999999999 + 1"), Ok(int("100000000000000000000000")));
+    assert_eq!(big("-(2 ^ 64) * 2 ^ 64"), Ok(-int("340282366920938463463374607431768211456")));
+  }
+
+  #[test]
+  fn division_floors() {
+    assert_eq!(big("-7 / 2"), Ok(int("-4")));
+    assert_eq!(big("7 / 2"), Ok(int("3")));
+    assert_eq!(big("2 ^ 100 / 2 ^ 99"), Ok(int("2")));
+    assert_eq!(big("1 / 0"), Err(EvalError::DivideByZero));
+    assert_eq!(big("5 % 0"), Err(EvalError::DivideByZero));
+  }
+
+  #[test]
+  fn integer_square_roots_floor() {
+    assert_eq!(big("isqrt(10 ^ 40)"), Ok(int("100000000000000000000")));
+    assert_eq!(big("isqrt(10 ^ 40 - 1)"), Ok(int("99999999999999999999")));
+    assert_eq!(big("isqrt(0)"), Ok(int("0")));
+    assert_eq!(big("isqrt(-4)"), Err(EvalError::DomainError));
+  }
+
+  #[test]
+  fn results_too_big_to_compute_are_refused() {
+    assert_eq!(big("10 ^ 4000000000"), Err(EvalError::Overflow));
+    assert_eq!(big("2 ^ 2000000"), Err(EvalError::Overflow));
+    assert_eq!(big("1 << 4000000000"), Err(EvalError::InvalidShift));
+    assert_eq!(big("1 << 2000000"), Err(EvalError::InvalidShift));
+    assert_eq!(big("4000000000!"), Err(EvalError::Overflow));
+    assert_eq!(big("100000!"), Err(EvalError::Overflow));
+    // Up to the limit they are computed
+    assert_eq!(big("(2 ^ 1048576) >> 1048575"), Ok(int("2")));
+    assert_eq!(big("(1 << 1048576) == 2 ^ 1048576"), Ok(int("1")));
+    assert_eq!(big("5000! / 4999!"), Ok(int("5000")));
+    // However large the exponent, 0, 1, and -1 stay small
+    assert_eq!(big("1 ^ 4000000000"), Ok(int("1")));
+    assert_eq!(big("(-1) ^ 4000000001"), Ok(int("-1")));
+    assert_eq!(big("0 ^ 4000000000"), Ok(int("0")));
+  }
+}
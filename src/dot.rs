@@ -0,0 +1,97 @@
+use std::fmt::Write;
+
+use parser::AST;
+
+
+/**********************************************************************
+ * GRAPHVIZ
+ */
+
+/// Renders the tree as a Graphviz graph, ready for `dot -Tpng`, with a
+/// box for every node labeled by its operator, number, or name, and an
+/// edge from each node to its children in order.  Every node gets its
+/// own box, so a literal used twice is drawn twice.
+pub fn to_dot(a : &AST) -> String {
+  let mut out = String::from("digraph ast {\n  node [shape=box];\n");
+  let mut next = 0;
+  node(a, &mut next, &mut out);
+  out.push_str("}\n");
+  out
+}
+
+// Write the node and its subtree, numbering them from next on, and
+// return the node's id
+fn node(a : &AST, next : &mut usize, out : &mut String) -> usize {
+  let id = *next;
+  *next += 1;
+  let _ = writeln!(out, "  n{} [label=\"{}\"];", id, label(a));
+  for x in a.children() {
+    let child = node(x, next, out);
+    let _ = writeln!(out, "  n{} -> n{};", id, child);
+  }
+  id
+}
+
+fn label(a : &AST) -> String {
+  let op = match *a {
+    AST::Num(ref x) | AST::Var(ref x) => return x.clone(),
+    AST::FNum(_) => return a.to_string(),
+    AST::Assign(ref name, _) => return format!("{} =", name),
+    AST::Call(ref name, _) => return format!("{}()", name),
+    AST::Plus(..) | AST::Sum(_) => "+",
+    AST::Minus(..) | AST::Neg(_) => "-",
+    AST::Times(..) | AST::Product(_) => "*",
+    AST::Divide(..) => "/",
+    AST::IntDivide(..) => "div",
+    AST::Modulo(..) | AST::Percent(_) => "%",
+    AST::Pow(..) => "^",
+    AST::BitAnd(..) => "&",
+    AST::BitOr(..) => "|",
+    AST::Abs(_) => "| |",
+    AST::Less(..) => "<",
+    AST::Greater(..) => ">",
+    AST::LessEq(..) => "<=",
+    AST::GreaterEq(..) => ">=",
+    AST::Equal(..) => "==",
+    AST::NotEqual(..) => "!=",
+    AST::Shl(..) => "<<",
+    AST::Shr(..) => ">>",
+    AST::Factorial(_) => "!",
+    AST::Cond(..) => "? :"
+  };
+  op.to_owned()
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  fn dot(input : &str) -> String {
+    to_dot(&parse(input).unwrap())
+  }
+
+  #[test]
+  fn a_box_per_node_and_an_edge_per_child() {
+    let out = dot("1+2*3");
+    assert_eq!(out.matches("[label=").count(), 5);
+    assert_eq!(out.matches(" -> ").count(), 4);
+    let lines = ["digraph ast {", "  node [shape=box];",
+                 "  n0 [label=\"+\"];", "  n1 [label=\"1\"];", "  n0 -> n1;",
+                 "  n2 [label=\"*\"];", "  n3 [label=\"2\"];", "  n2 -> n3;",
+                 "  n4 [label=\"3\"];", "  n2 -> n4;", "  n0 -> n2;", "}", ""];
+    assert_eq!(out, lines.join("\n"));
+  }
+
+  #[test]
+  fn repeated_literals_get_their_own_boxes() {
+    let out = dot("x * x");
+    assert_eq!(out.matches("[label=\"x\"]").count(), 2);
+    assert!(out.contains("n0 -> n1;") && out.contains("n0 -> n2;"), "{}", out);
+    let out = dot("y = max(1, |-2|) ? 3! : 4");
+    assert!(out.contains("[label=\"y =\"]") && out.contains("[label=\"max()\"]")
+            && out.contains("[label=\"| |\"]") && out.contains("[label=\"? :\"]"), "{}", out);
+    assert_eq!(out.matches("[label=").count(), 10);
+  }
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use lexer::Span;
+
+
+/**********************************************************************
+ * FORMATTING
+ */
+
+/// A radix to print integer results in.
+#[derive(Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub enum Base {
+  #[default]
+  Dec,
+  Hex,
+  Bin,
+  Oct
+}
+
+/// Renders `value` in `base`, prefixed with `0x`, `0b`, or `0o` unless
+/// it is decimal.  Negative values are printed as a `-` sign before the
+/// magnitude rather than in two's complement, so -255 in hex is `-0xff`.
+pub fn format_result(value : i32, base : Base) -> String {
+  let sign = if value < 0 { "-" } else { "" };
+  let n = value.unsigned_abs();
+  match base {
+    Base::Dec => value.to_string(),
+    Base::Hex => format!("{}0x{:x}", sign, n),
+    Base::Bin => format!("{}0b{:b}", sign, n),
+    Base::Oct => format!("{}0o{:o}", sign, n)
+  }
+}
+
+/// How many decimals `format_float` is usually asked for.
+pub const DEFAULT_PRECISION : usize = 6;
+
+/// Renders `value` rounded to `precision` decimals, then drops any
+/// trailing zeros and a trailing decimal point, so 1.5 at precision 5
+/// is `1.5` rather than `1.50000`, and 2 is just `2`.  A value that
+/// rounds to zero is `0` whatever its sign.  Infinities and NaN print as
+/// `inf`, `-inf`, and `NaN`.
+pub fn format_float(value : f64, precision : usize) -> String {
+  let mut s = format!("{:.*}", precision, value);
+  if s.contains('.') {
+    let trimmed = s.trim_end_matches('0').trim_end_matches('.').len();
+    s.truncate(trimmed);
+  }
+  if s == "-0" {
+    s.remove(0);
+  }
+  s
+}
+
+/// Shows where an error is: the line of `input` that `span` points into,
+/// a `^` under the column, and then `msg`, on three lines with no final
+/// newline.  Tabs before the column are kept so the caret lines up.
+pub fn render_error(input : &str, span : Span, msg : &str) -> String {
+  let line = input.lines().nth(span.line.saturating_sub(1)).unwrap_or("");
+  let pad : String = line.chars().take(span.col.saturating_sub(1))
+                         .map(|c| if c == '\t' { '\t' } else { ' ' })
+                         .collect();
+  format!("{}\n{}^\n{}", line, pad, msg)
+}
+
+/// Renders how long a statement took to parse and to evaluate, as
+/// `(parse 12µs, eval 3µs)`, in whole microseconds.
+pub fn format_timing(parse : Duration, eval : Duration) -> String {
+  format!("(parse {}µs, eval {}µs)", parse.as_micros(), eval.as_micros())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decimal_results() {
+    assert_eq!(format_result(255, Base::Dec), "255");
+    assert_eq!(format_result(0, Base::Dec), "0");
+    assert_eq!(format_result(-255, Base::Dec), "-255");
+    assert_eq!(format_result(i32::MIN, Base::Dec), "-2147483648");
+  }
+
+  #[test]
+  fn hex_results() {
+    assert_eq!(format_result(255, Base::Hex), "0xff");
+    assert_eq!(format_result(0, Base::Hex), "0x0");
+    assert_eq!(format_result(-255, Base::Hex), "-0xff");
+    assert_eq!(format_result(i32::MIN, Base::Hex), "-0x80000000");
+  }
+
+  #[test]
+  fn binary_results() {
+    assert_eq!(format_result(5, Base::Bin), "0b101");
+    assert_eq!(format_result(0, Base::Bin), "0b0");
+    assert_eq!(format_result(-5, Base::Bin), "-0b101");
+    assert_eq!(format_result(i32::MIN, Base::Bin), format!("-0b1{}", "0".repeat(31)));
+  }
+
+  #[test]
+  fn octal_results() {
+    assert_eq!(format_result(8, Base::Oct), "0o10");
+    assert_eq!(format_result(0, Base::Oct), "0o0");
+    assert_eq!(format_result(-8, Base::Oct), "-0o10");
+    assert_eq!(format_result(i32::MIN, Base::Oct), "-0o20000000000");
+  }
+
+  #[test]
+  fn caret_points_at_the_column() {
+    assert_eq!(render_error("1 + * 2", Span { line : 1, col : 5 }, "Syntax error: expected an operand, found '*'"),
+               "1 + * 2\n    ^\nSyntax error: expected an operand, found '*'");
+    assert_eq!(render_error("x", Span { line : 1, col : 1 }, "oops"), "x\n^\noops");
+    // Past the end of the line, as at the end of input
+    assert_eq!(render_error("1 +", Span { line : 1, col : 4 }, "oops"), "1 +\n   ^\noops");
+  }
+
+  #[test]
+  fn caret_finds_its_line() {
+    assert_eq!(render_error("1;\n\t2 $", Span { line : 2, col : 4 }, "bad"), "\t2 $\n\t  ^\nbad");
+    assert_eq!(render_error("é + $", Span { line : 1, col : 5 }, "bad"), "é + $\n    ^\nbad");
+    // A line that is not there is shown empty
+    assert_eq!(render_error("1", Span { line : 3, col : 2 }, "bad"), "\n^\nbad");
+  }
+
+  #[test]
+  fn timing_is_in_whole_microseconds() {
+    assert_eq!(format_timing(Duration::from_micros(12), Duration::from_nanos(3999)),
+               "(parse 12µs, eval 3µs)");
+    assert_eq!(format_timing(Duration::ZERO, Duration::from_secs(1)), "(parse 0µs, eval 1000000µs)");
+  }
+}
@@ -0,0 +1,1045 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::mem;
+
+use analysis::{IMPURE_FUNCTIONS, depth};
+use number::{Number, seed_random};
+use parser::{AST, MAX_DEPTH};
+
+
+/**********************************************************************
+ * INTERPRETER
+ */
+
+/// Ways a well-formed expression can fail to produce a value.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum EvalError {
+  DivideByZero,
+  NegativeExponent,
+  Overflow,
+  InvalidShift,
+  DomainError,
+  UndefinedVar(String),
+  ReadOnlyVar(String),
+  UnknownFunction(String),
+  ArityMismatch(String),
+  NotEnoughOperands,
+  TooManyOperands,
+  DepthLimitExceeded
+}
+
+impl fmt::Display for EvalError {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      EvalError::DivideByZero => write!(f, "division by zero"),
+      EvalError::NegativeExponent => write!(f, "negative exponent"),
+      EvalError::Overflow => write!(f, "overflow"),
+      EvalError::InvalidShift => write!(f, "shift amount out of range"),
+      EvalError::DomainError => write!(f, "argument out of domain"),
+      EvalError::UndefinedVar(ref name) => write!(f, "undefined variable '{}'", name),
+      EvalError::ReadOnlyVar(ref name) => write!(f, "variable '{}' cannot be assigned", name),
+      EvalError::UnknownFunction(ref name) => write!(f, "unknown function '{}'", name),
+      EvalError::ArityMismatch(ref name) => write!(f, "wrong number of arguments to '{}'", name),
+      EvalError::NotEnoughOperands => write!(f, "not enough operands"),
+      EvalError::TooManyOperands => write!(f, "too many operands"),
+      EvalError::DepthLimitExceeded => write!(f, "expression nested too deeply")
+    }
+  }
+}
+
+impl Error for EvalError {}
+
+/// How integer division rounds a quotient that is not whole.  For
+/// `-7 / 2`, `Trunc` gives -3, `Floor` gives -4, and `Ceil` gives -3.
+#[derive(Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub enum DivMode {
+  /// Toward zero, as Rust's `/` does
+  #[default]
+  Trunc,
+  /// Toward negative infinity
+  Floor,
+  /// Toward positive infinity
+  Ceil
+}
+
+impl DivMode {
+  // The division operator of this mode
+  fn op<N : Number>(self) -> fn(N, N) -> Result<N, EvalError> {
+    match self {
+      DivMode::Trunc => N::div,
+      DivMode::Floor => N::div_floor,
+      DivMode::Ceil => N::div_ceil
+    }
+  }
+}
+
+/// What integer arithmetic does with a result out of range.  Only `+`,
+/// `-`, `*`, `^`, and negation saturate or wrap; anything else that
+/// overflows, like `i32::MIN / -1`, is still an error.  Types that
+/// cannot overflow, or like `Rational64` have no sensible bound, always
+/// act as in `Error` mode.
+///
+/// Built with the `overflow-wrapping` feature, the crate always acts as
+/// in `Wrap` mode, whatever mode is configured, and every other integer
+/// operation wraps as well, so `i32::MIN / -1` is `i32::MIN` and `13!`
+/// is 1932053504.  Integer arithmetic then never gives
+/// `EvalError::Overflow`, though a literal out of range still does.
+/// The default `overflow-checked` feature only names the opposite
+/// choice; enabling it alongside `overflow-wrapping` still wraps.
+#[derive(Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub enum OverflowMode {
+  /// Report `EvalError::Overflow`
+  #[default]
+  Error,
+  /// Clamp to the nearest bound, so `2147483647 + 1` is 2147483647
+  Saturate,
+  /// Wrap around in two's complement, so `2147483647 + 1` is -2147483648
+  Wrap
+}
+
+impl OverflowMode {
+  // The mode arithmetic is done in, which the overflow-wrapping feature
+  // fixes at compile time
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn effective(self) -> OverflowMode {
+    self
+  }
+
+  #[cfg(feature = "overflow-wrapping")]
+  fn effective(self) -> OverflowMode {
+    OverflowMode::Wrap
+  }
+
+  // The operators of this mode
+  fn add<N : Number>(self) -> fn(N, N) -> Result<N, EvalError> {
+    match self.effective() {
+      OverflowMode::Error => N::add,
+      OverflowMode::Saturate => N::saturating_add,
+      OverflowMode::Wrap => N::wrapping_add
+    }
+  }
+
+  fn sub<N : Number>(self) -> fn(N, N) -> Result<N, EvalError> {
+    match self.effective() {
+      OverflowMode::Error => N::sub,
+      OverflowMode::Saturate => N::saturating_sub,
+      OverflowMode::Wrap => N::wrapping_sub
+    }
+  }
+
+  fn mul<N : Number>(self) -> fn(N, N) -> Result<N, EvalError> {
+    match self.effective() {
+      OverflowMode::Error => N::mul,
+      OverflowMode::Saturate => N::saturating_mul,
+      OverflowMode::Wrap => N::wrapping_mul
+    }
+  }
+
+  fn pow<N : Number>(self) -> fn(N, N) -> Result<N, EvalError> {
+    match self.effective() {
+      OverflowMode::Error => N::pow,
+      OverflowMode::Saturate => N::saturating_pow,
+      OverflowMode::Wrap => N::wrapping_pow
+    }
+  }
+
+  fn neg<N : Number>(self) -> fn(N) -> Result<N, EvalError> {
+    match self.effective() {
+      OverflowMode::Error => N::neg,
+      OverflowMode::Saturate => N::saturating_neg,
+      OverflowMode::Wrap => N::wrapping_neg
+    }
+  }
+}
+
+/// The unit trig functions take their arguments in.
+#[derive(Clone,Copy,PartialEq,Eq,Debug,Default)]
+pub enum AngleMode {
+  #[default]
+  Radians,
+  Degrees
+}
+
+/// Settings that change how an expression evaluates.  The default
+/// matches plain Rust arithmetic.
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub struct EvalConfig {
+  pub div : DivMode,
+  pub angle : AngleMode,
+  pub overflow : OverflowMode,
+  /// Trees deeper than this, as measured by `depth`, are refused
+  /// before evaluation starts
+  pub max_depth : usize,
+  /// Starts `rand` over from this seed at the start of each evaluation,
+  /// so it gives the same values every time.  Without one, `rand` goes
+  /// on from where it was, after first being seeded from the clock.
+  pub seed : Option<u64>
+}
+
+impl Default for EvalConfig {
+  fn default() -> EvalConfig {
+    EvalConfig {
+      div : DivMode::default(),
+      angle : AngleMode::default(),
+      overflow : OverflowMode::default(),
+      max_depth : MAX_DEPTH,
+      seed : None
+    }
+  }
+}
+
+// Refuse a tree deeper than config allows, and seed rand if it says to
+fn begin(a : &AST, config : &EvalConfig) -> Result<(), EvalError> {
+  if depth(a) > config.max_depth {
+    return Err(EvalError::DepthLimitExceeded);
+  }
+  if let Some(seed) = config.seed {
+    seed_random(seed);
+  }
+  Ok(())
+}
+
+// Integer literals are stored as decimal digits, so the only way one
+// can fail to parse is by being out of range of N
+fn literal<N : Number>(x : &str) -> Result<N, EvalError> {
+  x.parse::<N>().map_err(|_| EvalError::Overflow)
+}
+
+// A negated literal is read as a single negative one, so that the most
+// negative value of N can be written even though its magnitude is out of
+// range, as with -2147483648 in i32
+fn negative_literal<N : Number>(x : &str) -> Result<N, EvalError> {
+  literal(&format!("-{}", x))
+}
+
+// A percent is a hundredth, which in an integer N rounds toward zero
+fn percent<N : Number>(x : N) -> Result<N, EvalError> {
+  x.div(literal("100")?)
+}
+
+/// Recursively evaluate the expression tree in the number type `N`,
+/// reading and assigning variables in `env`.  An integer `N` truncates
+/// float literals toward zero.
+pub fn evaluate<N : Number>(a : &AST, env : &mut HashMap<String, N>,
+                            config : &EvalConfig) -> Result<N, EvalError> {
+  begin(a, config)?;
+  eval_node(a, env, config, &mut Memo::off())
+}
+
+/// Evaluate the expression tree like `evaluate`, but evaluate each
+/// distinct subtree only once, reusing its value wherever an identical
+/// copy appears, as substitution tends to make.  A subtree that calls
+/// `rand` is always evaluated afresh, and if the tree assigns a variable
+/// anywhere, so is one that reads a variable.  Finding the copies takes
+/// a pass over the whole tree first, so this pays off only when they are
+/// costly to evaluate.
+pub fn evaluate_memoized<N : Number>(a : &AST, env : &mut HashMap<String, N>,
+                                     config : &EvalConfig) -> Result<N, EvalError> {
+  begin(a, config)?;
+  eval_node(a, env, config, &mut Memo::on(a))
+}
+
+// A subtree as its root and the ids of its children, so that identical
+// subtrees, and only they, have equal keys.  Float literals are
+// compared bit for bit, so that every literal equals itself.
+#[derive(PartialEq,Eq,Hash)]
+struct Key<'a> {
+  kind : mem::Discriminant<AST>,
+  // What the root holds besides its children
+  payload : (Option<&'a str>, u64),
+  children : Vec<usize>
+}
+
+impl<'a> Key<'a> {
+  fn new(a : &'a AST, children : Vec<usize>) -> Key<'a> {
+    let payload = match *a {
+      AST::Num(ref x) | AST::Var(ref x) | AST::Assign(ref x, _) | AST::Call(ref x, _) => (Some(x.as_str()), 0),
+      AST::FNum(x) => (None, x.to_bits()),
+      _ => (None, 0)
+    };
+    Key { kind : mem::discriminant(a), payload, children }
+  }
+}
+
+fn assigns_any(a : &AST) -> bool {
+  matches!(*a, AST::Assign(..)) || a.children().into_iter().any(assigns_any)
+}
+
+// The values of subtrees already evaluated, for evaluate_memoized
+struct Memo<N> {
+  // The id shared by each subtree and its copies, by the address of the
+  // subtree, for those whose values can be reused
+  ids : HashMap<*const AST, usize>,
+  values : Vec<Option<N>>
+}
+
+impl<N : Clone> Memo<N> {
+  // A memo that caches nothing, for plain evaluation
+  fn off() -> Memo<N> {
+    Memo { ids : HashMap::new(), values : Vec::new() }
+  }
+
+  fn on(a : &AST) -> Memo<N> {
+    let mut memo = Memo::off();
+    let mut keys = HashMap::new();
+    memo.number(a, &mut keys, assigns_any(a));
+    memo.values = vec![None; keys.len()];
+    memo
+  }
+
+  // Give a and each of its subtrees the id of its key, and note those
+  // whose values can be reused.  That takes a tree that is pure, or if
+  // anything assigns a variable, constant.  A lone literal or variable is
+  // no cheaper to find in the cache than to evaluate.  Returns the id of
+  // a and whether it is pure and constant.
+  fn number<'a>(&mut self, a : &'a AST, keys : &mut HashMap<Key<'a>, usize>,
+                assigns : bool) -> (usize, bool, bool) {
+    let (mut pure, mut constant) = match *a {
+      AST::Assign(..) => (false, false),
+      AST::Call(ref name, _) if IMPURE_FUNCTIONS.contains(&name.as_str()) => (false, false),
+      AST::Var(_) => (true, false),
+      _ => (true, true)
+    };
+    let mut children = Vec::new();
+    for x in a.children() {
+      let (id, p, c) = self.number(x, keys, assigns);
+      children.push(id);
+      pure &= p;
+      constant &= c;
+    }
+    let leaf = children.is_empty();
+    let next = keys.len();
+    let id = *keys.entry(Key::new(a, children)).or_insert(next);
+    if !leaf && if assigns { constant } else { pure } {
+      self.ids.insert(a, id);
+    }
+    (id, pure, constant)
+  }
+}
+
+// The recursion of evaluate, once the tree is known not to be too deep.
+// A subtree memo has a value for is not evaluated again.
+fn eval_node<N : Number>(a : &AST, env : &mut HashMap<String, N>,
+                         config : &EvalConfig, memo : &mut Memo<N>) -> Result<N, EvalError> {
+  let id = match memo.ids.get(&(a as *const AST)) {
+    Some(&id) => id,
+    None => return eval_op(a, env, config, memo)
+  };
+  if let Some(ref v) = memo.values[id] {
+    return Ok(v.clone());
+  }
+  let v = eval_op(a, env, config, memo)?;
+  memo.values[id] = Some(v.clone());
+  Ok(v)
+}
+
+// The operator at the root of a, applied to its evaluated operands
+fn eval_op<N : Number>(a : &AST, env : &mut HashMap<String, N>,
+                       config : &EvalConfig, memo : &mut Memo<N>) -> Result<N, EvalError> {
+  match *a {
+    AST::Num(ref x) => literal(x),
+    AST::FNum(x) => N::from_f64(x),
+    AST::Plus(ref x, ref y) => config.overflow.add()(eval_node(x, env, config, memo)?,
+                                                     eval_node(y, env, config, memo)?),
+    AST::Minus(ref x, ref y) => config.overflow.sub()(eval_node(x, env, config, memo)?,
+                                                      eval_node(y, env, config, memo)?),
+    AST::Times(ref x, ref y) => config.overflow.mul()(eval_node(x, env, config, memo)?,
+                                                      eval_node(y, env, config, memo)?),
+    AST::Divide(ref x, ref y) => config.div.op()(eval_node(x, env, config, memo)?,
+                                                 eval_node(y, env, config, memo)?),
+    AST::IntDivide(ref x, ref y) => { let q = config.div.op()(eval_node(x, env, config, memo)?,
+                                                              eval_node(y, env, config, memo)?)?;
+                                      N::call("trunc", &[q], config) },
+    AST::Modulo(ref x, ref y) => eval_node(x, env, config, memo)?.rem(eval_node(y, env, config, memo)?),
+    AST::Pow(ref x, ref y) => config.overflow.pow()(eval_node(x, env, config, memo)?,
+                                                    eval_node(y, env, config, memo)?),
+    AST::BitAnd(ref x, ref y) => eval_node(x, env, config, memo)?.bitand(eval_node(y, env, config, memo)?),
+    AST::BitOr(ref x, ref y) => eval_node(x, env, config, memo)?.bitor(eval_node(y, env, config, memo)?),
+    AST::Less(ref x, ref y) => eval_node(x, env, config, memo)?.less(eval_node(y, env, config, memo)?),
+    AST::Greater(ref x, ref y) => eval_node(x, env, config, memo)?.greater(eval_node(y, env, config, memo)?),
+    AST::LessEq(ref x, ref y) => eval_node(x, env, config, memo)?.less_eq(eval_node(y, env, config, memo)?),
+    AST::GreaterEq(ref x, ref y) => eval_node(x, env, config, memo)?.greater_eq(eval_node(y, env, config, memo)?),
+    AST::Equal(ref x, ref y) => eval_node(x, env, config, memo)?.equal(eval_node(y, env, config, memo)?),
+    AST::NotEqual(ref x, ref y) => eval_node(x, env, config, memo)?.not_equal(eval_node(y, env, config, memo)?),
+    AST::Shl(ref x, ref y) => eval_node(x, env, config, memo)?.shl(eval_node(y, env, config, memo)?),
+    AST::Shr(ref x, ref y) => eval_node(x, env, config, memo)?.shr(eval_node(y, env, config, memo)?),
+    AST::Neg(ref x) => match **x {
+                         AST::Num(ref x) => negative_literal(x),
+                         _ => config.overflow.neg()(eval_node(x, env, config, memo)?)
+                       },
+    AST::Factorial(ref x) => eval_node(x, env, config, memo)?.factorial(),
+    AST::Percent(ref x) => percent(eval_node(x, env, config, memo)?),
+    AST::Abs(ref x) => N::call("abs", &[eval_node(x, env, config, memo)?], config),
+    AST::Var(ref name) => env.get(name).cloned()
+                            .ok_or_else(|| EvalError::UndefinedVar(name.clone())),
+    AST::Assign(ref name, ref x) => { let v = eval_node(x, env, config, memo)?;
+                                      env.insert(name.clone(), v.clone());
+                                      Ok(v) },
+    AST::Cond(ref c, ref x, ref y) => if eval_node(c, env, config, memo)? != N::from_bool(false) {
+                                        eval_node(x, env, config, memo)
+                                      } else {
+                                        eval_node(y, env, config, memo)
+                                      },
+    AST::Call(ref name, ref args) => { let mut vals = Vec::new();
+                                       for arg in args {
+                                         vals.push(eval_node(arg, env, config, memo)?);
+                                       }
+                                       N::call(name, &vals, config) },
+    AST::Sum(ref xs) => eval_chain(xs, config.overflow.add(), "0", env, config, memo),
+    AST::Product(ref xs) => eval_chain(xs, config.overflow.mul(), "1", env, config, memo)
+  }
+}
+
+// Apply op to the values of xs left to right, as a chain of binary
+// nodes would, or give the literal empty if there are none
+fn eval_chain<N : Number>(xs : &[AST], op : fn(N, N) -> Result<N, EvalError>, empty : &str,
+                          env : &mut HashMap<String, N>, config : &EvalConfig,
+                          memo : &mut Memo<N>) -> Result<N, EvalError> {
+  let (first, rest) = match xs.split_first() {
+    Some(x) => x,
+    None => return literal(empty)
+  };
+  let mut v = eval_node(first, env, config, memo)?;
+  for x in rest {
+    v = op(v, eval_node(x, env, config, memo)?)?;
+  }
+  Ok(v)
+}
+
+// Pending work for evaluate_iterative.  Operators are applied to values
+// popped off the value stack once their operands have been evaluated.
+enum Work<'a, N> {
+  Eval(&'a AST),
+  Unary(fn(N) -> Result<N, EvalError>),
+  Binary(fn(N, N) -> Result<N, EvalError>),
+  Assign(&'a str),
+  Call(&'a str, usize),
+  // Choose a branch once the condition is on the value stack
+  Cond(&'a AST, &'a AST)
+}
+
+/// Evaluate the expression tree like `evaluate`, but with an explicit
+/// work stack instead of recursion, so that arbitrarily deep trees
+/// cannot overflow the native stack.  They are still refused past
+/// `max_depth`, which may be raised as far as needed here.
+pub fn evaluate_iterative<N : Number>(a : &AST, env : &mut HashMap<String, N>,
+                                      config : &EvalConfig) -> Result<N, EvalError> {
+  begin(a, config)?;
+  let mut work = vec![Work::Eval(a)];
+  let mut vals : Vec<N> = Vec::new();
+
+  // Push a binary operator, then its operands so the left one is
+  // evaluated first
+  fn binary<'a, N>(work : &mut Vec<Work<'a, N>>, op : fn(N, N) -> Result<N, EvalError>,
+                   x : &'a AST, y : &'a AST) {
+    work.push(Work::Binary(op));
+    work.push(Work::Eval(y));
+    work.push(Work::Eval(x));
+  }
+
+  // Push a chain of op over xs as binary would push nested nodes, or
+  // the literal empty if there are none
+  fn chain<'a, N : Number>(work : &mut Vec<Work<'a, N>>, vals : &mut Vec<N>,
+                           op : fn(N, N) -> Result<N, EvalError>, xs : &'a [AST],
+                           empty : &str) -> Result<(), EvalError> {
+    match xs.split_first() {
+      Some((first, rest)) => { for x in rest.iter().rev() {
+                                 work.push(Work::Binary(op));
+                                 work.push(Work::Eval(x));
+                               }
+                               work.push(Work::Eval(first)) },
+      None => vals.push(literal(empty)?)
+    }
+    Ok(())
+  }
+
+  while let Some(w) = work.pop() {
+    match w {
+      Work::Eval(AST::Num(x)) => vals.push(literal(x)?),
+      Work::Eval(&AST::FNum(x)) => vals.push(N::from_f64(x)?),
+      Work::Eval(AST::Plus(x, y)) => binary(&mut work, config.overflow.add(), x, y),
+      Work::Eval(AST::Minus(x, y)) => binary(&mut work, config.overflow.sub(), x, y),
+      Work::Eval(AST::Times(x, y)) => binary(&mut work, config.overflow.mul(), x, y),
+      Work::Eval(AST::Divide(x, y)) => binary(&mut work, config.div.op(), x, y),
+      Work::Eval(AST::IntDivide(x, y)) => { work.push(Work::Call("trunc", 1));
+                                            binary(&mut work, config.div.op(), x, y) },
+      Work::Eval(AST::Modulo(x, y)) => binary(&mut work, N::rem, x, y),
+      Work::Eval(AST::Pow(x, y)) => binary(&mut work, config.overflow.pow(), x, y),
+      Work::Eval(AST::BitAnd(x, y)) => binary(&mut work, N::bitand, x, y),
+      Work::Eval(AST::BitOr(x, y)) => binary(&mut work, N::bitor, x, y),
+      Work::Eval(AST::Less(x, y)) => binary(&mut work, N::less, x, y),
+      Work::Eval(AST::Greater(x, y)) => binary(&mut work, N::greater, x, y),
+      Work::Eval(AST::LessEq(x, y)) => binary(&mut work, N::less_eq, x, y),
+      Work::Eval(AST::GreaterEq(x, y)) => binary(&mut work, N::greater_eq, x, y),
+      Work::Eval(AST::Equal(x, y)) => binary(&mut work, N::equal, x, y),
+      Work::Eval(AST::NotEqual(x, y)) => binary(&mut work, N::not_equal, x, y),
+      Work::Eval(AST::Shl(x, y)) => binary(&mut work, N::shl, x, y),
+      Work::Eval(AST::Shr(x, y)) => binary(&mut work, N::shr, x, y),
+      Work::Eval(AST::Neg(x)) => match **x {
+        AST::Num(ref n) => vals.push(negative_literal(n)?),
+        _ => { work.push(Work::Unary(config.overflow.neg()));
+               work.push(Work::Eval(x)) }
+      },
+      Work::Eval(AST::Factorial(x)) => { work.push(Work::Unary(N::factorial));
+                                              work.push(Work::Eval(x)) },
+      Work::Eval(AST::Percent(x)) => { work.push(Work::Unary(percent));
+                                       work.push(Work::Eval(x)) },
+      Work::Eval(AST::Abs(x)) => { work.push(Work::Call("abs", 1));
+                                   work.push(Work::Eval(x)) },
+      Work::Eval(AST::Var(name)) => {
+        let v = env.get(name).cloned()
+                   .ok_or_else(|| EvalError::UndefinedVar(name.clone()))?;
+        vals.push(v)
+      },
+      Work::Eval(AST::Assign(name, x)) => { work.push(Work::Assign(name));
+                                                     work.push(Work::Eval(x)) },
+      Work::Eval(AST::Cond(c, x, y)) => { work.push(Work::Cond(x, y));
+                                          work.push(Work::Eval(c)) },
+      Work::Eval(AST::Call(name, args)) => {
+        work.push(Work::Call(name, args.len()));
+        work.extend(args.iter().rev().map(Work::Eval))
+      },
+      Work::Eval(AST::Sum(xs)) => chain(&mut work, &mut vals, config.overflow.add(), xs, "0")?,
+      Work::Eval(AST::Product(xs)) => chain(&mut work, &mut vals, config.overflow.mul(), xs, "1")?,
+      Work::Binary(op) => { let y = pop(&mut vals)?;
+                            let x = pop(&mut vals)?;
+                            vals.push(op(x, y)?) },
+      Work::Unary(op) => { let x = pop(&mut vals)?;
+                           vals.push(op(x)?) },
+      Work::Assign(name) => { let v = pop(&mut vals)?;
+                              env.insert(name.to_owned(), v.clone());
+                              vals.push(v) },
+      Work::Cond(x, y) => { let c = pop(&mut vals)?;
+                            work.push(Work::Eval(if c != N::from_bool(false) { x } else { y })) },
+      Work::Call(name, n) => { let start = vals.len().checked_sub(n)
+                                               .ok_or(EvalError::NotEnoughOperands)?;
+                               let args = vals.split_off(start);
+                               vals.push(N::call(name, &args, config)?) }
+    }
+  }
+
+  pop(&mut vals)
+}
+
+// Every operator is pushed under its operands, so the value stack is
+// never short.  Were it to be through some bug, that is reported rather
+// than panicking.
+fn pop<N>(vals : &mut Vec<N>) -> Result<N, EvalError> {
+  vals.pop().ok_or(EvalError::NotEnoughOperands)
+}
+
+/// Evaluate the expression tree in floating point against an integer
+/// environment, as the REPL keeps one.  Variables are promoted to `f64`,
+/// and assigning a float stores it truncated toward zero, while the
+/// assignment itself still evaluates to the full float.
+pub fn evaluate_f64(a : &AST, env : &mut HashMap<String, i32>,
+                    config : &EvalConfig) -> Result<f64, EvalError> {
+  let mut floats = env.iter().map(|(k, &v)| (k.clone(), f64::from(v))).collect();
+  let result = evaluate(a, &mut floats, config);
+  // Every i32 survives the round trip through f64 exactly, so only the
+  // variables assigned here can change
+  for (k, v) in floats {
+    env.insert(k, v as i32);
+  }
+  result
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use lexer::{Token, tokenize};
+  use parse;
+  use transform::substitute;
+
+  // The value of the input in N, which both evaluators must agree on
+  fn value<N : Number>(input : &str, config : &EvalConfig) -> Result<N, EvalError> {
+    let a = parse(input).unwrap();
+    let rc = evaluate(&a, &mut HashMap::new(), config);
+    assert_eq!(rc, evaluate_iterative(&a, &mut HashMap::new(), config));
+    rc
+  }
+
+  fn int(input : &str) -> Result<i32, EvalError> {
+    value(input, &EvalConfig::default())
+  }
+
+  #[test]
+  fn division_by_zero_is_an_error() {
+    assert_eq!(int("1/0;"), Err(EvalError::DivideByZero));
+    assert_eq!(int("5 / (2 - 2)"), Err(EvalError::DivideByZero));
+    assert_eq!(int("0 / 5"), Ok(0));
+    assert_eq!(value::<i64>("1 / 0", &EvalConfig::default()), Err(EvalError::DivideByZero));
+    // Floats follow IEEE instead
+    assert_eq!(value("1 / 0", &EvalConfig::default()), Ok(f64::INFINITY));
+  }
+
+  #[test]
+  fn variables_persist_in_the_environment() {
+    let config = EvalConfig::default();
+    let mut env = HashMap::new();
+    let mut run = |input : &str| evaluate_iterative(&parse(input).unwrap(), &mut env, &config);
+    assert_eq!(run("x = 3 + 4;"), Ok(7));
+    assert_eq!(run("x * 2;"), Ok(14));
+    assert_eq!(run("x = x - 1"), Ok(6));
+    assert_eq!(run("y"), Err(EvalError::UndefinedVar("y".to_owned())));
+    assert_eq!(env.get("x"), Some(&6));
+    assert_eq!(env.get("y"), None);
+  }
+
+  #[test]
+  fn built_in_functions() {
+    assert_eq!(parse("f(1, x)"), Ok(AST::Call("f".to_owned(), vec![AST::Num("1".to_owned()),
+                                                                    AST::Var("x".to_owned())])));
+    assert_eq!(int("max(3, 7, 2);"), Ok(7));
+    assert_eq!(int("min(3, 7, 2)"), Ok(2));
+    assert_eq!(int("max(-4)"), Ok(-4));
+    assert_eq!(int("sqrt(17)"), Ok(4));
+    assert_eq!(int("sqrt(16)"), Ok(4));
+    assert_eq!(int("abs(-3) + abs(3)"), Ok(6));
+    assert_eq!(int("sqrt(-1)"), Err(EvalError::DomainError));
+    assert_eq!(int("nope(1)"), Err(EvalError::UnknownFunction("nope".to_owned())));
+    assert_eq!(int("max()"), Err(EvalError::ArityMismatch("max".to_owned())));
+    assert_eq!(int("abs(1, 2)"), Err(EvalError::ArityMismatch("abs".to_owned())));
+  }
+
+  #[test]
+  fn iteration_agrees_with_recursion() {
+    let config = EvalConfig::default();
+    let inputs = [
+      "1 + 2 * 3 - 4 / 2", "7 % 3 ^ 2", "-(2 ^ 10)", "5!", "6 & 3 | 8", "1 << 4 >> 2",
+      "1 < 2 == 1", "3 >= 4 != 2 <= 1", "0 ? 1 / 0 : 2", "max(1, abs(-5), 3)", "|2 - 9|",
+      "x = 4 * 2", "y", "2 ^ 40", "7 div 2", "1.5 * 2", "pi", "sin(0) + 10%"
+    ];
+    for input in inputs.iter() {
+      let a = parse(input).unwrap();
+      assert_eq!(evaluate::<i64>(&a, &mut HashMap::new(), &config),
+                 evaluate_iterative::<i64>(&a, &mut HashMap::new(), &config), "{}", input);
+      assert_eq!(evaluate::<f64>(&a, &mut HashMap::new(), &config),
+                 evaluate_iterative::<f64>(&a, &mut HashMap::new(), &config), "{}", input);
+    }
+  }
+
+  #[test]
+  fn deep_trees_evaluate_without_recursion() {
+    let n = 100_000;
+    let mut a = AST::Num("0".to_owned());
+    for _ in 0..n {
+      a = AST::Plus(Box::new(a), Box::new(AST::Num("1".to_owned())));
+    }
+    let config = EvalConfig { max_depth : n + 1, ..EvalConfig::default() };
+    assert_eq!(evaluate_iterative(&a, &mut HashMap::new(), &config), Ok(n as i32));
+    let config = EvalConfig { max_depth : n, ..EvalConfig::default() };
+    assert_eq!(evaluate_iterative::<i32>(&a, &mut HashMap::new(), &config),
+               Err(EvalError::DepthLimitExceeded));
+    // Dropping the tree would recurse as deep, so take it apart a level
+    // at a time
+    while let AST::Plus(x, _) = a {
+      a = *x;
+    }
+  }
+
+  #[test]
+  fn factorial() {
+    assert_eq!(parse("5!"), Ok(AST::Factorial(Box::new(AST::Num("5".to_owned())))));
+    assert_eq!(int("5!"), Ok(120));
+    assert_eq!(int("0!"), Ok(1));
+    assert_eq!(int("1!"), Ok(1));
+    assert_eq!(int("3!!"), Ok(720));
+    // Postfix binds tighter than prefix
+    assert_eq!(int("-3!"), Ok(-6));
+    assert_eq!(int("(-3)!"), Err(EvalError::DomainError));
+    assert_eq!(value("2.5!", &EvalConfig::default()), Err::<f64, _>(EvalError::DomainError));
+    assert_eq!(value("4.0!", &EvalConfig::default()), Ok(24.0));
+  }
+
+  #[test]
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn factorial_overflow_is_an_error() {
+    assert_eq!(int("12!"), Ok(479001600));
+    assert_eq!(int("13!"), Err(EvalError::Overflow));
+    assert_eq!(value::<i64>("13!", &EvalConfig::default()), Ok(6227020800));
+  }
+
+  #[test]
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn integer_overflow_is_an_error() {
+    assert_eq!(int("2147483647 * 2"), Err(EvalError::Overflow));
+    assert_eq!(int("46341 * 46341"), Err(EvalError::Overflow));
+    assert_eq!(int("46340 * 46340"), Ok(2147395600));
+    assert_eq!(int("2147483647 + 1"), Err(EvalError::Overflow));
+    assert_eq!(int("2147483646 + 1"), Ok(i32::MAX));
+    assert_eq!(int("-2147483647 - 2"), Err(EvalError::Overflow));
+    assert_eq!(int("-2147483647 - 1"), Ok(i32::MIN));
+    assert_eq!(int("(-2147483647 - 1) / -1"), Err(EvalError::Overflow));
+    // The same sums fit in a wider type
+    assert_eq!(value::<i64>("2147483647 * 2", &EvalConfig::default()), Ok(4294967294));
+  }
+
+  #[test]
+  fn bitwise_operators() {
+    assert_eq!(int("6 & 3"), Ok(2));
+    assert_eq!(int("6 | 3"), Ok(7));
+    assert_eq!(int("1 << 4"), Ok(16));
+    assert_eq!(int("-16 >> 2"), Ok(-4));
+    assert_eq!(int("1 << 31"), Ok(i32::MIN));
+    // Shifts bind looser than + and tighter than &, which binds tighter than |
+    assert_eq!(int("1 + 1 << 1 + 1"), Ok(8));
+    assert_eq!(int("1 | 6 & 3"), Ok(3));
+    assert_eq!(int("4 & 1 << 2"), Ok(4));
+  }
+
+  #[test]
+  fn shifting_out_of_range_is_an_error() {
+    assert_eq!(int("1 << 32"), Err(EvalError::InvalidShift));
+    assert_eq!(int("1 >> 32"), Err(EvalError::InvalidShift));
+    assert_eq!(int("1 << -1"), Err(EvalError::InvalidShift));
+    assert_eq!(int("8 >> -1"), Err(EvalError::InvalidShift));
+    assert_eq!(value::<i64>("1 << 32", &EvalConfig::default()), Ok(1 << 32));
+  }
+
+  #[test]
+  fn gcd_and_lcm() {
+    assert_eq!(int("gcd(12, 18)"), Ok(6));
+    assert_eq!(int("gcd(-12, 18)"), Ok(6));
+    assert_eq!(int("gcd(12, -18)"), Ok(6));
+    assert_eq!(int("gcd(0, 5)"), Ok(5));
+    assert_eq!(int("gcd(0, 0)"), Ok(0));
+    assert_eq!(int("lcm(4, 6)"), Ok(12));
+    assert_eq!(int("lcm(-4, 6)"), Ok(12));
+    assert_eq!(int("lcm(0, 5)"), Ok(0));
+    assert_eq!(int("lcm(5, 0)"), Ok(0));
+    assert_eq!(int("gcd(4)"), Err(EvalError::ArityMismatch("gcd".to_owned())));
+  }
+
+  #[test]
+  fn comparisons_are_zero_or_one() {
+    assert_eq!(int("3 < 5"), Ok(1));
+    assert_eq!(int("5 < 3"), Ok(0));
+    assert_eq!(int("3 > 5"), Ok(0));
+    assert_eq!(int("3 <= 3"), Ok(1));
+    assert_eq!(int("4 <= 3"), Ok(0));
+    assert_eq!(int("3 >= 3"), Ok(1));
+    assert_eq!(int("2 >= 3"), Ok(0));
+    assert_eq!(int("3 == 4"), Ok(0));
+    assert_eq!(int("4 == 4"), Ok(1));
+    assert_eq!(int("3 != 4"), Ok(1));
+    assert_eq!(int("4 != 4"), Ok(0));
+    // Below + and above ==, so this is (1 + 1 < 3) == 1
+    assert_eq!(int("1 + 1 < 3 == 1"), Ok(1));
+    assert_eq!(value("0.5 < 1", &EvalConfig::default()), Ok(1.0));
+    assert_eq!(tokenize("<=>=== !=<>"),
+               Ok(vec![Token::LessEq, Token::GreaterEq, Token::EqEq, Token::NotEq,
+                       Token::Less, Token::Greater]));
+  }
+
+  #[test]
+  fn conditional_evaluates_only_its_branch() {
+    assert_eq!(int("1 ? 2 : 3"), Ok(2));
+    assert_eq!(int("0 ? 2 : 3"), Ok(3));
+    assert_eq!(int("-5 ? 2 : 3"), Ok(2));
+    assert_eq!(int("1 ? 2 : (1/0)"), Ok(2));
+    assert_eq!(int("0 ? 1/0 : 4"), Ok(4));
+    assert_eq!(int("0 ? 2 : 1/0"), Err(EvalError::DivideByZero));
+    // Right-associative, and below everything else
+    assert_eq!(int("0 ? 1 : 0 ? 2 : 3"), Ok(3));
+    assert_eq!(int("1 < 2 ? 10 + 1 : 20"), Ok(11));
+    assert_eq!(int("x = 0 ? y : 7"), Ok(7));
+  }
+
+  #[test]
+  fn division_rounds_as_configured() {
+    let with = |div| EvalConfig { div, ..EvalConfig::default() };
+    let quotients = |input : &str| [DivMode::Trunc, DivMode::Floor, DivMode::Ceil]
+      .iter().map(|&div| value::<i32>(input, &with(div)).unwrap()).collect::<Vec<_>>();
+    assert_eq!(with(DivMode::default()), EvalConfig::default());
+    assert_eq!(quotients("-7 / 2"), [-3, -4, -3]);
+    assert_eq!(quotients("7 / 2"), [3, 3, 4]);
+    assert_eq!(quotients("7 / -2"), [-3, -4, -3]);
+    assert_eq!(quotients("-7 / -2"), [3, 3, 4]);
+    assert_eq!(quotients("-8 / 2"), [-4, -4, -4]);
+    assert_eq!(quotients("-7 div 2"), [-3, -4, -3]);
+    assert_eq!(value::<i64>("-7 / 2", &with(DivMode::Floor)), Ok(-4));
+    assert_eq!(value::<i32>("1 / 0", &with(DivMode::Floor)), Err(EvalError::DivideByZero));
+    assert_eq!(value::<i32>("1 / 0", &with(DivMode::Ceil)), Err(EvalError::DivideByZero));
+    // Floats are never rounded
+    assert_eq!(value("-7 / 2", &with(DivMode::Floor)), Ok(-3.5));
+  }
+
+  #[test]
+  fn trees_deeper_than_the_limit_are_refused() {
+    let negated = |n| (0..n).fold(AST::Num("1".to_owned()), |a, _| AST::Neg(Box::new(a)));
+    let config = EvalConfig { max_depth : 10, ..EvalConfig::default() };
+    assert_eq!(evaluate(&negated(9), &mut HashMap::new(), &config), Ok(-1));
+    assert_eq!(evaluate_iterative(&negated(9), &mut HashMap::new(), &config), Ok(-1));
+    assert_eq!(evaluate::<i32>(&negated(10), &mut HashMap::new(), &config),
+               Err(EvalError::DepthLimitExceeded));
+    assert_eq!(evaluate_iterative::<i32>(&negated(10), &mut HashMap::new(), &config),
+               Err(EvalError::DepthLimitExceeded));
+    // Far too deep to evaluate by recursion, but refused before starting
+    let config = EvalConfig::default();
+    assert_eq!(config.max_depth, MAX_DEPTH);
+    assert_eq!(evaluate::<i32>(&negated(5000), &mut HashMap::new(), &config),
+               Err(EvalError::DepthLimitExceeded));
+  }
+
+  #[test]
+  fn the_most_negative_literal() {
+    assert_eq!(int("-2147483648"), Ok(i32::MIN));
+    assert_eq!(int("- 2147483648;"), Ok(i32::MIN));
+    assert_eq!(int("-(2147483648)"), Ok(i32::MIN));
+    assert_eq!(int("-0x80000000"), Ok(i32::MIN));
+    assert_eq!(int("-2147483648 / 2"), Ok(-1073741824));
+    // Only negated is it in range
+    assert_eq!(int("2147483648"), Err(EvalError::Overflow));
+    assert_eq!(int("0 - 2147483648"), Err(EvalError::Overflow));
+    assert_eq!(int("-2147483649"), Err(EvalError::Overflow));
+  }
+
+  #[test]
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn overflow_is_handled_as_configured() {
+    let results = |input : &str| [OverflowMode::Error, OverflowMode::Saturate, OverflowMode::Wrap]
+      .iter().map(|&overflow| value::<i32>(input, &EvalConfig { overflow, ..EvalConfig::default() }))
+      .collect::<Vec<_>>();
+    let overflow = Err(EvalError::Overflow);
+    assert_eq!(EvalConfig::default().overflow, OverflowMode::Error);
+    assert_eq!(results("2147483647 + 1"), [overflow.clone(), Ok(i32::MAX), Ok(i32::MIN)]);
+    assert_eq!(results("-2147483647 - 2"), [overflow.clone(), Ok(i32::MIN), Ok(i32::MAX)]);
+    assert_eq!(results("65536 * 65536"), [overflow.clone(), Ok(i32::MAX), Ok(0)]);
+    assert_eq!(results("-65536 * 65537"), [overflow.clone(), Ok(i32::MIN), Ok(-65536)]);
+    assert_eq!(results("2 ^ 31"), [overflow.clone(), Ok(i32::MAX), Ok(i32::MIN)]);
+    assert_eq!(results("-(-2147483648)"), [overflow, Ok(i32::MAX), Ok(i32::MIN)]);
+    // In range, every mode agrees
+    assert_eq!(results("2147483646 + 1"), [Ok(i32::MAX), Ok(i32::MAX), Ok(i32::MAX)]);
+  }
+
+  fn float(input : &str) -> Result<f64, EvalError> {
+    value(input, &EvalConfig::default())
+  }
+
+  #[test]
+  fn min_max_and_clamp_on_floats() {
+    assert_eq!(float("max(1, 2.5, 2)"), Ok(2.5));
+    assert_eq!(float("min(1, -2.5, 2)"), Ok(-2.5));
+    assert_eq!(float("max(0.5)"), Ok(0.5));
+    assert_eq!(float("clamp(7, 1.5, 3)"), Ok(3.0));
+    assert_eq!(float("clamp(-7, 1.5, 3)"), Ok(1.5));
+    assert_eq!(float("clamp(2.25, 1, 3)"), Ok(2.25));
+    assert_eq!(float("clamp(2, 3, 3)"), Ok(3.0));
+    assert_eq!(float("clamp(2, 3.5, 1)"), Err(EvalError::DomainError));
+    assert_eq!(float("min()"), Err(EvalError::ArityMismatch("min".to_owned())));
+    assert_eq!(float("max()"), Err(EvalError::ArityMismatch("max".to_owned())));
+    assert_eq!(float("clamp(1, 2)"), Err(EvalError::ArityMismatch("clamp".to_owned())));
+    // Integers clamp too
+    assert_eq!(int("clamp(9, 1, 5)"), Ok(5));
+    assert_eq!(int("clamp(0, 5, 1)"), Err(EvalError::DomainError));
+  }
+
+  #[test]
+  fn double_star_is_a_power() {
+    assert_eq!(int("2 ** 10"), Ok(1024));
+    assert_eq!(int("2 ** 3 ** 2"), Ok(512));
+    assert_eq!(parse("2 ** 3"), parse("2 ^ 3"));
+    assert_eq!(int("-2 ** 2"), int("-2 ^ 2"));
+    // A single * still multiplies
+    assert_eq!(int("2 * 3"), Ok(6));
+    assert_eq!(int("2 * 3 ** 2"), Ok(18));
+  }
+
+  #[test]
+  fn errors_print_as_messages() {
+    let name = || "f".to_owned();
+    let messages = [
+      (EvalError::DivideByZero, "division by zero"),
+      (EvalError::NegativeExponent, "negative exponent"),
+      (EvalError::Overflow, "overflow"),
+      (EvalError::InvalidShift, "shift amount out of range"),
+      (EvalError::DomainError, "argument out of domain"),
+      (EvalError::UndefinedVar(name()), "undefined variable 'f'"),
+      (EvalError::ReadOnlyVar(name()), "variable 'f' cannot be assigned"),
+      (EvalError::UnknownFunction(name()), "unknown function 'f'"),
+      (EvalError::ArityMismatch(name()), "wrong number of arguments to 'f'"),
+      (EvalError::NotEnoughOperands, "not enough operands"),
+      (EvalError::TooManyOperands, "too many operands"),
+      (EvalError::DepthLimitExceeded, "expression nested too deeply")
+    ];
+    for (e, msg) in messages.iter() {
+      assert_eq!(e.to_string(), *msg);
+    }
+    let e : Box<dyn Error> = Box::new(EvalError::Overflow);
+    assert_eq!(e.to_string(), "overflow");
+  }
+
+  #[test]
+  fn signum_and_abs_on_integers() {
+    assert_eq!(int("signum(42)"), Ok(1));
+    assert_eq!(int("signum(-42)"), Ok(-1));
+    assert_eq!(int("signum(0)"), Ok(0));
+    assert_eq!(int("sign(-7)"), Ok(-1));
+    assert_eq!(int("abs(42)"), Ok(42));
+    assert_eq!(int("abs(-42)"), Ok(42));
+    assert_eq!(int("abs(0)"), Ok(0));
+    assert_eq!(int("abs(-2147483647)"), Ok(i32::MAX));
+    assert_eq!(int("signum(-2147483648)"), Ok(-1));
+    assert_eq!(int("signum()"), Err(EvalError::ArityMismatch("signum".to_owned())));
+  }
+
+  #[test]
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn abs_of_the_most_negative_integer_overflows() {
+    assert_eq!(int("abs(-2147483648)"), Err(EvalError::Overflow));
+    assert_eq!(int("|-2147483648|"), Err(EvalError::Overflow));
+    assert_eq!(value::<i64>("abs(-2147483648)", &EvalConfig::default()), Ok(2147483648));
+  }
+
+  #[test]
+  fn pow_function() {
+    assert_eq!(int("pow(2, 10)"), Ok(1024));
+    assert_eq!(int("pow(-3, 3)"), Ok(-27));
+    assert_eq!(int("pow(5, 0)"), Ok(1));
+    assert_eq!(int("pow(0, 0)"), Ok(1));
+    assert_eq!(int("pow(2, -1)"), Err(EvalError::DomainError));
+    assert_eq!(int("pow(2)"), Err(EvalError::ArityMismatch("pow".to_owned())));
+  }
+
+  #[test]
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn pow_function_overflow_is_an_error() {
+    assert_eq!(int("pow(10, 20)"), Err(EvalError::Overflow));
+    assert_eq!(int("pow(2, 31)"), Err(EvalError::Overflow));
+    assert_eq!(int("pow(-2, 31)"), Ok(i32::MIN));
+    // Even where ^ would saturate
+    let config = EvalConfig { overflow : OverflowMode::Saturate, ..EvalConfig::default() };
+    assert_eq!(value::<i32>("pow(10, 20)", &config), Err(EvalError::Overflow));
+    assert_eq!(value::<i32>("10 ^ 20", &config), Ok(i32::MAX));
+  }
+
+  #[test]
+  fn euclidean_modulo() {
+    assert_eq!(int("emod(7, 3)"), Ok(1));
+    assert_eq!(int("emod(-7, 3)"), Ok(2));
+    assert_eq!(int("emod(7, -3)"), Ok(1));
+    assert_eq!(int("emod(-7, -3)"), Ok(2));
+    assert_eq!(int("emod(6, 3)"), Ok(0));
+    // Where % keeps the sign of the dividend
+    assert_eq!(int("-7 % 3"), Ok(-1));
+    assert_eq!(int("emod(-2147483648, -1)"), Ok(0));
+    assert_eq!(int("emod(7, 0)"), Err(EvalError::DivideByZero));
+    assert_eq!(int("emod(7)"), Err(EvalError::ArityMismatch("emod".to_owned())));
+    assert_eq!(float("emod(-7.5, 2)"), Ok(0.5));
+  }
+
+  #[test]
+  fn seeded_rand_repeats() {
+    let seeded = |seed| EvalConfig { seed : Some(seed), ..EvalConfig::default() };
+    let input = "rand() + rand(1, 1000) * 3";
+    let first = value::<i64>(input, &seeded(7));
+    assert!(first.is_ok());
+    assert_eq!(value::<i64>(input, &seeded(7)), first);
+    assert_ne!(value::<i64>(input, &seeded(8)), first);
+    assert_eq!(value::<f64>("rand(0, 1)", &seeded(7)), value::<f64>("rand(0, 1)", &seeded(7)));
+    // Each draw moves the sequence on
+    assert_ne!(value::<i64>("rand() - rand()", &seeded(7)), Ok(0));
+  }
+
+  #[test]
+  fn rand_stays_in_its_range() {
+    let config = EvalConfig { seed : Some(1), ..EvalConfig::default() };
+    let mut env = HashMap::new();
+    let a = parse("rand(-3, 4)").unwrap();
+    for _ in 0..200 {
+      let n : i32 = evaluate_iterative(&a, &mut env, &EvalConfig::default()).unwrap();
+      assert!((-3..4).contains(&n), "{}", n);
+    }
+    let a = parse("rand()").unwrap();
+    assert!(evaluate_iterative::<i32>(&a, &mut env, &config).unwrap() >= 0);
+    assert!(evaluate_iterative::<f64>(&parse("rand(0.5, 1.5)").unwrap(), &mut HashMap::new(), &config)
+            .is_ok_and(|x| (0.5..1.5).contains(&x)));
+    assert_eq!(int("rand(2, 2)"), Err(EvalError::DomainError));
+    assert_eq!(int("rand(1)"), Err(EvalError::ArityMismatch("rand".to_owned())));
+  }
+
+  // An i64 that counts the calls made on it, to tell what was evaluated
+  #[derive(Clone,Copy,PartialEq,PartialOrd,Debug)]
+  struct Counted(i64);
+
+  thread_local! {
+    static CALLS : std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+  }
+
+  impl std::str::FromStr for Counted {
+    type Err = std::num::ParseIntError;
+    fn from_str(s : &str) -> Result<Counted, Self::Err> {
+      s.parse().map(Counted)
+    }
+  }
+
+  impl fmt::Display for Counted {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+      self.0.fmt(f)
+    }
+  }
+
+  impl Number for Counted {
+    fn from_f64(x : f64) -> Result<Counted, EvalError> { i64::from_f64(x).map(Counted) }
+    fn from_bool(b : bool) -> Counted { Counted(i64::from_bool(b)) }
+    fn add(self, y : Counted) -> Result<Counted, EvalError> { Number::add(self.0, y.0).map(Counted) }
+    fn sub(self, y : Counted) -> Result<Counted, EvalError> { Number::sub(self.0, y.0).map(Counted) }
+    fn mul(self, y : Counted) -> Result<Counted, EvalError> { Number::mul(self.0, y.0).map(Counted) }
+    fn div(self, y : Counted) -> Result<Counted, EvalError> { Number::div(self.0, y.0).map(Counted) }
+    fn div_floor(self, y : Counted) -> Result<Counted, EvalError> { Number::div_floor(self.0, y.0).map(Counted) }
+    fn div_ceil(self, y : Counted) -> Result<Counted, EvalError> { Number::div_ceil(self.0, y.0).map(Counted) }
+    fn rem(self, y : Counted) -> Result<Counted, EvalError> { Number::rem(self.0, y.0).map(Counted) }
+    fn pow(self, e : Counted) -> Result<Counted, EvalError> { Number::pow(self.0, e.0).map(Counted) }
+    fn neg(self) -> Result<Counted, EvalError> { Number::neg(self.0).map(Counted) }
+    fn factorial(self) -> Result<Counted, EvalError> { Number::factorial(self.0).map(Counted) }
+    fn call(name : &str, args : &[Counted], config : &EvalConfig) -> Result<Counted, EvalError> {
+      CALLS.with(|calls| calls.set(calls.get() + 1));
+      let args : Vec<i64> = args.iter().map(|x| x.0).collect();
+      i64::call(name, &args, config).map(Counted)
+    }
+  }
+
+  // The value of the input evaluated with memoization, and how many
+  // calls evaluating it made
+  fn memoized(input : &str, config : &EvalConfig) -> (Result<Counted, EvalError>, usize) {
+    CALLS.with(|calls| calls.set(0));
+    let a = parse(input).unwrap();
+    let v = evaluate_memoized(&a, &mut HashMap::new(), config);
+    let calls = CALLS.with(|calls| calls.get());
+    assert_eq!(v, evaluate(&a, &mut HashMap::new(), config), "{}", input);
+    (v, calls)
+  }
+
+  #[test]
+  fn memoizing_evaluates_each_copy_once() {
+    let config = EvalConfig::default();
+    let shared = "max(1, abs(-2) * 3)";
+    let a = substitute(&parse("x * x + x").unwrap(), "x", &parse(shared).unwrap());
+    assert_eq!(memoized(&a.to_string(), &config), (Ok(Counted(42)), 2));
+    CALLS.with(|calls| calls.set(0));
+    assert_eq!(evaluate::<Counted>(&a, &mut HashMap::new(), &config), Ok(Counted(42)));
+    assert_eq!(CALLS.with(|calls| calls.get()), 6);
+    // Different subtrees each count
+    assert_eq!(memoized("abs(-1) + abs(-2) + abs(-1)", &config), (Ok(Counted(4)), 2));
+  }
+
+  #[test]
+  fn memoizing_leaves_impure_trees_alone() {
+    let config = EvalConfig { seed : Some(3), ..EvalConfig::default() };
+    assert_eq!(memoized("rand(0, 1000) + rand(0, 1000)", &config).1, 2);
+    let (v, _) = memoized("rand(0, 1000) - rand(0, 1000)", &config);
+    assert_ne!(v, Ok(Counted(0)));
+    // A read after an assignment sees the new value, as x + 1 before and
+    // after x = 5 shows
+    let x_plus_1 = || Box::new(parse("x + 1").unwrap());
+    let a = AST::Plus(Box::new(AST::Plus(x_plus_1(), Box::new(AST::Assign("x".to_owned(),
+                                                                           Box::new(AST::Num("5".to_owned())))))),
+                      x_plus_1());
+    let mut env = HashMap::new();
+    env.insert("x".to_owned(), 1);
+    assert_eq!(evaluate_memoized(&a, &mut env, &config), Ok(13));
+  }
+}
@@ -0,0 +1,46 @@
+use serde_json;
+
+use parser::AST;
+
+
+/**********************************************************************
+ * JSON
+ */
+
+/// Serializes a tree so it can be sent elsewhere and evaluated there.
+pub fn ast_to_json(a : &AST) -> Result<String, serde_json::Error> {
+  serde_json::to_string(a)
+}
+
+/// Reads back a tree written by `ast_to_json`.
+pub fn ast_from_json(s : &str) -> Result<AST, serde_json::Error> {
+  serde_json::from_str(s)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use interpreter::{EvalConfig, evaluate};
+  use parse;
+
+  #[test]
+  fn round_trips() {
+    let a = parse("1 + 2 * 3").unwrap();
+    let b = ast_from_json(&ast_to_json(&a).unwrap()).unwrap();
+    assert_eq!(b, a);
+    let config = EvalConfig::default();
+    assert_eq!(evaluate::<i32>(&b, &mut HashMap::new(), &config), Ok(7));
+    let a = parse("x = max(-1.5, |2|, 3!) ? pi : 4 % 5").unwrap();
+    assert_eq!(ast_from_json(&ast_to_json(&a).unwrap()).unwrap(), a);
+  }
+
+  #[test]
+  fn malformed_json_is_an_error() {
+    assert!(ast_from_json("").is_err());
+    assert!(ast_from_json("{\"Plus\": [{\"Num\": \"1\"}]}").is_err());
+    assert!(ast_from_json("{\"Nope\": 1}").is_err());
+  }
+}
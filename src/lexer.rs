@@ -0,0 +1,712 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+
+/**********************************************************************
+ * LEXER
+ */
+
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum Token {
+  Num(String),
+  Ident(String),
+  Plus,
+  Minus,
+  Times,
+  Divide,
+  Percent,
+  /// The keyword `mod`, another spelling of `%` as modulo
+  Mod,
+  /// The keyword `div`, integer division
+  Div,
+  Caret,
+  Bang,
+  Amp,
+  Pipe,
+  Shl,
+  Shr,
+  Less,
+  Greater,
+  LessEq,
+  GreaterEq,
+  EqEq,
+  NotEq,
+  Equals,
+  Question,
+  Colon,
+  LParen,
+  RParen,
+  Comma,
+  Semi,
+  /// The end of input, which `get_token` returns from then on
+  Eof
+}
+
+/// Tokens print as they are written in the input, and the end of input
+/// as `end of input`.
+impl fmt::Display for Token {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    let s = match *self {
+      Token::Num(ref x) | Token::Ident(ref x) => x,
+      Token::Plus => "+",
+      Token::Minus => "-",
+      Token::Times => "*",
+      Token::Divide => "/",
+      Token::Percent => "%",
+      Token::Mod => "mod",
+      Token::Div => "div",
+      Token::Caret => "^",
+      Token::Bang => "!",
+      Token::Amp => "&",
+      Token::Pipe => "|",
+      Token::Shl => "<<",
+      Token::Shr => ">>",
+      Token::Less => "<",
+      Token::Greater => ">",
+      Token::LessEq => "<=",
+      Token::GreaterEq => ">=",
+      Token::EqEq => "==",
+      Token::NotEq => "!=",
+      Token::Equals => "=",
+      Token::Question => "?",
+      Token::Colon => ":",
+      Token::LParen => "(",
+      Token::RParen => ")",
+      Token::Comma => ",",
+      Token::Semi => ";",
+      Token::Eof => "end of input"
+    };
+    write!(f, "{}", s)
+  }
+}
+
+/// A position in the input.  Lines and columns both count from 1, and
+/// columns count characters rather than bytes.
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub struct Span {
+  pub line : usize,
+  pub col : usize
+}
+
+/// Ways the character stream can fail to form a token.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum LexError {
+  UnexpectedChar { found : char, span : Span },
+  /// A literal that is not a number, like a `0x` with no digits after it
+  InvalidLiteral { literal : String, span : Span },
+  /// The input could not be read, for instance because it was not
+  /// UTF-8.  It is treated as ending there.
+  Read(io::ErrorKind)
+}
+
+impl LexError {
+  /// Where in the input the error was found, if anywhere.
+  pub fn span(&self) -> Option<Span> {
+    match *self {
+      LexError::UnexpectedChar { span, .. } | LexError::InvalidLiteral { span, .. } => Some(span),
+      LexError::Read(_) => None
+    }
+  }
+}
+
+impl fmt::Display for LexError {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      LexError::UnexpectedChar { found, .. } => write!(f, "unexpected character '{}'", found),
+      LexError::InvalidLiteral { ref literal, .. } => write!(f, "invalid number '{}'", literal),
+      LexError::Read(kind) => write!(f, "could not read input: {}", io::Error::from(kind))
+    }
+  }
+}
+
+impl Error for LexError {}
+
+pub struct Lexer {
+  buffer: String,
+  offset: usize,
+  line: usize,
+  start: Span,
+  // A token read ahead by peek, with where it starts
+  peeked: Option<(Token, Span)>,
+  // Why reading stopped early, if it did
+  failed: Option<io::ErrorKind>,
+  reader: Box<dyn BufRead>
+}
+
+impl Lexer {
+  /// Creates a lexer that reads its input line by line from stdin.
+  #[allow(clippy::new_without_default)]
+  pub fn new() -> Lexer {
+    Lexer::from_reader(io::stdin().lock())
+  }
+
+  /// Creates a lexer over a fixed input string.
+  pub fn from_string(input : &str) -> Lexer {
+    Lexer::from_reader(io::Cursor::new(input.to_owned()))
+  }
+
+  /// Creates a lexer that pulls lines from `reader` as it needs them,
+  /// the first one on the first call to `get_token`.
+  pub fn from_reader<R : BufRead + 'static>(reader : R) -> Lexer {
+    Lexer {
+      buffer: String::new(),
+      offset: 0,
+      line: 0,
+      start: Span { line : 1, col : 1 },
+      peeked: None,
+      failed: None,
+      reader: Box::new(reader)
+    }
+  }
+
+  // Replace the buffer with the next line of input.  False once the
+  // input is exhausted, leaving the last line in the buffer, used up, so
+  // errors at the end of input can still show it.
+  fn fill(&mut self) -> bool {
+    if self.failed.is_some() {
+      return false;
+    }
+    let mut line = String::new();
+    match self.reader.read_line(&mut line) {
+      Ok(0) => { self.offset = self.buffer.len();
+                 false },
+      Ok(_) => { self.buffer = line;
+                 self.offset = 0;
+                 self.line += 1;
+                 true },
+      Err(e) => { self.buffer.clear();
+                  self.offset = 0;
+                  self.failed = Some(e.kind());
+                  false }
+    }
+  }
+
+  // Step past the current character.  Never reads input, so it is safe
+  // on an empty or used-up buffer.
+  fn advance(&mut self) {
+    if let Some(c) = self.buffer[self.offset..].chars().next() {
+      self.offset += c.len_utf8();
+    }
+  }
+
+  // Where the current character is
+  fn position(&self) -> Span {
+    Span {
+      line : self.line,
+      col : self.buffer[..self.offset].chars().count() + 1
+    }
+  }
+
+  /// Where the token most recently returned by `get_token` starts.
+  pub fn span(&self) -> Span {
+    self.start
+  }
+
+  /// Moves on to the next input line, discarding the rest of this one
+  /// along with any token peeked from it.
+  pub fn skip_line(&mut self) {
+    self.peeked = None;
+    self.offset = self.buffer.len();
+  }
+
+  /// The text of input line `line`, counting from 1, if it is the line
+  /// being lexed.  Earlier lines are not kept.
+  pub fn source_line(&self, line : usize) -> Option<&str> {
+    if line == self.line {
+      Some(self.buffer.trim_end_matches(&['\n', '\r'][..]))
+    } else {
+      None
+    }
+  }
+
+  /// True if nothing but whitespace or a comment remains on the current
+  /// input line.  Checking this never blocks waiting to read another line.
+  pub fn at_line_end(&self) -> bool {
+    if self.peeked.is_some() {
+      return false;
+    }
+    let rest = self.buffer[self.offset..].trim_start();
+    rest.is_empty() || rest.starts_with('#')
+  }
+
+  /// Reads the next input line once the current one is used up, and
+  /// true if it turns out to be blank or only a comment, so there is
+  /// nothing to lex before the line after.  False at the end of input.
+  pub fn skip_blank_line(&mut self) -> bool {
+    if !self.at_line_end() {
+      return false;
+    }
+    self.fill() && self.at_line_end()
+  }
+
+  /// If what is left of the current input line starts with `prefix`,
+  /// past any whitespace, consumes the whole line and returns the rest
+  /// of it after the prefix, trimmed.  This is how the REPL reads a
+  /// command instead of input.  It never reads another line.
+  pub fn take_command(&mut self, prefix : char) -> Option<String> {
+    if self.peeked.is_some() {
+      return None;
+    }
+    let command = self.buffer[self.offset..].trim().strip_prefix(prefix)?.trim().to_owned();
+    self.skip_line();
+    Some(command)
+  }
+
+  // The current character, reading another line once this one is used
+  // up.  None means the input is exhausted.
+  fn current(&mut self) -> Option<char> {
+    if self.offset >= self.buffer.len() && !self.fill() {
+      return None;
+    }
+    self.buffer[self.offset..].chars().next()
+  }
+
+  // The character after the current one, if it is on the same line
+  fn peek_char(&self) -> Option<char> {
+    self.buffer[self.offset..].chars().nth(1)
+  }
+
+  // Append characters to t for as long as they satisfy pred
+  fn take_while<F : Fn(char) -> bool>(&mut self, t : &mut String, pred : F) {
+    while let Some(c) = self.current() {
+      if !pred(c) {
+        break;
+      }
+      t.push(c);
+      self.advance();
+    }
+  }
+
+  // Append a run of digits in radix to t.  Single underscores may
+  // separate digits, as in 1_000, but are left out of t.
+  fn take_digits(&mut self, t : &mut String, radix : u32) -> Result<(), LexError> {
+    let mut after_digit = false;
+    while let Some(c) = self.current() {
+      if c.is_digit(radix) {
+        t.push(c);
+        after_digit = true;
+      } else if c == '_' {
+        if !after_digit || !self.peek_char().is_some_and(|d| d.is_digit(radix)) {
+          let span = self.position();
+          self.advance();
+          return Err(LexError::UnexpectedChar { found : c, span });
+        }
+        after_digit = false;
+      } else {
+        break;
+      }
+      self.advance();
+    }
+    Ok(())
+  }
+
+  // A # comment runs to the end of the line and counts as whitespace
+  fn skip_whitespace(&mut self) {
+    while let Some(c) = self.current() {
+      if c == '#' {
+        self.skip_line();
+      } else if c.is_whitespace() {
+        self.advance();
+      } else {
+        break;
+      }
+    }
+  }
+
+  /// True if no tokens remain in the input.  This reads ahead past
+  /// whitespace and comments, so on stdin it may block for more input.
+  pub fn at_eof(&mut self) -> bool {
+    if self.peeked.is_some() {
+      return false;
+    }
+    self.skip_whitespace();
+    self.current().is_none()
+  }
+
+  /// Returns the next token without consuming it, so that the
+  /// following `get_token` returns it again.  `span` still refers to the
+  /// token before it until then.
+  pub fn peek(&mut self) -> Result<&Token, LexError> {
+    let peeked = match self.peeked.take() {
+      Some(p) => p,
+      None => { let prev = self.start;
+                let t = self.read_token()?;
+                let span = self.start;
+                self.start = prev;
+                (t, span) }
+    };
+    Ok(&self.peeked.insert(peeked).0)
+  }
+
+  /// Consumes and returns the next token.  Once the input runs out,
+  /// every call returns `Token::Eof`.
+  pub fn get_token(&mut self) -> Result<Token, LexError> {
+    match self.peeked.take() {
+      Some((t, span)) => { self.start = span;
+                           Ok(t) },
+      None => self.read_token()
+    }
+  }
+
+  // Lex a token straight from the input.  Once the input is exhausted
+  // that is Eof, unless it could not be read.
+  fn read_token(&mut self) -> Result<Token, LexError> {
+    let mut t = String::new();
+    self.skip_whitespace();
+    let c = self.current();
+    self.start = self.position();
+    let c = match (c, self.failed) {
+      (Some(c), _) => c,
+      (None, Some(kind)) => return Err(LexError::Read(kind)),
+      (None, None) => return Ok(Token::Eof)
+    };
+
+    // mod and div are operators, so nothing can be named either
+    if c.is_alphabetic() {
+      self.take_while(&mut t, |c| c.is_alphanumeric() || c == '_');
+      return Ok(match t.as_str() {
+        "mod" => Token::Mod,
+        "div" => Token::Div,
+        _ => Token::Ident(t)
+      });
+    }
+
+    // A 0x or 0b prefix introduces a hex or binary literal, kept whole
+    // in the token.  The literal must have at least one digit, and may
+    // not run straight into a decimal digit outside its radix.  A prefix
+    // cut short by a failed read is not the literal's fault.
+    let (radix, prefix) = match (c, self.peek_char()) {
+      ('0', Some(x @ 'x')) | ('0', Some(x @ 'X')) => (16, x),
+      ('0', Some(b @ 'b')) | ('0', Some(b @ 'B')) => (2, b),
+      _ => (10, ' ')
+    };
+    if radix != 10 {
+      t.push(c);
+      t.push(prefix);
+      self.advance();
+      self.advance();
+      self.take_digits(&mut t, radix)?;
+      return match (self.current(), self.failed) {
+        (None, Some(kind)) if t.len() == 2 => Err(LexError::Read(kind)),
+        _ if t.len() == 2 => Err(LexError::InvalidLiteral { literal : t, span : self.start }),
+        (Some(c), _) if c.is_ascii_digit() => {
+          let span = self.position();
+          self.advance();
+          Err(LexError::UnexpectedChar { found : c, span })
+        },
+        _ => Ok(Token::Num(t))
+      };
+    }
+
+    // A numeric literal is a run of digits with at most one decimal
+    // point anywhere in it, so `4.` and `.5` are both floats, and `1.2.3`
+    // is an error rather than 1.2 next to .3
+    self.take_digits(&mut t, 10)?;
+
+    if self.current() == Some('.') {
+      t.push('.');
+      self.advance();
+      self.take_digits(&mut t, 10)?;
+      if t == "." {
+        return Err(LexError::UnexpectedChar { found : '.', span : self.start });
+      }
+      if self.current() == Some('.') {
+        let span = self.position();
+        self.advance();
+        return Err(LexError::UnexpectedChar { found : '.', span });
+      }
+    }
+
+    if !t.is_empty() {
+      return Ok(Token::Num(t));
+    }
+
+    // An operator is one character or two, and the two-character one
+    // wins whenever its second character follows directly, so `<=` is
+    // one token and `< =` is two
+    let (tok, len) = match (c, self.peek_char()) {
+      ('<', Some('<')) => (Token::Shl, 2),
+      ('>', Some('>')) => (Token::Shr, 2),
+      ('<', Some('=')) => (Token::LessEq, 2),
+      ('>', Some('=')) => (Token::GreaterEq, 2),
+      ('=', Some('=')) => (Token::EqEq, 2),
+      // So `5!=3` is a comparison, not a factorial assigned to
+      ('!', Some('=')) => (Token::NotEq, 2),
+      // The spelling of powers some languages use
+      ('*', Some('*')) => (Token::Caret, 2),
+      ('+', _) => (Token::Plus, 1),
+      ('-', _) => (Token::Minus, 1),
+      ('*', _) => (Token::Times, 1),
+      ('/', _) => (Token::Divide, 1),
+      ('%', _) => (Token::Percent, 1),
+      ('^', _) => (Token::Caret, 1),
+      ('!', _) => (Token::Bang, 1),
+      ('&', _) => (Token::Amp, 1),
+      ('|', _) => (Token::Pipe, 1),
+      ('<', _) => (Token::Less, 1),
+      ('>', _) => (Token::Greater, 1),
+      ('=', _) => (Token::Equals, 1),
+      ('?', _) => (Token::Question, 1),
+      (':', _) => (Token::Colon, 1),
+      ('(', _) => (Token::LParen, 1),
+      (')', _) => (Token::RParen, 1),
+      (',', _) => (Token::Comma, 1),
+      (';', _) => (Token::Semi, 1),
+      (x, _) => { self.advance();
+                  return Err(LexError::UnexpectedChar { found : x, span : self.start }) }
+    };
+    for _ in 0..len {
+      self.advance();
+    }
+    Ok(tok)
+  }
+}
+
+/// Lexes all of `input`.  The end of input is not represented in the
+/// result, so `tokenize("")` is an empty vector.
+pub fn tokenize(input : &str) -> Result<Vec<Token>, LexError> {
+  let mut lexer = Lexer::from_string(input);
+  let mut tokens = Vec::new();
+  while !lexer.at_eof() {
+    tokens.push(lexer.get_token()?);
+  }
+  Ok(tokens)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn num(s : &str) -> Token {
+    Token::Num(s.to_owned())
+  }
+
+  #[test]
+  fn float_literals() {
+    assert_eq!(tokenize("1.5 4. .5"), Ok(vec![num("1.5"), num("4."), num(".5")]));
+    assert_eq!(tokenize("2*.5"), Ok(vec![num("2"), Token::Times, num(".5")]));
+  }
+
+  #[test]
+  fn lone_point_is_an_error() {
+    assert_eq!(tokenize("1 + ."),
+               Err(LexError::UnexpectedChar { found : '.', span : Span { line : 1, col : 5 } }));
+  }
+
+  #[test]
+  fn hex_literals() {
+    assert_eq!(tokenize("0x1f 0XFF 0x0"), Ok(vec![num("0x1f"), num("0XFF"), num("0x0")]));
+    assert_eq!(tokenize("0x1g"),
+               Ok(vec![num("0x1"), Token::Ident("g".to_owned())]));
+  }
+
+  #[test]
+  fn binary_literals() {
+    assert_eq!(tokenize("0b101 0B1"), Ok(vec![num("0b101"), num("0B1")]));
+    assert_eq!(tokenize("0b102"),
+               Err(LexError::UnexpectedChar { found : '2', span : Span { line : 1, col : 5 } }));
+  }
+
+  #[test]
+  fn prefix_without_digits() {
+    let invalid = |literal : &str, col| Err(LexError::InvalidLiteral { literal : literal.to_owned(),
+                                                                       span : Span { line : 1, col } });
+    assert_eq!(tokenize("0x"), invalid("0x", 1));
+    assert_eq!(tokenize("1 + 0b"), invalid("0b", 5));
+    assert_eq!(tokenize("0x + 1"), invalid("0x", 1));
+    assert_eq!(tokenize("0xg"), invalid("0x", 1));
+  }
+
+  #[test]
+  fn second_point_is_an_error() {
+    assert_eq!(tokenize("1.2.3"),
+               Err(LexError::UnexpectedChar { found : '.', span : Span { line : 1, col : 4 } }));
+    assert_eq!(tokenize("1..2"),
+               Err(LexError::UnexpectedChar { found : '.', span : Span { line : 1, col : 3 } }));
+  }
+
+  #[test]
+  fn unrecognized_character_is_an_error() {
+    assert_eq!(tokenize("1 + $"),
+               Err(LexError::UnexpectedChar { found : '$', span : Span { line : 1, col : 5 } }));
+    assert_eq!(tokenize("@"),
+               Err(LexError::UnexpectedChar { found : '@', span : Span { line : 1, col : 1 } }));
+    let mut lexer = Lexer::from_string("2 ` 3");
+    assert_eq!(lexer.get_token(), Ok(num("2")));
+    assert!(lexer.get_token().is_err());
+    // Lexing goes on after the bad character
+    assert_eq!(lexer.get_token(), Ok(num("3")));
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+  }
+
+  #[test]
+  fn comments_run_to_the_end_of_the_line() {
+    assert_eq!(tokenize("2+3; # a sum + more; 4"),
+               Ok(vec![num("2"), Token::Plus, num("3"), Token::Semi]));
+    assert_eq!(tokenize("1 # one\n+ 2"), Ok(vec![num("1"), Token::Plus, num("2")]));
+    assert_eq!(tokenize("# only a comment\n"), Ok(vec![]));
+    let mut lexer = Lexer::from_string("7 # seven\n8");
+    assert_eq!(lexer.get_token(), Ok(num("7")));
+    assert!(lexer.at_line_end());
+    assert_eq!(lexer.get_token(), Ok(num("8")));
+    assert_eq!(lexer.span(), Span { line : 2, col : 1 });
+  }
+
+  #[test]
+  fn multibyte_and_empty_lines() {
+    // An ideographic space and a no-break space are whitespace
+    assert_eq!(tokenize("1\u{3000}+\u{a0}2"), Ok(vec![num("1"), Token::Plus, num("2")]));
+    assert_eq!(tokenize("é + 1"), Ok(vec![Token::Ident("é".to_owned()), Token::Plus, num("1")]));
+    assert_eq!(tokenize("\n\n3\n"), Ok(vec![num("3")]));
+    assert_eq!(tokenize(""), Ok(vec![]));
+    let mut lexer = Lexer::from_string("αβ ∞");
+    assert_eq!(lexer.get_token(), Ok(Token::Ident("αβ".to_owned())));
+    assert_eq!(lexer.get_token(),
+               Err(LexError::UnexpectedChar { found : '∞', span : Span { line : 1, col : 4 } }));
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+  }
+
+  #[test]
+  fn reads_lines_from_any_reader() {
+    let mut lexer = Lexer::from_reader(io::Cursor::new("1 +\n  2\n".to_owned()));
+    assert_eq!(lexer.get_token(), Ok(num("1")));
+    assert_eq!(lexer.get_token(), Ok(Token::Plus));
+    assert_eq!(lexer.get_token(), Ok(num("2")));
+    assert_eq!(lexer.span(), Span { line : 2, col : 3 });
+    assert_eq!(lexer.source_line(2), Some("  2"));
+    assert_eq!(lexer.source_line(1), None);
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+  }
+
+  // Yields its input and then fails, as a pipe might
+  struct Failing(io::Cursor<String>);
+
+  impl Read for Failing {
+    fn read(&mut self, buf : &mut [u8]) -> io::Result<usize> {
+      self.0.read(buf)
+    }
+  }
+
+  impl BufRead for Failing {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+      if self.0.position() == self.0.get_ref().len() as u64 {
+        return Err(io::Error::from(io::ErrorKind::BrokenPipe));
+      }
+      self.0.fill_buf()
+    }
+
+    fn consume(&mut self, n : usize) {
+      self.0.consume(n)
+    }
+  }
+
+  #[test]
+  fn read_errors_end_the_input() {
+    let mut lexer = Lexer::from_reader(Failing(io::Cursor::new("1\n".to_owned())));
+    assert_eq!(lexer.get_token(), Ok(num("1")));
+    assert_eq!(lexer.get_token(), Err(LexError::Read(io::ErrorKind::BrokenPipe)));
+    assert!(lexer.at_eof());
+  }
+
+  #[test]
+  fn tokenize_lexes_the_whole_input() {
+    assert_eq!(tokenize("1 + 2"), Ok(vec![num("1"), Token::Plus, num("2")]));
+    assert_eq!(tokenize("  \t123\t*\r\n 45  "), Ok(vec![num("123"), Token::Times, num("45")]));
+    assert_eq!(tokenize("x1 = y_2"),
+               Ok(vec![Token::Ident("x1".to_owned()), Token::Equals, Token::Ident("y_2".to_owned())]));
+    let ops = tokenize("+ - * / % ^ ! & | << >> < > <= >= == != = ? : ( ) , ; mod div").unwrap();
+    assert_eq!(ops, vec![
+      Token::Plus, Token::Minus, Token::Times, Token::Divide, Token::Percent, Token::Caret,
+      Token::Bang, Token::Amp, Token::Pipe, Token::Shl, Token::Shr, Token::Less, Token::Greater,
+      Token::LessEq, Token::GreaterEq, Token::EqEq, Token::NotEq, Token::Equals, Token::Question,
+      Token::Colon, Token::LParen, Token::RParen, Token::Comma, Token::Semi, Token::Mod, Token::Div
+    ]);
+    // Each prints as it was written
+    let shown : Vec<String> = ops.iter().map(Token::to_string).collect();
+    assert_eq!(shown.join(" "), "+ - * / % ^ ! & | << >> < > <= >= == != = ? : ( ) , ; mod div");
+  }
+
+  #[test]
+  fn peek_does_not_consume() {
+    let mut lexer = Lexer::from_string("1 <= x\ny");
+    assert_eq!(lexer.peek(), Ok(&num("1")));
+    assert_eq!(lexer.peek(), Ok(&num("1")));
+    assert_eq!(lexer.get_token(), Ok(num("1")));
+    assert_eq!(lexer.span(), Span { line : 1, col : 1 });
+    assert_eq!(lexer.peek(), Ok(&Token::LessEq));
+    // The span is still the last token's until the peeked one is taken
+    assert_eq!(lexer.span(), Span { line : 1, col : 1 });
+    assert_eq!(lexer.get_token(), Ok(Token::LessEq));
+    assert_eq!(lexer.span(), Span { line : 1, col : 3 });
+    assert_eq!(lexer.get_token(), Ok(Token::Ident("x".to_owned())));
+    assert_eq!(lexer.peek(), Ok(&Token::Ident("y".to_owned())));
+    assert_eq!(lexer.get_token(), Ok(Token::Ident("y".to_owned())));
+    assert_eq!(lexer.span(), Span { line : 2, col : 1 });
+    assert_eq!(lexer.peek(), Ok(&Token::Eof));
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+  }
+
+  #[test]
+  fn underscores_separate_digits() {
+    assert_eq!(tokenize("1_000 + 1;"), Ok(vec![num("1000"), Token::Plus, num("1"), Token::Semi]));
+    assert_eq!(tokenize("1_0.5_0 0xf_f 0b1_0"), Ok(vec![num("10.50"), num("0xff"), num("0b10")]));
+    let misplaced = |input : &str, col| assert_eq!(tokenize(input),
+                                                    Err(LexError::UnexpectedChar { found : '_',
+                                                                                   span : Span { line : 1, col } }),
+                                                    "{}", input);
+    misplaced("1__0", 2);
+    misplaced("_5", 1);
+    misplaced("5_", 2);
+    misplaced("5_ + 1", 2);
+    misplaced("0x_ff", 3);
+    misplaced("1._5", 3);
+  }
+
+  #[test]
+  fn two_character_operators_need_no_space() {
+    assert_eq!(tokenize("<="), Ok(vec![Token::LessEq]));
+    assert_eq!(tokenize("< ="), Ok(vec![Token::Less, Token::Equals]));
+    assert_eq!(tokenize("= ="), Ok(vec![Token::Equals, Token::Equals]));
+    assert_eq!(tokenize("2**3"), Ok(vec![num("2"), Token::Caret, num("3")]));
+    assert_eq!(tokenize("2* *3"), Ok(vec![num("2"), Token::Times, Token::Times, num("3")]));
+    assert_eq!(tokenize("5!=3"), Ok(vec![num("5"), Token::NotEq, num("3")]));
+    assert_eq!(tokenize("5! =3"), Ok(vec![num("5"), Token::Bang, Token::Equals, num("3")]));
+    // The longest operator wins at each point
+    assert_eq!(tokenize("<<="), Ok(vec![Token::Shl, Token::Equals]));
+    assert_eq!(tokenize("<=="), Ok(vec![Token::LessEq, Token::Equals]));
+    assert_eq!(tokenize("==="), Ok(vec![Token::EqEq, Token::Equals]));
+    assert_eq!(tokenize("***"), Ok(vec![Token::Caret, Token::Times]));
+    // Nor is one read across the end of a line
+    assert_eq!(tokenize("1 >\n= 2"), Ok(vec![num("1"), Token::Greater, Token::Equals, num("2")]));
+  }
+
+  #[test]
+  fn errors_print_as_messages() {
+    let span = Span { line : 1, col : 1 };
+    assert_eq!(LexError::UnexpectedChar { found : '$', span }.to_string(), "unexpected character '$'");
+    assert_eq!(LexError::InvalidLiteral { literal : "0x".to_owned(), span }.to_string(),
+               "invalid number '0x'");
+    assert_eq!(LexError::Read(io::ErrorKind::InvalidData).to_string(),
+               format!("could not read input: {}", io::Error::from(io::ErrorKind::InvalidData)));
+  }
+
+  #[test]
+  fn eof_repeats_past_the_end() {
+    for input in &["", "   ", "# just a comment", "1 + 2\n\n"] {
+      let mut lexer = Lexer::from_string(input);
+      while lexer.get_token() != Ok(Token::Eof) {}
+      for _ in 0..3 {
+        assert_eq!(lexer.get_token(), Ok(Token::Eof), "{:?}", input);
+        assert!(lexer.at_eof());
+      }
+    }
+    let mut lexer = Lexer::from_reader(io::Cursor::new(String::new()));
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+    assert_eq!(lexer.peek(), Ok(&Token::Eof));
+    assert_eq!(lexer.get_token(), Ok(Token::Eof));
+  }
+}
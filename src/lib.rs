@@ -0,0 +1,527 @@
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "bignum")]
+extern crate num_bigint;
+#[cfg(feature = "bignum")]
+extern crate num_integer;
+#[cfg(feature = "rational")]
+extern crate num_rational;
+#[cfg(any(feature = "bignum", feature = "rational"))]
+extern crate num_traits;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::BufRead;
+
+mod lexer;
+mod parser;
+mod interpreter;
+mod number;
+mod optimize;
+mod transform;
+mod analysis;
+mod format;
+mod trace;
+mod plot;
+mod repl;
+mod rpn;
+mod sexpr;
+mod dot;
+#[cfg(feature = "serde")]
+mod json;
+#[cfg(feature = "bignum")]
+mod bignum;
+#[cfg(feature = "rational")]
+mod rational;
+
+pub use lexer::{Lexer, LexError, Span, Token, tokenize};
+pub use parser::{Assoc, MAX_DEPTH, MAX_NESTING, Parser, ParseError, PrecedenceTable, AST, skip_statement};
+pub use interpreter::{AngleMode, DivMode, EvalConfig, EvalError, OverflowMode, evaluate, evaluate_f64, evaluate_iterative, evaluate_memoized};
+pub use number::{FLOAT_FUNCTIONS, Number};
+pub use optimize::fold;
+pub use transform::{derivative, flatten, normalize, simplify, substitute, unflatten};
+pub use analysis::{IMPURE_FUNCTIONS, depth, equivalent, is_constant, is_pure, node_count};
+pub use format::{Base, DEFAULT_PRECISION, format_float, format_result, format_timing, render_error};
+pub use trace::evaluate_traced;
+pub use plot::{plot_ascii, tabulate};
+pub use repl::{ReplConfig, run_repl, run_repl_with};
+pub use rpn::{eval_rpn, to_rpn};
+pub use sexpr::from_sexpr;
+pub use dot::to_dot;
+#[cfg(feature = "serde")]
+pub use json::{ast_to_json, ast_from_json};
+#[cfg(feature = "bignum")]
+pub use bignum::evaluate_big;
+#[cfg(feature = "bignum")]
+pub use num_bigint::BigInt;
+#[cfg(feature = "rational")]
+pub use rational::evaluate_rational;
+#[cfg(feature = "rational")]
+pub use num_rational::Rational64;
+
+
+/**********************************************************************
+ * LIBRARY API
+ */
+
+/// Everything that can go wrong while evaluating an input string.
+#[derive(Debug)]
+pub enum CalcError {
+  Lex(LexError),
+  Parse(ParseError),
+  Eval(EvalError)
+}
+
+impl CalcError {
+  /// Where in the input the error was found.  Evaluation errors belong
+  /// to a whole statement rather than a position, so they have none.
+  pub fn span(&self) -> Option<Span> {
+    match *self {
+      CalcError::Lex(ref e) => e.span(),
+      CalcError::Parse(ref e) => e.span(),
+      CalcError::Eval(_) => None
+    }
+  }
+}
+
+impl fmt::Display for CalcError {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CalcError::Lex(ref e) => write!(f, "{}", e),
+      CalcError::Parse(ref e) => write!(f, "{}", e),
+      CalcError::Eval(ref e) => write!(f, "{}", e)
+    }
+  }
+}
+
+impl Error for CalcError {}
+
+impl From<LexError> for CalcError {
+  fn from(e : LexError) -> CalcError {
+    CalcError::Lex(e)
+  }
+}
+
+impl From<ParseError> for CalcError {
+  fn from(e : ParseError) -> CalcError {
+    match e {
+      ParseError::Lex(e) => CalcError::Lex(e),
+      e => CalcError::Parse(e)
+    }
+  }
+}
+
+impl From<EvalError> for CalcError {
+  fn from(e : EvalError) -> CalcError {
+    CalcError::Eval(e)
+  }
+}
+
+/// Tokenizes and parses a single statement without evaluating it.  A
+/// `;` after the statement is optional, but anything else after it is
+/// an error, so use `Parser::statements` to parse more than one.
+pub fn parse(input : &str) -> Result<AST, ParseError> {
+  let mut lexer = Lexer::from_string(input);
+  let mut parser = Parser::new(&mut lexer)?;
+  let a = parser.program()?;
+  parser.finish()?;
+  Ok(a)
+}
+
+/// Tokenizes and parses all the statements of the input, which may span
+/// lines, without evaluating them.  Each must be followed by a `;` or
+/// the end of input, and anything else after a complete statement is a
+/// `TrailingTokens` error, so `1 + 2 ) 3` is rejected at the `)`.
+pub fn checked_parse_all(input : &str) -> Result<Vec<AST>, ParseError> {
+  let mut lexer = Lexer::from_string(input);
+  let mut stmts = Vec::new();
+  loop {
+    let mut parser = Parser::new(&mut lexer)?;
+    if parser.at_end() {
+      return Ok(stmts);
+    }
+    match parser.program() {
+      Ok(a) => stmts.push(a),
+      Err(ParseError::UnexpectedToken { expected : Token::Semi, found, span }) =>
+        return Err(ParseError::TrailingTokens(found, span)),
+      Err(e) => return Err(e)
+    }
+  }
+}
+
+/// Like `checked_parse_all`, but a statement that fails to parse is
+/// skipped up to its `;`, and parsing goes on with the next one, so each
+/// statement of the input has a result here in order.
+pub fn parse_recovering(input : &str) -> Vec<Result<AST, ParseError>> {
+  let mut lexer = Lexer::from_string(input);
+  let mut stmts = Vec::new();
+  loop {
+    let statement = match Parser::new(&mut lexer) {
+      Ok(ref parser) if parser.at_end() => return stmts,
+      Ok(mut parser) => parser.program(),
+      Err(e) => Err(e)
+    };
+    let statement = statement.map_err(|e| match e {
+      ParseError::UnexpectedToken { expected : Token::Semi, found, span } =>
+        ParseError::TrailingTokens(found, span),
+      e => e
+    });
+    if let Err(ref e) = statement {
+      skip_statement(&mut lexer, e);
+    }
+    stmts.push(statement);
+  }
+}
+
+// Tokenizes, parses, and evaluates a single statement as an N in env
+fn eval_as<N : Number>(input : &str, env : &mut HashMap<String, N>) -> Result<N, CalcError> {
+  let mut lexer = Lexer::from_string(input);
+  let mut parser = Parser::new(&mut lexer)?;
+  Ok(evaluate_iterative(&parser.program()?, env, &EvalConfig::default())?)
+}
+
+/// Tokenizes, parses, and evaluates a single statement in a fresh
+/// environment.
+pub fn eval(input : &str) -> Result<i32, CalcError> {
+  eval_as(input, &mut HashMap::new())
+}
+
+/// Like `eval`, but variables start out with the values in `env`.  The
+/// statement works on a copy, so an assignment in it is not seen in
+/// `env` afterwards.
+pub fn eval_with(input : &str, env : &HashMap<String, i32>) -> Result<i32, CalcError> {
+  eval_as(input, &mut env.clone())
+}
+
+/// Same as `eval`.
+pub fn eval_i32(input : &str) -> Result<i32, CalcError> {
+  eval_as(input, &mut HashMap::new())
+}
+
+/// Like `eval`, but in 64-bit integers.
+pub fn eval_i64(input : &str) -> Result<i64, CalcError> {
+  eval_as(input, &mut HashMap::new())
+}
+
+/// Like `eval`, but evaluates in floating point.
+pub fn eval_f64(input : &str) -> Result<f64, CalcError> {
+  eval_as(input, &mut HashMap::new())
+}
+
+/// The statements of a reader, each evaluated as it is read, in one
+/// environment so that variables carry over from one to the next.  An
+/// error in one statement is yielded in its place, and iteration goes
+/// on with the next, until the input runs out.
+pub struct Statements {
+  lexer : Lexer,
+  env : HashMap<String, i32>,
+  config : EvalConfig,
+  // Set once nothing more can be read
+  done : bool
+}
+
+impl Statements {
+  pub fn new<R : BufRead + 'static>(reader : R) -> Statements {
+    Statements {
+      lexer : Lexer::from_reader(reader),
+      env : HashMap::new(),
+      config : EvalConfig::default(),
+      done : false
+    }
+  }
+}
+
+impl Iterator for Statements {
+  type Item = Result<i32, CalcError>;
+
+  fn next(&mut self) -> Option<Result<i32, CalcError>> {
+    if self.done {
+      return None;
+    }
+    // Running out of input between statements is the normal way out
+    let statement = match Parser::new(&mut self.lexer) {
+      Ok(ref parser) if parser.at_end() => { self.done = true;
+                                             return None },
+      Ok(mut parser) => parser.program(),
+      Err(e) => Err(e)
+    };
+    match statement {
+      Ok(a) => Some(evaluate_iterative(&a, &mut self.env, &self.config).map_err(CalcError::from)),
+      Err(e) => { match e {
+                    ParseError::Lex(LexError::Read(_)) => self.done = true,
+                    _ => skip_statement(&mut self.lexer, &e)
+                  }
+                  Some(Err(e.into())) }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::io;
+
+  use super::*;
+
+  #[test]
+  fn eval_evaluates_one_statement() {
+    assert_eq!(eval("1 + 2 * 3").ok(), Some(7));
+    assert_eq!(eval("(1 + 2) * 3").ok(), Some(9));
+    assert_eq!(eval("  10 - 4 - 3 ;").ok(), Some(3));
+    assert_eq!(eval("8 / 2 / 2").ok(), Some(2));
+    assert_eq!(eval("x = 6 * 7").ok(), Some(42));
+  }
+
+  #[test]
+  fn each_stage_has_its_own_error() {
+    let at = |col| Span { line : 1, col };
+    match eval("1 + $") {
+      Err(CalcError::Lex(LexError::UnexpectedChar { found : '$', span })) => assert_eq!(span, at(5)),
+      e => panic!("{:?}", e)
+    }
+    match eval("(1 + 2") {
+      Err(CalcError::Parse(ParseError::UnclosedParen(span))) => assert_eq!(span, at(1)),
+      e => panic!("{:?}", e)
+    }
+    assert!(matches!(eval("1 +"), Err(CalcError::Parse(ParseError::UnexpectedEof))));
+    assert!(matches!(eval("1 / 0"), Err(CalcError::Eval(EvalError::DivideByZero))));
+    assert!(matches!(eval("y"), Err(CalcError::Eval(EvalError::UndefinedVar(ref name))) if name == "y"));
+    assert!(matches!(eval_f64("1 / 0").ok(), Some(n) if n.is_infinite()));
+  }
+
+  #[test]
+  fn parse_errors_wrap_lex_errors() {
+    let e = LexError::UnexpectedChar { found : '$', span : Span { line : 2, col : 3 } };
+    assert_eq!(parse("1 +\n  $"), Err(ParseError::Lex(e.clone())));
+    assert_eq!(checked_parse_all("1;\n  $"), Err(ParseError::Lex(e.clone())));
+    assert_eq!(checked_parse_all("1; 2 3"),
+               Err(ParseError::TrailingTokens(Token::Num("3".to_owned()), Span { line : 1, col : 6 })));
+    assert_eq!(checked_parse_all("1; (2"), Err(ParseError::UnclosedParen(Span { line : 1, col : 4 })));
+    // Converting one takes the lex error back out
+    assert!(matches!(CalcError::from(ParseError::Lex(e.clone())), CalcError::Lex(ref x) if *x == e));
+    assert!(matches!(CalcError::from(ParseError::UnexpectedEof), CalcError::Parse(ParseError::UnexpectedEof)));
+  }
+
+  #[test]
+  fn errors_print_and_locate_as_their_stage_does() {
+    let e = eval("2 * @").unwrap_err();
+    assert_eq!(e.to_string(), LexError::UnexpectedChar { found : '@', span : Span { line : 1, col : 5 } }.to_string());
+    assert_eq!(e.span(), Some(Span { line : 1, col : 5 }));
+    let e = eval("1 / 0").unwrap_err();
+    assert_eq!(e.to_string(), "division by zero");
+    assert_eq!(e.span(), None);
+  }
+
+  #[test]
+  fn bare_prefix_is_a_lex_error() {
+    assert!(matches!(eval("0x"), Err(CalcError::Lex(LexError::InvalidLiteral { .. }))));
+    assert_eq!(parse("0b"), Err(ParseError::Lex(LexError::InvalidLiteral {
+      literal : "0b".to_owned(),
+      span : Span { line : 1, col : 1 }
+    })));
+  }
+
+  // Evaluating these may fail, but must never panic or overflow the
+  // stack, in any number type
+  fn survives(input : &str) {
+    let _ = eval(input);
+    let _ = eval_i64(input);
+    let _ = eval_f64(input);
+    let _ = parse_recovering(input);
+  }
+
+  #[test]
+  fn adversarial_input_does_not_panic() {
+    let deep = [
+      format!("{}1{}", "(".repeat(20_000), ")".repeat(20_000)),
+      "(".repeat(20_000),
+      format!("{}1{}", "|".repeat(10_000), "|".repeat(10_000)),
+      format!("{}1", "-".repeat(20_000)),
+      format!("{}1", "abs(".repeat(10_000)),
+      vec!["2"; 20_000].join("^"),
+      vec!["1"; 20_000].join("+"),
+      vec!["x"; 20_000].join(" "),
+      format!("3{}", "!".repeat(20_000)),
+      format!("1{}", "%".repeat(20_000)),
+      "1?".repeat(10_000),
+      "9".repeat(20_000),
+      format!("0x{}", "f".repeat(10_000)),
+      format!("0.{}e{}", "9".repeat(1000), "9".repeat(1000))
+    ];
+    for input in deep.iter() {
+      survives(input);
+    }
+    let edges = [
+      "-2147483648 / -1", "-2147483648 % -1", "abs(-2147483648)", "-(-2147483648)",
+      "-9223372036854775808 / -1", "-9223372036854775808 % -1", "abs(-9223372036854775808)",
+      "9223372036854775807 + 1", "-9223372036854775808 - 1", "(-9223372036854775808)^-1",
+      "2^63", "2^-1", "0^-1", "pow(10, 20)", "lcm(2147483647, 2147483646)", "gcd(-2147483648, 0)",
+      "13!", "(-1)!", "1 / 0", "1 % 0", "sqrt(-1)", "0x", "0b2", "1.2.3", ".", "1e", "1e999",
+      ";", ";;", "=", "x = ", "ans = 1", "()", "(,)", "f(", "|", "||", "?:", "1 ? 2", "%", "!"
+    ];
+    for input in edges.iter() {
+      survives(input);
+    }
+  }
+
+  #[test]
+  fn random_input_does_not_panic() {
+    let alphabet : Vec<char> = "0123456789.xyabsqrt()|!%^*/+-=?:;, \n\u{301}é∞".chars().collect();
+    // A fixed xorshift, so that a failure can be reproduced
+    let mut state : u64 = 0x2545_f491_4f6c_dd1d;
+    for _ in 0..2000 {
+      let mut input = String::new();
+      for _ in 0..(state % 40) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        input.push(alphabet[(state % alphabet.len() as u64) as usize]);
+      }
+      survives(&input);
+    }
+  }
+
+  #[test]
+  fn evaluates_in_each_number_type() {
+    assert_eq!(eval_i32("7 / 2").ok(), Some(3));
+    assert_eq!(eval_i64("7 / 2").ok(), Some(3));
+    assert_eq!(eval_f64("7 / 2").ok(), Some(3.5));
+    assert_eq!(eval_i32("-7 / 2").ok(), Some(-3));
+    assert_eq!(eval_f64("-7 / 2").ok(), Some(-3.5));
+    // Each type has its own range
+    assert!(eval_i32("3000000000").is_err());
+    assert_eq!(eval_i64("3000000000 * 2").ok(), Some(6000000000));
+    assert_eq!(eval_f64("3000000000 * 2").ok(), Some(6e9));
+    // The same tree, evaluated generically
+    let a = parse("7 / 2").unwrap();
+    let config = EvalConfig::default();
+    assert_eq!(evaluate::<i64>(&a, &mut HashMap::new(), &config), Ok(3));
+    assert_eq!(evaluate::<f64>(&a, &mut HashMap::new(), &config), Ok(3.5));
+  }
+
+  // The result of each statement in the input, or its error as printed
+  fn results(input : &str) -> Vec<Result<i32, String>> {
+    Statements::new(io::Cursor::new(input.to_owned())).map(|r| r.map_err(|e| e.to_string())).collect()
+  }
+
+  #[test]
+  fn last_statement_needs_no_semicolon() {
+    assert_eq!(results("2 + 3"), vec![Ok(5)]);
+    assert_eq!(results("2 + 3;"), vec![Ok(5)]);
+    assert_eq!(results("2+3; 4+5"), vec![Ok(5), Ok(9)]);
+    assert_eq!(results("2+3; 4+5;"), vec![Ok(5), Ok(9)]);
+    assert_eq!(eval("2 + 3").ok(), Some(5));
+  }
+
+  #[test]
+  fn statements_still_need_separating() {
+    assert_eq!(results("1 2"), vec![Err("expected ';', found '2'".to_owned())]);
+    assert_eq!(results("2+3; 4+5 6"), vec![Ok(5), Err("expected ';', found '6'".to_owned())]);
+    assert!(eval("1 2").is_err());
+  }
+
+  #[test]
+  fn parse_returns_the_tree_of_one_statement() {
+    let n = |x : &str| Box::new(AST::Num(x.to_owned()));
+    let tree = AST::Plus(n("1"), Box::new(AST::Times(n("2"), n("3"))));
+    assert_eq!(parse("1+2*3"), Ok(tree.clone()));
+    assert_eq!(parse("1 + 2 * 3;"), Ok(tree));
+    // Nothing is evaluated, so this is no error yet
+    assert_eq!(parse("x = 1/0"),
+               Ok(AST::Assign("x".to_owned(), Box::new(AST::Divide(n("1"), n("0"))))));
+    assert_eq!(parse("1; 2"), Err(ParseError::TrailingTokens(Token::Num("2".to_owned()), Span { line : 1, col : 4 })));
+    assert_eq!(parse("1;;"), Err(ParseError::TrailingTokens(Token::Semi, Span { line : 1, col : 3 })));
+    assert_eq!(parse(""), Err(ParseError::UnexpectedEof));
+  }
+
+  #[test]
+  fn statements_go_on_past_errors() {
+    assert_eq!(results("x = 2; 1 / 0; y; x * 3;\n1 + ; x + 1\n"),
+               vec![Ok(2), Err("division by zero".to_owned()), Err("undefined variable 'y'".to_owned()),
+                    Ok(6), Err("expected an operand, found ';'".to_owned()), Ok(3)]);
+    assert_eq!(results(""), vec![]);
+    assert_eq!(results("  \n# nothing\n"), vec![]);
+    let mut statements = Statements::new(io::Cursor::new("1\n".to_owned()));
+    assert_eq!(statements.next().map(|r| r.ok()), Some(Some(1)));
+    assert!(statements.next().is_none());
+    assert!(statements.next().is_none());
+  }
+
+  #[test]
+  fn checked_parse_all_rejects_trailing_tokens() {
+    let n = |x : &str| AST::Num(x.to_owned());
+    assert_eq!(checked_parse_all("1; 2;\n3"), Ok(vec![n("1"), n("2"), n("3")]));
+    assert_eq!(checked_parse_all("1 + 2"), Ok(vec![AST::Plus(Box::new(n("1")), Box::new(n("2")))]));
+    assert_eq!(checked_parse_all(""), Ok(vec![]));
+    assert_eq!(checked_parse_all("1 + 2 ) 3"),
+               Err(ParseError::TrailingTokens(Token::RParen, Span { line : 1, col : 7 })));
+    assert_eq!(checked_parse_all("4;\n5 6"),
+               Err(ParseError::TrailingTokens(Token::Num("6".to_owned()), Span { line : 2, col : 3 })));
+    // Other errors are reported as they are
+    assert_eq!(checked_parse_all("1 + ;"), Err(ParseError::BadFactor(Token::Semi, Span { line : 1, col : 5 })));
+  }
+
+  #[test]
+  fn errors_work_with_question_mark() {
+    fn run(input : &str) -> Result<i32, Box<dyn Error>> {
+      let a = parse(input)?;
+      let n : i32 = evaluate_iterative(&a, &mut HashMap::new(), &EvalConfig::default())?;
+      Ok(n + eval(input)?)
+    }
+    assert_eq!(run("1 + 2").ok(), Some(6));
+    assert_eq!(run("1 +").unwrap_err().to_string(), "unexpected end of input");
+    assert_eq!(run("1 / 0").unwrap_err().to_string(), "division by zero");
+    let e : Box<dyn Error> = Box::new(eval("$").unwrap_err());
+    assert_eq!(e.to_string(), "unexpected character '$'");
+  }
+
+  #[test]
+  fn eval_with_reads_the_host_environment() {
+    let mut env = HashMap::new();
+    env.insert("x".to_owned(), 5);
+    assert_eq!(eval_with("x * x + 1", &env).ok(), Some(26));
+    assert!(matches!(eval_with("x + y", &env),
+                     Err(CalcError::Eval(EvalError::UndefinedVar(ref name))) if name == "y"));
+    // Assignments change only the statement's copy
+    assert_eq!(eval_with("x = x * 2", &env).ok(), Some(10));
+    assert_eq!(env.get("x"), Some(&5));
+    assert_eq!(eval_with("y = 3", &env).ok(), Some(3));
+    assert_eq!(env.get("y"), None);
+    assert!(eval_with("x", &HashMap::new()).is_err());
+  }
+
+  #[test]
+  fn parse_recovering_skips_to_the_next_statement() {
+    let n = |x : &str| Box::new(AST::Num(x.to_owned()));
+    let at = |line, col| Span { line, col };
+    assert_eq!(parse_recovering("1+; 3*4;"),
+               vec![Err(ParseError::BadFactor(Token::Semi, at(1, 3))), Ok(AST::Times(n("3"), n("4")))]);
+    assert_eq!(parse_recovering("1 ) 2; 3"),
+               vec![Err(ParseError::TrailingTokens(Token::RParen, at(1, 3))), Ok(*n("3"))]);
+    assert_eq!(parse_recovering("$ 1; 2"),
+               vec![Err(ParseError::Lex(LexError::UnexpectedChar { found : '$', span : at(1, 1) })), Ok(*n("2"))]);
+    assert_eq!(parse_recovering("1\n+\n;4"), vec![Err(ParseError::BadFactor(Token::Semi, at(3, 1))), Ok(*n("4"))]);
+    assert_eq!(parse_recovering("1; 2"), vec![Ok(*n("1")), Ok(*n("2"))]);
+    assert_eq!(parse_recovering(""), vec![]);
+  }
+
+  #[test]
+  fn the_end_of_input_ends_the_statements() {
+    assert_eq!(checked_parse_all(""), Ok(vec![]));
+    assert_eq!(checked_parse_all("  # nothing\n"), Ok(vec![]));
+    assert_eq!(checked_parse_all("1;").map(|v| v.len()), Ok(1));
+    assert_eq!(checked_parse_all("1; 2\n").map(|v| v.len()), Ok(2));
+    assert!(parse_recovering("").is_empty());
+    assert_eq!(parse(""), Err(ParseError::UnexpectedEof));
+  }
+
+  #[test]
+  fn float_literals_out_of_range_overflow_like_integers() {
+    assert!(matches!(eval("3000000000.0"), Err(CalcError::Eval(EvalError::Overflow))));
+    assert!(matches!(eval("3000000000"), Err(CalcError::Eval(EvalError::Overflow))));
+    assert_eq!(eval("2147483647.5").ok(), Some(2147483647));
+  }
+}
@@ -1,233 +1,314 @@
+extern crate calc;
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
 use std::io;
+use std::io::BufReader;
 use std::io::prelude::*;
+use std::io::IsTerminal;
+use std::process;
+use std::time::{Duration, Instant};
+
+use calc::{Base, CalcError, EvalConfig, Lexer, LexError, ParseError, Parser, ReplConfig};
+use calc::{DEFAULT_PRECISION, evaluate_f64, evaluate_iterative, format_float, format_result, format_timing};
+use calc::{render_error, run_repl_with, skip_statement};
 
 
 /**********************************************************************
- * LEXER
+ * COMMAND LINE
  */
 
-#[derive(Clone,PartialEq,Eq,Debug)]
-enum Token {
-  Num(String),
-  Plus,
-  Minus,
-  Times,
-  Divide,
-  LParen,
-  RParen,
-  Semi
-}
-
-struct Lexer {
-  buffer: String,
-  offset: usize
-}
-
-impl Lexer {
-  fn new() -> Lexer {
-    let mut buffer = String::new();
-    let offset = 0;
-    io::stdin().read_line(&mut buffer).unwrap_or_else(|_| {
-      panic!("Could not initialize lexer:  unable to read line");
-    });
-
-    Lexer {
-      buffer: buffer,
-      offset: offset
-    }
-  }
+const USAGE : &str = "usage: calc [--expr <expression> | --file <path> | --batch | --interactive] [--base dec|hex|bin|oct] [--precision <decimals>] [--json] [--time] [--prompt <prompt>]";
+
+#[derive(Default)]
+struct Options {
+  expr : Option<String>,
+  file : Option<String>,
+  base : Base,
+  // Decimals to print float results with
+  precision : Option<usize>,
+  // Print each result or error as a line of JSON
+  json : bool,
+  // Print how long each statement took to parse and evaluate
+  time : bool,
+  // Read statements from all of stdin at once, or prompt for them,
+  // whether or not stdin is a terminal
+  batch : bool,
+  interactive : bool,
+  // What the REPL prompts with, if not its default
+  prompt : Option<String>
+}
 
-  fn advance(&mut self) {
-    if self.offset >= self.buffer.len() - 1 {
-      self.buffer = String::new();
-      io::stdin().read_line(&mut self.buffer).unwrap_or_else(|_| {
-        panic!("Could not advance lexer:  unable to read line");
-      });
-      self.offset = 0;
-    } else {
-      self.offset += self.current().len_utf8();
-    }
-  }
+// How long a statement took to parse and to evaluate, when --time asks
+type Timing = Option<(Duration, Duration)>;
 
-  fn current(&self) -> char {
-    self.buffer[self.offset..].chars().next()
-      .expect("Tried to get a nonsensical character")
+fn parse_base(s : &str) -> Result<Base, String> {
+  match s {
+    "dec" => Ok(Base::Dec),
+    "hex" => Ok(Base::Hex),
+    "bin" => Ok(Base::Bin),
+    "oct" => Ok(Base::Oct),
+    x => Err(format!("unrecognized base: {}", x))
   }
+}
 
-  fn get_token(&mut self) -> Token {
-    let mut t = String::new();
-    let mut c = self.current();
-
-    while c.is_whitespace() {
-      self.advance();
-      c = self.current();
-    }
-
-    while c.is_digit(10) {
-      t.push(c);
-      self.advance();
-      c = self.current();
-    }
-
-    if t.len() > 0 {
-      return Token::Num(t);
+fn parse_args(args : &[String]) -> Result<Options, String> {
+  let mut opts = Options::default();
+  let mut args = args.iter();
+
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      "--expr" => opts.expr = Some(args.next().ok_or("--expr needs a value")?.clone()),
+      "--file" => opts.file = Some(args.next().ok_or("--file needs a value")?.clone()),
+      "--base" => opts.base = parse_base(args.next().ok_or("--base needs a value")?)?,
+      "--precision" => {
+        let n = args.next().ok_or("--precision needs a value")?;
+        opts.precision = Some(n.parse().map_err(|_| format!("invalid precision: {}", n))?);
+      },
+      "--json" => opts.json = true,
+      "--time" => opts.time = true,
+      "--batch" => opts.batch = true,
+      "--interactive" => opts.interactive = true,
+      "--prompt" => opts.prompt = Some(args.next().ok_or("--prompt needs a value")?.clone()),
+      x => return Err(format!("unrecognized argument: {}", x))
     }
+  }
 
-    self.advance();
+  if opts.expr.is_some() && opts.file.is_some() {
+    return Err("--expr and --file cannot be used together".to_owned());
+  }
+  if opts.batch && opts.interactive {
+    return Err("--batch and --interactive cannot be used together".to_owned());
+  }
+  Ok(opts)
+}
 
+// A string as a JSON string literal
+fn json_string(s : &str) -> String {
+  let mut out = String::from("\"");
+  for c in s.chars() {
     match c {
-      '+' => Token::Plus,
-      '-' => Token::Minus,
-      '*' => Token::Times,
-      '/' => Token::Divide,
-      '(' => Token::LParen,
-      ')' => Token::RParen,
-      ';' => Token::Semi,
-      x => panic!("unrecognized character: {}", x)
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+      c => out.push(c)
     }
   }
+  out.push('"');
+  out
 }
 
-
-
-/**********************************************************************
- * PARSER
- */
-
-// Abstract syntax tree
-#[derive(Clone,Debug)]
-enum AST {
-  Num(i32),
-  Plus(Box<AST>, Box<AST>),
-  Minus(Box<AST>, Box<AST>),
-  Times(Box<AST>, Box<AST>),
-  Divide(Box<AST>, Box<AST>)
+// Print a result, shown as text or as a JSON value, with its timing if
+// any.  JSON gives the timing in microseconds as fields of its own.
+fn print_result(shown : &str, json : &str, timing : Timing, opts : &Options) {
+  match (opts.json, timing) {
+    (true, Some((parse, eval))) =>
+      println!("{{\"result\": {}, \"parse_us\": {}, \"eval_us\": {}}}",
+               json, parse.as_micros(), eval.as_micros()),
+    (true, None) => println!("{{\"result\": {}}}", json),
+    (false, Some((parse, eval))) => println!("{} {}", shown, format_timing(parse, eval)),
+    (false, None) => println!("{}", shown)
+  }
 }
 
-struct Parser<'a> {
-  tok : Token,
-  lex : &'a mut Lexer
+// Print an integer result in the chosen base, or for JSON in decimal
+fn print_int(n : i32, timing : Timing, opts : &Options) {
+  print_result(&format_result(n, opts.base), &n.to_string(), timing, opts);
 }
 
-impl<'a> Parser<'a> {
-  fn new(lex : &'a mut Lexer) -> Parser {
-    Parser {
-      tok : lex.get_token(),
-      lex : lex
-    }
-  }
-
-  fn get_token(&mut self) {
-    self.tok = self.lex.get_token();
-  }
+// Print a float result rounded to the chosen precision.  JSON has no
+// infinities or NaN, so they are a null result there.
+fn print_float(n : f64, timing : Timing, opts : &Options) {
+  let s = format_float(n, opts.precision.unwrap_or(DEFAULT_PRECISION));
+  let json = if n.is_finite() { s.as_str() } else { "null" };
+  print_result(&s, json, timing, opts);
+}
 
-  fn eat(&mut self, t : Token) {
-    if self.tok == t {
-      self.get_token();
-    } else {
-      panic!("Syntax error: expected {:?}, found {:?}", t, self.tok);
-    }
+// An error as a JSON object, with where it was found if known
+fn json_error(msg : &str, line : Option<usize>, col : Option<usize>) -> String {
+  let mut s = format!("{{\"error\": {}", json_string(msg));
+  if let Some(line) = line {
+    s.push_str(&format!(", \"line\": {}", line));
   }
-  
-  /********************************************************************
-   * GRAMMAR PRODUCTIONS
-   * x_ productions are hacks to make the grammar right recursive
-   * and therefore suitable for recursive descent parsing
-   */
-  // Starting production. Use this as entry into the parser.
-  fn program(&mut self) -> AST {
-    let a = self.exp();
-    self.semi(a)
+  if let Some(col) = col {
+    s.push_str(&format!(", \"column\": {}", col));
   }
+  s.push('}');
+  s
+}
 
-  fn exp(&mut self) -> AST {
-    let t = self.term();
-    self.exp_(t)
+// Print an error in a file, which JSON puts on stdout with the rest
+fn print_file_error(msg : &str, line : usize, col : Option<usize>, opts : &Options) {
+  if opts.json {
+    println!("{}", json_error(msg, Some(line), col));
+  } else {
+    eprintln!("line {}: Error: {}", line, msg);
   }
+}
 
-  fn exp_(&mut self, t : AST) -> AST {
-    match self.tok {
-      Token::Plus  => { self.eat(Token::Plus);
-                        let s = self.term();
-                        let rc = AST::Plus(Box::new(t), Box::new(s));
-                        self.exp_(rc) },
-      Token::Minus => { self.eat(Token::Minus);
-                        let s = self.term();
-                        let rc = AST::Minus(Box::new(t), Box::new(s));
-                        self.exp_(rc) },
-      _ => { t }
+// Evaluate each statement in the file, as run_statements does.
+// Returns the process exit code.
+fn run_file(path : &str, opts : &Options) -> i32 {
+  let file = match File::open(path) {
+    Ok(file) => file,
+    Err(e) => {
+      eprintln!("Could not open {}:  {}", path, e);
+      return 1;
     }
-  }
-
-  fn term(&mut self) -> AST {
-    let f = self.factor();
-    self.term_(f)
-  }
+  };
+  run_statements(Lexer::from_reader(BufReader::new(file)), opts)
+}
 
-  fn term_(&mut self, f : AST) -> AST {
-    match self.tok {
-      Token::Times  => { self.eat(Token::Times);
-                         let g = self.factor();
-                         let rc = AST::Times(Box::new(f), Box::new(g));
-                         self.term_(rc) },
-      Token::Divide => { self.eat(Token::Divide);
-                         let g = self.factor();
-                         let rc = AST::Divide(Box::new(f), Box::new(g));
-                         self.term_(rc) },
-      _ => { f }
-    }
+// Read all of stdin before evaluating any of it, as run_statements
+// does, so piped input is not prompted for line by line.  Returns the
+// process exit code.
+fn run_batch(opts : &Options) -> i32 {
+  let mut input = String::new();
+  if let Err(e) = io::stdin().read_to_string(&mut input) {
+    eprintln!("Could not read stdin:  {}", e);
+    return 1;
   }
+  run_statements(Lexer::from_string(&input), opts)
+}
 
-  fn factor(&mut self) -> AST {
-    let tok = self.tok.clone();  // Make the borrow checker stop complaining
-    match tok {
-      Token::Num(ref x) => { self.get_token();
-                             AST::Num(x.parse::<i32>().unwrap()) } ,
-      Token::LParen => { self.eat(Token::LParen);
-                         let rc = self.exp();
-                         self.eat(Token::RParen);
-                         rc } ,
-      _ => { panic!("Syntax error: expected number or parenthesis") }
+// Evaluate each statement in one environment and print its result, as
+// the REPL would.  An error is reported with its line, or for an
+// evaluation error the line the statement ends on, and evaluation goes
+// on with the next statement.  Returns the process exit code.
+fn run_statements(mut lexer : Lexer, opts : &Options) -> i32 {
+  let mut env = HashMap::new();
+  let config = EvalConfig::default();
+  let mut status = 0;
+
+  loop {
+    // Running out of input between statements is the normal way out
+    let start = Instant::now();
+    let statement = match Parser::new(&mut lexer) {
+      Ok(ref parser) if parser.at_end() => return status,
+      Ok(mut parser) => parser.program(),
+      Err(e) => Err(e)
+    };
+    let parse_time = start.elapsed();
+    let line = lexer.span().line;
+    let start = Instant::now();
+    let timing = || Some((parse_time, start.elapsed())).filter(|_| opts.time);
+    match statement {
+      Ok(ref a) if a.has_float() => match evaluate_f64(a, &mut env, &config) {
+        Ok(n) => print_float(n, timing(), opts),
+        Err(e) => { print_file_error(&e.to_string(), line, None, opts);
+                    status = 1; }
+      },
+      Ok(a) => match evaluate_iterative(&a, &mut env, &config) {
+        Ok(n) => print_int(n, timing(), opts),
+        Err(e) => { print_file_error(&e.to_string(), line, None, opts);
+                    status = 1; }
+      },
+      Err(e) => { let line = e.span().map_or(line, |span| span.line);
+                  print_file_error(&e.to_string(), line, e.span().map(|span| span.col), opts);
+                  if let ParseError::Lex(LexError::Read(_)) = e {
+                    return 1;
+                  }
+                  skip_statement(&mut lexer, &e);
+                  status = 1; }
     }
   }
+}
 
-  // Terminal production.  Ends parsing.
-  fn semi(&mut self, a : AST) -> AST {
-    a
-  }
+// A result, in whichever type its statement was evaluated in
+enum Value {
+  Int(i32),
+  Float(f64)
 }
 
+// Parse and evaluate a single statement, in floating point if it needs
+// it as the other modes do, timing each apart
+fn eval_timed(input : &str) -> Result<(Value, Duration, Duration), CalcError> {
+  let start = Instant::now();
+  let mut lexer = Lexer::from_string(input);
+  let a = Parser::new(&mut lexer)?.program()?;
+  let parse_time = start.elapsed();
+  let start = Instant::now();
+  let mut env = HashMap::new();
+  let config = EvalConfig::default();
+  let value = if a.has_float() {
+    Value::Float(evaluate_f64(&a, &mut env, &config)?)
+  } else {
+    Value::Int(evaluate_iterative(&a, &mut env, &config)?)
+  };
+  Ok((value, parse_time, start.elapsed()))
+}
 
+// Run with the given arguments, returning the process exit code
+fn run(args : &[String]) -> i32 {
+  let opts = match parse_args(args) {
+    Ok(opts) => opts,
+    Err(e) => {
+      eprintln!("{}\n{}", e, USAGE);
+      return 2;
+    }
+  };
 
-/**********************************************************************
- * INTERPRETER
- */
+  if let Some(ref path) = opts.file {
+    return run_file(path, &opts);
+  }
+  if opts.expr.is_none() && (opts.batch || !opts.interactive && !io::stdin().is_terminal()) {
+    return run_batch(&opts);
+  }
 
-// Recursively evaluate the expression tree
-fn evaluate(a : AST) -> i32 {
-  match a {
-    AST::Num(x) => x,
-    AST::Plus(x, y) => evaluate(*x) + evaluate(*y),
-    AST::Minus(x, y) => evaluate(*x) - evaluate(*y),
-    AST::Times(x, y) => evaluate(*x) * evaluate(*y),
-    AST::Divide(x, y) => evaluate(*x) / evaluate(*y)
+  match opts.expr {
+    Some(ref expr) => match eval_timed(expr) {
+      Ok((value, parse, eval)) => { let timing = Some((parse, eval)).filter(|_| opts.time);
+                                    match value {
+                                      Value::Int(n) => print_int(n, timing, &opts),
+                                      Value::Float(n) => print_float(n, timing, &opts)
+                                    }
+                                    0 },
+      Err(e) => { let span = e.span();
+                  let msg = format!("Error: {}", e);
+                  if opts.json {
+                    let (line, col) = (span.map(|span| span.line), span.map(|span| span.col));
+                    println!("{}", json_error(&e.to_string(), line, col));
+                  } else if let Some(span) = span {
+                    eprintln!("{}", render_error(expr, span, &msg));
+                  } else {
+                    eprintln!("{}", msg);
+                  }
+                  1 }
+    },
+    // Results in the REPL are for reading, between prompts
+    None if opts.json => {
+      eprintln!("--json cannot be used in the REPL, only with --expr, --file, or --batch\n{}", USAGE);
+      2
+    },
+    None => {
+      println!("Enter an arithmetic expression using integers, with a ; between two on one line");
+
+      let mut config = ReplConfig {
+        time : opts.time,
+        base : opts.base,
+        precision : opts.precision.unwrap_or(DEFAULT_PRECISION),
+        ..ReplConfig::default()
+      };
+      if let Some(ref prompt) = opts.prompt {
+        config.prompt = prompt.clone();
+      }
+      match run_repl_with(io::stdin().lock(), io::stdout(), &config) {
+        Ok(()) => 0,
+        Err(e) => { eprintln!("Could not run REPL:  {}", e);
+                    1 }
+      }
+    }
   }
 }
 
 
-
 /**********************************************************************
  * MAIN
  */
- 
-fn main() {
-  let mut lexer = Lexer::new();
-  let mut parser = Parser::new(&mut lexer);
 
-  println!("Enter an arithmetic expression using integers followed by a ;");
-
-  let expression = parser.program();
-
-  //println!("{:?}", expression);
-  println!("{}", evaluate(expression));
+fn main() {
+  let args : Vec<String> = env::args().skip(1).collect();
+  process::exit(run(&args));
 }
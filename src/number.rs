@@ -0,0 +1,674 @@
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use interpreter::{AngleMode, EvalConfig, EvalError};
+
+
+/**********************************************************************
+ * NUMBERS
+ */
+
+/// A numeric type the evaluator can compute in.  The operator traits
+/// alone are not enough, since overflow and division by zero have to
+/// come back as errors rather than panics, so each operation is its own
+/// fallible method.  Bitwise operators are only defined for integers.
+pub trait Number : Clone + PartialOrd + FromStr + fmt::Display + fmt::Debug {
+  /// Converts a float literal, truncating toward zero for integer types,
+  /// which overflow if it is out of their range or not finite.
+  fn from_f64(x : f64) -> Result<Self, EvalError>;
+
+  /// 1 for true and 0 for false, as comparisons evaluate to.
+  fn from_bool(b : bool) -> Self;
+
+  fn add(self, y : Self) -> Result<Self, EvalError>;
+  fn sub(self, y : Self) -> Result<Self, EvalError>;
+  fn mul(self, y : Self) -> Result<Self, EvalError>;
+  fn div(self, y : Self) -> Result<Self, EvalError>;
+  /// Division rounding toward negative infinity.  Only integer division
+  /// rounds, so for floats this is the same as `div`.
+  fn div_floor(self, y : Self) -> Result<Self, EvalError>;
+  /// Division rounding toward positive infinity, like `div_floor`.
+  fn div_ceil(self, y : Self) -> Result<Self, EvalError>;
+  fn rem(self, y : Self) -> Result<Self, EvalError>;
+  fn pow(self, e : Self) -> Result<Self, EvalError>;
+  fn neg(self) -> Result<Self, EvalError>;
+  fn factorial(self) -> Result<Self, EvalError>;
+
+  /// `add`, clamping a result out of range to the nearest bound.  The
+  /// saturating and wrapping operations default to the checked ones,
+  /// which suits types that never overflow or have no sensible bound.
+  fn saturating_add(self, y : Self) -> Result<Self, EvalError> {
+    self.add(y)
+  }
+
+  fn saturating_sub(self, y : Self) -> Result<Self, EvalError> {
+    self.sub(y)
+  }
+
+  fn saturating_mul(self, y : Self) -> Result<Self, EvalError> {
+    self.mul(y)
+  }
+
+  fn saturating_pow(self, e : Self) -> Result<Self, EvalError> {
+    self.pow(e)
+  }
+
+  fn saturating_neg(self) -> Result<Self, EvalError> {
+    self.neg()
+  }
+
+  /// `add`, wrapping a result out of range around in two's complement.
+  fn wrapping_add(self, y : Self) -> Result<Self, EvalError> {
+    self.add(y)
+  }
+
+  fn wrapping_sub(self, y : Self) -> Result<Self, EvalError> {
+    self.sub(y)
+  }
+
+  fn wrapping_mul(self, y : Self) -> Result<Self, EvalError> {
+    self.mul(y)
+  }
+
+  fn wrapping_pow(self, e : Self) -> Result<Self, EvalError> {
+    self.pow(e)
+  }
+
+  fn wrapping_neg(self) -> Result<Self, EvalError> {
+    self.neg()
+  }
+
+  fn bitand(self, _y : Self) -> Result<Self, EvalError> {
+    Err(EvalError::DomainError)
+  }
+
+  fn bitor(self, _y : Self) -> Result<Self, EvalError> {
+    Err(EvalError::DomainError)
+  }
+
+  fn shl(self, _y : Self) -> Result<Self, EvalError> {
+    Err(EvalError::DomainError)
+  }
+
+  fn shr(self, _y : Self) -> Result<Self, EvalError> {
+    Err(EvalError::DomainError)
+  }
+
+  fn less(self, y : Self) -> Result<Self, EvalError> {
+    Ok(Self::from_bool(self < y))
+  }
+
+  fn greater(self, y : Self) -> Result<Self, EvalError> {
+    Ok(Self::from_bool(self > y))
+  }
+
+  fn less_eq(self, y : Self) -> Result<Self, EvalError> {
+    Ok(Self::from_bool(self <= y))
+  }
+
+  fn greater_eq(self, y : Self) -> Result<Self, EvalError> {
+    Ok(Self::from_bool(self >= y))
+  }
+
+  fn equal(self, y : Self) -> Result<Self, EvalError> {
+    Ok(Self::from_bool(self == y))
+  }
+
+  fn not_equal(self, y : Self) -> Result<Self, EvalError> {
+    Ok(Self::from_bool(self != y))
+  }
+
+  /// Dispatches a call to one of this type's built-in functions.
+  fn call(name : &str, args : &[Self], config : &EvalConfig) -> Result<Self, EvalError>;
+}
+
+thread_local! {
+  // The state of the xorshift generator that rand draws from, seeded
+  // from the clock the first time it is used on a thread
+  static RANDOM : Cell<u64> = Cell::new(random_state(
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64)));
+}
+
+// A generator state for a seed.  Xorshift never leaves a zero state, so
+// that one seed is moved aside.
+fn random_state(seed : u64) -> u64 {
+  match seed ^ 0x9e37_79b9_7f4a_7c15 {
+    0 => 1,
+    x => x
+  }
+}
+
+/// Starts the sequence that `rand` draws from over, so the same seed
+/// always gives the same values on this thread.
+pub fn seed_random(seed : u64) {
+  RANDOM.with(|state| state.set(random_state(seed)));
+}
+
+/// The next value of the sequence that `rand` draws from.
+pub fn next_random() -> u64 {
+  RANDOM.with(|state| {
+    let mut x = state.get();
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    state.set(x);
+    x
+  })
+}
+
+/// Built-in functions that only floats have, so a call to one needs
+/// float evaluation even if its arguments are integers.
+pub const FLOAT_FUNCTIONS : &[&str] = &["sin", "cos", "tan", "ln", "log", "exp"];
+
+// The result of an integer operation, given as checked if it did not
+// overflow.  Overflow is an error in debug and release builds alike,
+// unless the overflow-wrapping feature has the wrapped result instead.
+#[cfg(not(feature = "overflow-wrapping"))]
+fn overflowed<T, F>(checked : Option<T>, _wrapped : F) -> Result<T, EvalError>
+  where F : FnOnce() -> Result<T, EvalError> {
+  checked.ok_or(EvalError::Overflow)
+}
+
+#[cfg(feature = "overflow-wrapping")]
+fn overflowed<T, F>(checked : Option<T>, wrapped : F) -> Result<T, EvalError>
+  where F : FnOnce() -> Result<T, EvalError> {
+  match checked {
+    Some(x) => Ok(x),
+    None => wrapped()
+  }
+}
+
+// Integer arithmetic is all checked, as overflowed says.  $u is the
+// unsigned type of the same width, which holds the magnitude of any
+// value of $t.
+macro_rules! int_number {
+  ($t:ident, $u:ident) => {
+    impl Number for $t {
+      // Out of range once truncated, or not finite, is an overflow
+      // rather than saturating as `as` would
+      fn from_f64(x : f64) -> Result<$t, EvalError> {
+        let x = x.trunc();
+        if x.is_finite() && x >= $t::MIN as f64 && x < -($t::MIN as f64) {
+          Ok(x as $t)
+        } else {
+          Err(EvalError::Overflow)
+        }
+      }
+
+      fn from_bool(b : bool) -> $t {
+        b as $t
+      }
+
+      fn add(self, y : $t) -> Result<$t, EvalError> {
+        overflowed(self.checked_add(y), || Number::wrapping_add(self, y))
+      }
+
+      fn sub(self, y : $t) -> Result<$t, EvalError> {
+        overflowed(self.checked_sub(y), || Number::wrapping_sub(self, y))
+      }
+
+      fn mul(self, y : $t) -> Result<$t, EvalError> {
+        overflowed(self.checked_mul(y), || Number::wrapping_mul(self, y))
+      }
+
+      fn div(self, y : $t) -> Result<$t, EvalError> {
+        if y == 0 {
+          return Err(EvalError::DivideByZero);
+        }
+        overflowed(self.checked_div(y), || Ok(self.wrapping_div(y)))
+      }
+
+      // A truncated quotient with a remainder is one too big exactly
+      // when the signs of the operands differ
+      fn div_floor(self, y : $t) -> Result<$t, EvalError> {
+        let q = Number::div(self, y)?;
+        if self % y != 0 && (self < 0) != (y < 0) {
+          return Ok(q - 1);
+        }
+        Ok(q)
+      }
+
+      fn div_ceil(self, y : $t) -> Result<$t, EvalError> {
+        let q = Number::div(self, y)?;
+        if self % y != 0 && (self < 0) == (y < 0) {
+          return Ok(q + 1);
+        }
+        Ok(q)
+      }
+
+      fn rem(self, y : $t) -> Result<$t, EvalError> {
+        if y == 0 {
+          return Err(EvalError::DivideByZero);
+        }
+        overflowed(self.checked_rem(y), || Ok(self.wrapping_rem(y)))
+      }
+
+      fn pow(self, e : $t) -> Result<$t, EvalError> {
+        if e < 0 {
+          return Err(EvalError::NegativeExponent);
+        }
+        let checked = u32::try_from(e).ok().and_then(|e| self.checked_pow(e));
+        overflowed(checked, || Number::wrapping_pow(self, e))
+      }
+
+      fn neg(self) -> Result<$t, EvalError> {
+        overflowed(self.checked_neg(), || Number::wrapping_neg(self))
+      }
+
+      fn saturating_add(self, y : $t) -> Result<$t, EvalError> {
+        Ok(<$t>::saturating_add(self, y))
+      }
+
+      fn saturating_sub(self, y : $t) -> Result<$t, EvalError> {
+        Ok(<$t>::saturating_sub(self, y))
+      }
+
+      fn saturating_mul(self, y : $t) -> Result<$t, EvalError> {
+        Ok(<$t>::saturating_mul(self, y))
+      }
+
+      // Past u32::MAX only the parity of the exponent can still matter,
+      // for a base of -1
+      fn saturating_pow(self, e : $t) -> Result<$t, EvalError> {
+        if e < 0 {
+          return Err(EvalError::NegativeExponent);
+        }
+        let e = u32::try_from(e).unwrap_or(u32::MAX - (e % 2 == 0) as u32);
+        Ok(<$t>::saturating_pow(self, e))
+      }
+
+      fn saturating_neg(self) -> Result<$t, EvalError> {
+        Ok(<$t>::saturating_neg(self))
+      }
+
+      fn wrapping_add(self, y : $t) -> Result<$t, EvalError> {
+        Ok(<$t>::wrapping_add(self, y))
+      }
+
+      fn wrapping_sub(self, y : $t) -> Result<$t, EvalError> {
+        Ok(<$t>::wrapping_sub(self, y))
+      }
+
+      fn wrapping_mul(self, y : $t) -> Result<$t, EvalError> {
+        Ok(<$t>::wrapping_mul(self, y))
+      }
+
+      // By repeated squaring, since the exponent may not fit in a u32
+      fn wrapping_pow(self, mut e : $t) -> Result<$t, EvalError> {
+        if e < 0 {
+          return Err(EvalError::NegativeExponent);
+        }
+        let (mut b, mut acc) = (self, 1 as $t);
+        while e > 0 {
+          if e & 1 == 1 {
+            acc = acc.wrapping_mul(b);
+          }
+          b = b.wrapping_mul(b);
+          e >>= 1;
+        }
+        Ok(acc)
+      }
+
+      fn wrapping_neg(self) -> Result<$t, EvalError> {
+        Ok(<$t>::wrapping_neg(self))
+      }
+
+      fn factorial(self) -> Result<$t, EvalError> {
+        if self < 0 {
+          return Err(EvalError::DomainError);
+        }
+        let mut product : $t = 1;
+        for i in 2..=self {
+          product = overflowed(product.checked_mul(i), || Ok(product.wrapping_mul(i)))?;
+          // Wrapped, it has as many factors of 2 as $t has bits, and
+          // stays zero from here on
+          if product == 0 {
+            break;
+          }
+        }
+        Ok(product)
+      }
+
+      fn bitand(self, y : $t) -> Result<$t, EvalError> {
+        Ok(self & y)
+      }
+
+      fn bitor(self, y : $t) -> Result<$t, EvalError> {
+        Ok(self | y)
+      }
+
+      fn shl(self, y : $t) -> Result<$t, EvalError> {
+        let y = u32::try_from(y).map_err(|_| EvalError::InvalidShift)?;
+        self.checked_shl(y).ok_or(EvalError::InvalidShift)
+      }
+
+      fn shr(self, y : $t) -> Result<$t, EvalError> {
+        let y = u32::try_from(y).map_err(|_| EvalError::InvalidShift)?;
+        self.checked_shr(y).ok_or(EvalError::InvalidShift)
+      }
+
+      fn call(name : &str, args : &[$t], _config : &EvalConfig) -> Result<$t, EvalError> {
+        // Euclid's algorithm on magnitudes
+        fn gcd(mut x : $u, mut y : $u) -> $u {
+          while y != 0 {
+            let t = x % y;
+            x = y;
+            y = t;
+          }
+          x
+        }
+
+        fn narrow(x : $u) -> Result<$t, EvalError> {
+          overflowed($t::try_from(x).ok(), || Ok(x as $t))
+        }
+
+        match (name, args) {
+          // Floor of the square root, by Newton's method
+          ("sqrt", &[n]) | ("isqrt", &[n]) => {
+            if n < 0 {
+              return Err(EvalError::DomainError);
+            }
+            let n = n as $u;
+            let mut x = n;
+            let mut y = x.div_ceil(2);
+            while y < x {
+              x = y;
+              y = (x + n / x) / 2;
+            }
+            Ok(x as $t)
+          },
+          ("abs", &[x]) => overflowed(x.checked_abs(), || Ok(x.wrapping_abs())),
+          ("signum", &[x]) | ("sign", &[x]) => Ok(x.signum()),
+          // Checked whatever the overflow mode says of ^, though the
+          // overflow-wrapping feature still wraps it
+          ("pow", &[_, e]) if e < 0 => Err(EvalError::DomainError),
+          ("pow", &[b, e]) => Number::pow(b, e),
+          // The remainder that is never negative, unlike %
+          ("emod", &[_, 0]) => Err(EvalError::DivideByZero),
+          // Only MIN by -1 overflows, and everything divides by -1
+          ("emod", &[x, y]) => Ok(x.checked_rem_euclid(y).unwrap_or(0)),
+          // An integer is already rounded
+          ("floor", &[x]) | ("ceil", &[x]) | ("round", &[x]) | ("trunc", &[x]) => Ok(x),
+          ("min", &[x, ref rest @ ..]) => Ok(rest.iter().fold(x, |a, &b| a.min(b))),
+          ("max", &[x, ref rest @ ..]) => Ok(rest.iter().fold(x, |a, &b| a.max(b))),
+          ("clamp", &[_, lo, hi]) if lo > hi => Err(EvalError::DomainError),
+          ("clamp", &[x, lo, hi]) => Ok(x.clamp(lo, hi)),
+          // The greatest common divisor is never negative
+          ("gcd", &[x, y]) => narrow(gcd(x.unsigned_abs(), y.unsigned_abs())),
+          // Nor is the least common multiple, which is zero if either
+          // argument is
+          ("lcm", &[x, y]) => {
+            if x == 0 || y == 0 {
+              return Ok(0);
+            }
+            let (x, y) = (x.unsigned_abs(), y.unsigned_abs());
+            let m = x / gcd(x, y);
+            overflowed(m.checked_mul(y), || Ok(m.wrapping_mul(y))).and_then(narrow)
+          },
+          // Never negative, and below 2^31 so it fits any width
+          ("rand", &[]) => Ok((next_random() >> 33) as $t),
+          // The offset from lo is taken modulo the width of the range,
+          // which may be more than $t holds but always fits in $u
+          ("rand", &[lo, hi]) if lo >= hi => Err(EvalError::DomainError),
+          ("rand", &[lo, hi]) => {
+            let width = hi.wrapping_sub(lo) as $u;
+            Ok(lo.wrapping_add((next_random() as $u % width) as $t))
+          },
+          ("sqrt", _) | ("abs", _) | ("signum", _) | ("sign", _) | ("pow", _) | ("emod", _)
+          | ("min", _) | ("max", _) | ("clamp", _) | ("gcd", _) | ("lcm", _) | ("floor", _) | ("ceil", _) | ("round", _) | ("trunc", _)
+          | ("rand", _) | ("isqrt", _) =>
+            Err(EvalError::ArityMismatch(name.to_owned())),
+          _ => Err(EvalError::UnknownFunction(name.to_owned()))
+        }
+      }
+    }
+  }
+}
+
+int_number!(i32, u32);
+int_number!(i64, u64);
+int_number!(i128, u128);
+
+/// Floating point follows IEEE semantics, so dividing by zero gives an
+/// infinity or NaN instead of an error.
+impl Number for f64 {
+  fn from_f64(x : f64) -> Result<f64, EvalError> {
+    Ok(x)
+  }
+
+  fn from_bool(b : bool) -> f64 {
+    if b { 1.0 } else { 0.0 }
+  }
+
+  fn add(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self + y)
+  }
+
+  fn sub(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self - y)
+  }
+
+  fn mul(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self * y)
+  }
+
+  fn div(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self / y)
+  }
+
+  fn div_floor(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self / y)
+  }
+
+  fn div_ceil(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self / y)
+  }
+
+  fn rem(self, y : f64) -> Result<f64, EvalError> {
+    Ok(self % y)
+  }
+
+  fn pow(self, e : f64) -> Result<f64, EvalError> {
+    Ok(self.powf(e))
+  }
+
+  fn neg(self) -> Result<f64, EvalError> {
+    Ok(-self)
+  }
+
+  fn factorial(self) -> Result<f64, EvalError> {
+    if self < 0.0 || self.fract() != 0.0 {
+      return Err(EvalError::DomainError);
+    }
+    // Anything past 170! is too big for an f64
+    if self > 170.0 {
+      return Ok(f64::INFINITY);
+    }
+    Ok((2..self as i64 + 1).fold(1.0, |p, i| p * i as f64))
+  }
+
+  fn call(name : &str, args : &[f64], config : &EvalConfig) -> Result<f64, EvalError> {
+    // Trig functions take their argument in the configured unit
+    let angle = |x : f64| match config.angle {
+      AngleMode::Radians => x,
+      AngleMode::Degrees => x.to_radians()
+    };
+    match (name, args) {
+      ("sqrt", &[x]) if x < 0.0 => Err(EvalError::DomainError),
+      ("sqrt", &[x]) => Ok(x.sqrt()),
+      // The floor of the root, as for integers
+      ("isqrt", &[x]) if x < 0.0 => Err(EvalError::DomainError),
+      ("isqrt", &[x]) => Ok(x.sqrt().floor()),
+      ("abs", &[x]) => Ok(x.abs()),
+      // Unlike f64::signum, zero of either sign has a sign of zero
+      ("signum", &[x]) | ("sign", &[x]) if x == 0.0 => Ok(0.0),
+      ("signum", &[x]) | ("sign", &[x]) => Ok(x.signum()),
+      ("pow", &[b, e]) => Ok(b.powf(e)),
+      // Like %, NaN rather than an error for a zero divisor
+      ("emod", &[x, y]) => Ok(x.rem_euclid(y)),
+      ("floor", &[x]) => Ok(x.floor()),
+      ("ceil", &[x]) => Ok(x.ceil()),
+      // Halfway cases round away from zero, so round(2.5) is 3 and
+      // round(-2.5) is -3
+      ("round", &[x]) => Ok(x.round()),
+      ("trunc", &[x]) => Ok(x.trunc()),
+      ("sin", &[x]) => Ok(angle(x).sin()),
+      ("cos", &[x]) => Ok(angle(x).cos()),
+      ("tan", &[x]) => Ok(angle(x).tan()),
+      // Logarithms are only defined for positive numbers, and a base of
+      // 1 would divide by zero
+      ("ln", &[x]) if x <= 0.0 => Err(EvalError::DomainError),
+      ("ln", &[x]) => Ok(x.ln()),
+      ("log", &[x, b]) if x <= 0.0 || b <= 0.0 || b == 1.0 => Err(EvalError::DomainError),
+      ("log", &[x, b]) => Ok(x.log(b)),
+      ("exp", &[x]) => Ok(x.exp()),
+      ("min", &[x, ref rest @ ..]) => Ok(rest.iter().fold(x, |a, &b| a.min(b))),
+      ("max", &[x, ref rest @ ..]) => Ok(rest.iter().fold(x, |a, &b| a.max(b))),
+      // Bounds that are out of order or NaN have nothing between them
+      ("clamp", &[_, lo, hi]) if lo > hi || lo.is_nan() || hi.is_nan() => Err(EvalError::DomainError),
+      ("clamp", &[x, lo, hi]) => Ok(x.clamp(lo, hi)),
+      ("gcd", _) | ("lcm", _) => call_integral(name, args, config),
+      // A whole number as for integers, or anywhere in [lo, hi) from
+      // the top 53 bits, as many as an f64 holds exactly
+      ("rand", &[]) => Ok((next_random() >> 33) as f64),
+      ("rand", &[lo, hi]) if lo >= hi || lo.is_nan() || hi.is_nan() => Err(EvalError::DomainError),
+      ("rand", &[lo, hi]) => {
+        let unit = (next_random() >> 11) as f64 / (1u64 << 53) as f64;
+        Ok(lo + (hi - lo) * unit)
+      },
+      ("sqrt", _) | ("abs", _) | ("signum", _) | ("sign", _) | ("pow", _) | ("emod", _) | ("min", _)
+      | ("max", _) | ("clamp", _) | ("rand", _) | ("isqrt", _)
+      | ("floor", _) | ("ceil", _) | ("round", _) | ("trunc", _)
+      | ("sin", _) | ("cos", _) | ("tan", _) | ("ln", _) | ("log", _) | ("exp", _) =>
+        Err(EvalError::ArityMismatch(name.to_owned())),
+      _ => Err(EvalError::UnknownFunction(name.to_owned()))
+    }
+  }
+}
+
+// Call an integer-only built-in from the float path.  Every argument
+// must be a whole number in range of an i32.
+fn call_integral(name : &str, args : &[f64], config : &EvalConfig) -> Result<f64, EvalError> {
+  let mut ints = Vec::new();
+  for &x in args {
+    if x.fract() != 0.0 || x < i32::MIN as f64 || x > i32::MAX as f64 {
+      return Err(EvalError::DomainError);
+    }
+    ints.push(x as i32);
+  }
+  i32::call(name, &ints, config).map(f64::from)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::f64::consts;
+
+  use super::*;
+
+  fn call<N : Number>(name : &str, args : &[N]) -> Result<N, EvalError> {
+    N::call(name, args, &EvalConfig::default())
+  }
+
+  #[test]
+  fn float_rounding() {
+    assert_eq!(call("floor", &[-2.5]), Ok(-3.0));
+    assert_eq!(call("ceil", &[-2.5]), Ok(-2.0));
+    assert_eq!(call("round", &[2.5]), Ok(3.0));
+    assert_eq!(call("round", &[-2.5]), Ok(-3.0));
+    assert_eq!(call("trunc", &[-2.7]), Ok(-2.0));
+    assert_eq!(call::<f64>("floor", &[1.0, 2.0]), Err(EvalError::ArityMismatch("floor".to_owned())));
+  }
+
+  #[test]
+  fn integer_rounding_is_the_identity() {
+    for name in &["floor", "ceil", "round", "trunc"] {
+      assert_eq!(call(name, &[-7]), Ok(-7));
+    }
+  }
+
+  #[test]
+  #[cfg(not(feature = "overflow-wrapping"))]
+  fn integer_overflow_is_an_error() {
+    assert_eq!(Number::div(i32::MIN, -1), Err(EvalError::Overflow));
+    assert_eq!(Number::rem(i32::MIN, -1), Err(EvalError::Overflow));
+    assert_eq!(Number::pow(10i32, 20), Err(EvalError::Overflow));
+    assert_eq!(Number::neg(i64::MIN), Err(EvalError::Overflow));
+    assert_eq!(13i32.factorial(), Err(EvalError::Overflow));
+    assert_eq!(12i32.factorial(), Ok(479001600));
+    assert_eq!(call("abs", &[i32::MIN]), Err(EvalError::Overflow));
+    assert_eq!(call("lcm", &[65536, 65537]), Err(EvalError::Overflow));
+    assert_eq!(call("gcd", &[i32::MIN, 0]), Err(EvalError::Overflow));
+  }
+
+  fn close(x : Result<f64, EvalError>, y : f64) -> bool {
+    x.is_ok_and(|x| (x - y).abs() < 1e-9)
+  }
+
+  #[test]
+  fn trig_in_either_angle_mode() {
+    let radians = EvalConfig::default();
+    let degrees = EvalConfig { angle : AngleMode::Degrees, ..EvalConfig::default() };
+    assert_eq!(radians.angle, AngleMode::Radians);
+    assert!(close(f64::call("sin", &[consts::FRAC_PI_2], &radians), 1.0));
+    assert!(close(f64::call("sin", &[90.0], &degrees), 1.0));
+    assert!(close(f64::call("cos", &[consts::PI], &radians), -1.0));
+    assert!(close(f64::call("cos", &[180.0], &degrees), -1.0));
+    assert!(close(f64::call("tan", &[consts::FRAC_PI_4], &radians), 1.0));
+    assert!(close(f64::call("tan", &[45.0], &degrees), 1.0));
+    assert!(close(f64::call("sin", &[0.0], &degrees), 0.0));
+    assert_eq!(call::<f64>("sin", &[]), Err(EvalError::ArityMismatch("sin".to_owned())));
+  }
+
+  #[test]
+  fn logarithms_and_exponentials() {
+    assert!(close(call("ln", &[consts::E]), 1.0));
+    assert!(close(call("ln", &[1.0]), 0.0));
+    assert!(close(call("log", &[8.0, 2.0]), 3.0));
+    assert!(close(call("log", &[1000.0, 10.0]), 3.0));
+    assert!(close(call("exp", &[0.0]), 1.0));
+    assert!(close(call("exp", &[1.0]), consts::E));
+    assert_eq!(call("ln", &[0.0]), Err(EvalError::DomainError));
+    assert_eq!(call("ln", &[-1.0]), Err(EvalError::DomainError));
+    assert_eq!(call("log", &[0.0, 2.0]), Err(EvalError::DomainError));
+    assert_eq!(call("log", &[8.0, 1.0]), Err(EvalError::DomainError));
+    assert_eq!(call("log", &[8.0, -2.0]), Err(EvalError::DomainError));
+    assert_eq!(call::<f64>("log", &[8.0]), Err(EvalError::ArityMismatch("log".to_owned())));
+  }
+
+  #[test]
+  fn integer_square_roots_floor() {
+    for &(n, r) in &[(0, 0), (1, 1), (2, 1), (3, 1), (4, 2), (16, 4), (17, 4), (24, 4), (25, 5)] {
+      assert_eq!(call("isqrt", &[n]), Ok(r), "isqrt({})", n);
+    }
+    assert_eq!(call("isqrt", &[i32::MAX]), Ok(46340));
+    assert_eq!(call("isqrt", &[i64::MAX]), Ok(3037000499));
+    assert_eq!(call("isqrt", &[i128::MAX]), Ok(13043817825332782212));
+    assert_eq!(call("isqrt", &[17.9]), Ok(4.0));
+    assert_eq!(call("isqrt", &[-1]), Err(EvalError::DomainError));
+    assert_eq!(call("isqrt", &[-0.5]), Err(EvalError::DomainError));
+    assert_eq!(call::<i32>("isqrt", &[4, 2]), Err(EvalError::ArityMismatch("isqrt".to_owned())));
+  }
+
+  #[test]
+  fn float_literals_out_of_integer_range_overflow() {
+    assert_eq!(i32::from_f64(2.9), Ok(2));
+    assert_eq!(i32::from_f64(-2.9), Ok(-2));
+    assert_eq!(i32::from_f64(2147483647.9), Ok(i32::MAX));
+    assert_eq!(i32::from_f64(-2147483648.9), Ok(i32::MIN));
+    assert_eq!(i32::from_f64(2147483648.0), Err(EvalError::Overflow));
+    assert_eq!(i32::from_f64(-2147483649.0), Err(EvalError::Overflow));
+    assert_eq!(i32::from_f64(3000000000.0), Err(EvalError::Overflow));
+    assert_eq!(i64::from_f64(9223372036854775807.0), Err(EvalError::Overflow));
+    assert_eq!(i64::from_f64(-9223372036854775808.0), Ok(i64::MIN));
+    assert_eq!(i128::from_f64(1e40), Err(EvalError::Overflow));
+    for x in &[f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+      assert_eq!(i32::from_f64(*x), Err(EvalError::Overflow), "{}", x);
+      assert_eq!(i64::from_f64(*x), Err(EvalError::Overflow), "{}", x);
+      assert_eq!(i128::from_f64(*x), Err(EvalError::Overflow), "{}", x);
+    }
+  }
+}
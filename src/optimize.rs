@@ -0,0 +1,120 @@
+use interpreter::{EvalConfig, EvalError};
+use number::Number;
+use parser::AST;
+
+
+/**********************************************************************
+ * OPTIMIZER
+ */
+
+/// Replace integer subtrees whose operands are all literals with their
+/// values, computed as i32 under the default `EvalConfig`.  An operation
+/// that would fail, like a division by zero, is left in place so the
+/// evaluator still reports it.
+pub fn fold(a : AST) -> AST {
+  match a {
+    AST::Plus(x, y) => fold_binary(AST::Plus, Number::add, *x, *y),
+    AST::Minus(x, y) => fold_binary(AST::Minus, Number::sub, *x, *y),
+    AST::Times(x, y) => fold_binary(AST::Times, Number::mul, *x, *y),
+    AST::Divide(x, y) => fold_binary(AST::Divide, Number::div, *x, *y),
+    // An i32 quotient is already whole
+    AST::IntDivide(x, y) => fold_binary(AST::IntDivide, Number::div, *x, *y),
+    AST::Modulo(x, y) => fold_binary(AST::Modulo, Number::rem, *x, *y),
+    AST::Pow(x, y) => fold_binary(AST::Pow, Number::pow, *x, *y),
+    AST::BitAnd(x, y) => fold_binary(AST::BitAnd, Number::bitand, *x, *y),
+    AST::BitOr(x, y) => fold_binary(AST::BitOr, Number::bitor, *x, *y),
+    AST::Less(x, y) => fold_binary(AST::Less, Number::less, *x, *y),
+    AST::Greater(x, y) => fold_binary(AST::Greater, Number::greater, *x, *y),
+    AST::LessEq(x, y) => fold_binary(AST::LessEq, Number::less_eq, *x, *y),
+    AST::GreaterEq(x, y) => fold_binary(AST::GreaterEq, Number::greater_eq, *x, *y),
+    AST::Equal(x, y) => fold_binary(AST::Equal, Number::equal, *x, *y),
+    AST::NotEqual(x, y) => fold_binary(AST::NotEqual, Number::not_equal, *x, *y),
+    AST::Shl(x, y) => fold_binary(AST::Shl, Number::shl, *x, *y),
+    AST::Shr(x, y) => fold_binary(AST::Shr, Number::shr, *x, *y),
+    AST::Neg(x) => fold_unary(AST::Neg, Number::neg, *x),
+    AST::Factorial(x) => fold_unary(AST::Factorial, Number::factorial, *x),
+    AST::Abs(x) => fold_unary(AST::Abs, |x| i32::call("abs", &[x], &EvalConfig::default()), *x),
+    // Percents are taken in floating point, so they never fold
+    AST::Percent(x) => AST::Percent(Box::new(fold(*x))),
+    AST::Assign(name, x) => AST::Assign(name, Box::new(fold(*x))),
+    AST::Call(name, args) => AST::Call(name, args.into_iter().map(fold).collect()),
+    AST::Sum(xs) => AST::Sum(xs.into_iter().map(fold).collect()),
+    AST::Product(xs) => AST::Product(xs.into_iter().map(fold).collect()),
+    // A literal condition picks its branch, and the other is dropped
+    AST::Cond(c, x, y) => { let c = fold(*c);
+                            match literal(&c) {
+                              Some(0) => fold(*y),
+                              Some(_) => fold(*x),
+                              None => AST::Cond(Box::new(c), Box::new(fold(*x)),
+                                                Box::new(fold(*y)))
+                            } },
+    a => a
+  }
+}
+
+fn fold_unary(node : fn(Box<AST>) -> AST,
+              op : fn(i32) -> Result<i32, EvalError>,
+              x : AST) -> AST {
+  let x = fold(x);
+  match literal(&x).map(op) {
+    Some(Ok(n)) => AST::Num(n.to_string()),
+    _ => node(Box::new(x))
+  }
+}
+
+fn fold_binary(node : fn(Box<AST>, Box<AST>) -> AST,
+               op : fn(i32, i32) -> Result<i32, EvalError>,
+               x : AST, y : AST) -> AST {
+  let (x, y) = (fold(x), fold(y));
+  match (literal(&x), literal(&y)) {
+    (Some(a), Some(b)) => match op(a, b) {
+      Ok(n) => AST::Num(n.to_string()),
+      Err(_) => node(Box::new(x), Box::new(y))
+    },
+    _ => node(Box::new(x), Box::new(y))
+  }
+}
+
+// The value of an integer literal, if it fits in an i32.  A negated one
+// counts, since -2147483648 fits even though 2147483648 does not.
+fn literal(a : &AST) -> Option<i32> {
+  match *a {
+    AST::Num(ref x) => x.parse().ok(),
+    AST::Neg(ref x) => match **x {
+      AST::Num(ref x) => format!("-{}", x).parse().ok(),
+      _ => None
+    },
+    _ => None
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  fn folded(input : &str) -> AST {
+    fold(parse(input).unwrap())
+  }
+
+  #[test]
+  fn folds_constant_arithmetic() {
+    assert_eq!(folded("(2 + 3) * 4"), AST::Num("20".to_owned()));
+    assert_eq!(folded("10 - 2 * 3"), AST::Num("4".to_owned()));
+    assert_eq!(folded("7 / 2"), AST::Num("3".to_owned()));
+  }
+
+  #[test]
+  fn leaves_failures_for_the_evaluator() {
+    assert_eq!(folded("1 / 0"), parse("1 / 0").unwrap());
+    // What can fold still does around what cannot
+    assert_eq!(folded("(1 + 1) / (2 - 2)"), parse("2 / 0").unwrap());
+  }
+
+  #[test]
+  fn leaves_variables_alone() {
+    assert_eq!(folded("x * (2 + 3)"), parse("x * 5").unwrap());
+    assert_eq!(folded("x + 2 + 3"), parse("x + 2 + 3").unwrap());
+  }
+}
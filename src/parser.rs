@@ -0,0 +1,1346 @@
+use std::convert::Infallible;
+use std::error::Error;
+use std::f64::consts;
+use std::fmt;
+use std::mem;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use analysis::depth;
+use lexer::{Lexer, LexError, Span, Token};
+use number::FLOAT_FUNCTIONS;
+
+
+/**********************************************************************
+ * PARSER
+ */
+
+/// Abstract syntax tree
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone,PartialEq,Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AST {
+  /// Integer literal, as decimal digits so that each number type can
+  /// parse it in its own range.
+  Num(String),
+  FNum(f64),
+  Plus(Box<AST>, Box<AST>),
+  Minus(Box<AST>, Box<AST>),
+  Times(Box<AST>, Box<AST>),
+  Divide(Box<AST>, Box<AST>),
+  /// `x div y`, the quotient truncated toward zero even in floating
+  /// point.  An integer quotient is rounded as `/` would round it.
+  IntDivide(Box<AST>, Box<AST>),
+  /// Truncated remainder, as with Rust's `%`: the result takes the sign
+  /// of the dividend, so `-7 % 3` is `-1` and `7 % -3` is `1`.
+  Modulo(Box<AST>, Box<AST>),
+  Pow(Box<AST>, Box<AST>),
+  BitAnd(Box<AST>, Box<AST>),
+  BitOr(Box<AST>, Box<AST>),
+  /// Comparisons evaluate to 1 if they hold and 0 if not.
+  Less(Box<AST>, Box<AST>),
+  Greater(Box<AST>, Box<AST>),
+  LessEq(Box<AST>, Box<AST>),
+  GreaterEq(Box<AST>, Box<AST>),
+  Equal(Box<AST>, Box<AST>),
+  NotEqual(Box<AST>, Box<AST>),
+  /// Shifts take their amount from the right operand, which must be in
+  /// `0..32`.  `>>` is arithmetic, preserving the sign of the left.
+  Shl(Box<AST>, Box<AST>),
+  Shr(Box<AST>, Box<AST>),
+  Neg(Box<AST>),
+  Factorial(Box<AST>),
+  /// `x%`, x divided by 100.  A `%` followed by anything that can start
+  /// an operand, which is a number, name, `(`, `-`, `+`, or outside bars
+  /// a `|`, is modulo instead, so `200 * 10%` is a percent and `7 % -3`
+  /// is not, and a percent followed by `+` or `-` needs parentheses, as
+  /// in `(50%) - 1`.
+  Percent(Box<AST>),
+  /// `|x|`, the absolute value of x
+  Abs(Box<AST>),
+  Var(String),
+  Assign(String, Box<AST>),
+  /// `c ? a : b` evaluates only `a` if `c` is non-zero, and only `b`
+  /// otherwise.
+  Cond(Box<AST>, Box<AST>, Box<AST>),
+  Call(String, Vec<AST>),
+  /// The terms added left to right, as `flatten` makes of a chain of
+  /// `+` and `-`.  The parser never makes one.  With no terms it is 0.
+  Sum(Vec<AST>),
+  /// The factors multiplied left to right, as `flatten` makes of a chain
+  /// of `*`.  With no factors it is 1.
+  Product(Vec<AST>)
+}
+
+/// Ways a token stream can fail to match the grammar.
+#[derive(Clone,PartialEq,Eq,Debug)]
+pub enum ParseError {
+  UnexpectedToken { expected : Token, found : Token, span : Span },
+  UnexpectedEof,
+  BadFactor(Token, Span),
+  /// Input left over after the last statement
+  TrailingTokens(Token, Span),
+  /// Expressions nested past `MAX_NESTING`, or a tree deeper than
+  /// `MAX_DEPTH`, found at this position
+  DepthLimitExceeded(Span),
+  /// A `(` at this position that the input ran out before closing
+  UnclosedParen(Span),
+  Lex(LexError)
+}
+
+impl ParseError {
+  /// Where in the input the error was found, if anywhere.
+  pub fn span(&self) -> Option<Span> {
+    match *self {
+      ParseError::UnexpectedToken { span, .. } | ParseError::BadFactor(_, span)
+      | ParseError::TrailingTokens(_, span)
+      | ParseError::DepthLimitExceeded(span)
+      | ParseError::UnclosedParen(span) => Some(span),
+      ParseError::Lex(ref e) => e.span(),
+      ParseError::UnexpectedEof => None
+    }
+  }
+}
+
+impl fmt::Display for ParseError {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ParseError::UnexpectedToken { ref expected, ref found, .. } =>
+        write!(f, "expected '{}', found '{}'", expected, found),
+      ParseError::UnexpectedEof => write!(f, "unexpected end of input"),
+      ParseError::BadFactor(ref tok, _) => write!(f, "expected an operand, found '{}'", tok),
+      ParseError::TrailingTokens(ref tok, _) => write!(f, "unexpected '{}' after the statement", tok),
+      ParseError::DepthLimitExceeded(_) => write!(f, "expression nested too deeply"),
+      ParseError::UnclosedParen(_) => write!(f, "expected ')' before the end of input"),
+      ParseError::Lex(ref e) => write!(f, "{}", e)
+    }
+  }
+}
+
+impl Error for ParseError {}
+
+impl From<LexError> for ParseError {
+  fn from(e : LexError) -> ParseError {
+    ParseError::Lex(e)
+  }
+}
+
+impl AST {
+  /// The direct subtrees of this node, left to right.
+  pub fn children(&self) -> Vec<&AST> {
+    match *self {
+      AST::Num(_) | AST::FNum(_) | AST::Var(_) => vec![],
+      AST::Neg(ref x)
+      | AST::Factorial(ref x)
+      | AST::Percent(ref x)
+      | AST::Abs(ref x)
+      | AST::Assign(_, ref x) => vec![x],
+      AST::Call(_, ref args) | AST::Sum(ref args) | AST::Product(ref args) => args.iter().collect(),
+      AST::Cond(ref c, ref x, ref y) => vec![c, x, y],
+      AST::Plus(ref x, ref y)
+      | AST::Minus(ref x, ref y)
+      | AST::Times(ref x, ref y)
+      | AST::Divide(ref x, ref y)
+      | AST::IntDivide(ref x, ref y)
+      | AST::Modulo(ref x, ref y)
+      | AST::Pow(ref x, ref y)
+      | AST::BitAnd(ref x, ref y)
+      | AST::BitOr(ref x, ref y)
+      | AST::Less(ref x, ref y)
+      | AST::Greater(ref x, ref y)
+      | AST::LessEq(ref x, ref y)
+      | AST::GreaterEq(ref x, ref y)
+      | AST::Equal(ref x, ref y)
+      | AST::NotEqual(ref x, ref y)
+      | AST::Shl(ref x, ref y)
+      | AST::Shr(ref x, ref y) => vec![x, y]
+    }
+  }
+
+  /// A copy of this node with each direct subtree replaced by `f` of it.
+  pub fn map_children<F : FnMut(&AST) -> AST>(&self, mut f : F) -> AST {
+    match self.try_map_children(|x| Ok::<AST, Infallible>(f(x))) {
+      Ok(a) => a,
+      Err(e) => match e {}
+    }
+  }
+
+  /// Like `map_children`, but stops at the first error from `f`.
+  pub fn try_map_children<E, F>(&self, mut f : F) -> Result<AST, E>
+    where F : FnMut(&AST) -> Result<AST, E> {
+    let mut g = |x : &AST| f(x).map(Box::new);
+    Ok(match *self {
+      AST::Num(_) | AST::FNum(_) | AST::Var(_) => self.clone(),
+      AST::Plus(ref x, ref y) => AST::Plus(g(x)?, g(y)?),
+      AST::Minus(ref x, ref y) => AST::Minus(g(x)?, g(y)?),
+      AST::Times(ref x, ref y) => AST::Times(g(x)?, g(y)?),
+      AST::Divide(ref x, ref y) => AST::Divide(g(x)?, g(y)?),
+      AST::IntDivide(ref x, ref y) => AST::IntDivide(g(x)?, g(y)?),
+      AST::Modulo(ref x, ref y) => AST::Modulo(g(x)?, g(y)?),
+      AST::Pow(ref x, ref y) => AST::Pow(g(x)?, g(y)?),
+      AST::BitAnd(ref x, ref y) => AST::BitAnd(g(x)?, g(y)?),
+      AST::BitOr(ref x, ref y) => AST::BitOr(g(x)?, g(y)?),
+      AST::Less(ref x, ref y) => AST::Less(g(x)?, g(y)?),
+      AST::Greater(ref x, ref y) => AST::Greater(g(x)?, g(y)?),
+      AST::LessEq(ref x, ref y) => AST::LessEq(g(x)?, g(y)?),
+      AST::GreaterEq(ref x, ref y) => AST::GreaterEq(g(x)?, g(y)?),
+      AST::Equal(ref x, ref y) => AST::Equal(g(x)?, g(y)?),
+      AST::NotEqual(ref x, ref y) => AST::NotEqual(g(x)?, g(y)?),
+      AST::Shl(ref x, ref y) => AST::Shl(g(x)?, g(y)?),
+      AST::Shr(ref x, ref y) => AST::Shr(g(x)?, g(y)?),
+      AST::Neg(ref x) => AST::Neg(g(x)?),
+      AST::Factorial(ref x) => AST::Factorial(g(x)?),
+      AST::Percent(ref x) => AST::Percent(g(x)?),
+      AST::Abs(ref x) => AST::Abs(g(x)?),
+      AST::Assign(ref name, ref x) => AST::Assign(name.clone(), g(x)?),
+      AST::Cond(ref c, ref x, ref y) => AST::Cond(g(c)?, g(x)?, g(y)?),
+      AST::Call(ref name, ref args) => {
+        let mut new_args = Vec::new();
+        for arg in args {
+          new_args.push(f(arg)?);
+        }
+        AST::Call(name.clone(), new_args)
+      },
+      AST::Sum(ref xs) => AST::Sum(xs.iter().map(f).collect::<Result<_, _>>()?),
+      AST::Product(ref xs) => AST::Product(xs.iter().map(f).collect::<Result<_, _>>()?)
+    })
+  }
+
+  /// True if any literal in the tree is a float, it takes a percent, or
+  /// it calls one of the `FLOAT_FUNCTIONS`, meaning the tree should be
+  /// evaluated with `evaluate_f64`.
+  pub fn has_float(&self) -> bool {
+    match *self {
+      AST::FNum(_) | AST::Percent(_) => true,
+      AST::Call(ref name, _) if FLOAT_FUNCTIONS.contains(&name.as_str()) => true,
+      _ => self.children().into_iter().any(AST::has_float)
+    }
+  }
+}
+
+// Binding powers of the grammar productions, loosest first
+const PREC_ASSIGN : u8 = 0;
+const PREC_COND : u8 = 1;
+const PREC_BITOR : u8 = 2;
+const PREC_BITAND : u8 = 3;
+const PREC_CMP : u8 = 4;
+const PREC_SHIFT : u8 = 5;
+const PREC_SUM : u8 = 6;
+const PREC_TERM : u8 = 7;
+const PREC_UNARY : u8 = 8;
+const PREC_POWER : u8 = 9;
+const PREC_FACTOR : u8 = 10;
+
+// Operands that may appear anywhere an expression is expected
+const PREC_EXP : u8 = PREC_COND;
+
+impl AST {
+  // Binding power of the production that parses this node.  A child
+  // printed where a tighter production is expected needs parentheses.
+  fn precedence(&self) -> u8 {
+    match *self {
+      AST::Sum(ref xs) | AST::Product(ref xs) if xs.len() < 2 =>
+        xs.first().map_or(PREC_FACTOR, AST::precedence),
+      AST::Sum(_) => PREC_SUM,
+      AST::Product(_) => PREC_TERM,
+      AST::Assign(..) => PREC_ASSIGN,
+      AST::Cond(..) => PREC_COND,
+      AST::BitOr(..) => PREC_BITOR,
+      AST::BitAnd(..) => PREC_BITAND,
+      AST::Less(..) | AST::Greater(..) | AST::LessEq(..) | AST::GreaterEq(..)
+      | AST::Equal(..) | AST::NotEqual(..) => PREC_CMP,
+      AST::Shl(..) | AST::Shr(..) => PREC_SHIFT,
+      AST::Plus(..) | AST::Minus(..) => PREC_SUM,
+      AST::Times(..) | AST::Divide(..) | AST::IntDivide(..) | AST::Modulo(..) => PREC_TERM,
+      AST::Neg(_) => PREC_UNARY,
+      AST::Num(ref x) if x.starts_with('-') => PREC_UNARY,
+      AST::FNum(x) if x.is_sign_negative() => PREC_UNARY,
+      AST::Pow(..) => PREC_POWER,
+      _ => PREC_FACTOR
+    }
+  }
+
+  fn fmt_prec(&self, f : &mut fmt::Formatter, prec : u8) -> fmt::Result {
+    if self.precedence() < prec {
+      write!(f, "({})", self)
+    } else {
+      write!(f, "{}", self)
+    }
+  }
+}
+
+// True if a may print with a % last, going by its last operand
+// whether or not that is in parentheses
+fn ends_in_percent(mut a : &AST) -> bool {
+  loop {
+    a = match *a {
+      AST::Percent(_) => return true,
+      // These print a character of their own last
+      AST::Factorial(_) | AST::Abs(_) | AST::Call(..) => return false,
+      _ => match a.children().pop() {
+        Some(y) => y,
+        None => return false
+      }
+    }
+  }
+}
+
+// Left and right operands are printed at the precedence their positions
+// in the grammar demand
+fn fmt_binary(f : &mut fmt::Formatter, x : &AST, op : &str, y : &AST,
+              lprec : u8, rprec : u8) -> fmt::Result {
+  // A percent before an operator that can also start an operand would
+  // be read as modulo
+  if matches!(op, "+" | "-" | "|") && ends_in_percent(x) {
+    write!(f, "({})", x)?;
+  } else {
+    x.fmt_prec(f, lprec)?;
+  }
+  write!(f, " {} ", op)?;
+  y.fmt_prec(f, rprec)
+}
+
+// A chain of one left associative operator, or the literal empty if it
+// has no operands
+fn fmt_chain(f : &mut fmt::Formatter, xs : &[AST], op : &str, empty : &str,
+             prec : u8) -> fmt::Result {
+  let (first, rest) = match xs.split_first() {
+    Some(x) => x,
+    None => return write!(f, "{}", empty)
+  };
+  first.fmt_prec(f, prec)?;
+  for x in rest {
+    write!(f, " {} ", op)?;
+    x.fmt_prec(f, prec + 1)?;
+  }
+  Ok(())
+}
+
+// Most binary operators are left associative
+fn fmt_left(f : &mut fmt::Formatter, x : &AST, op : &str, y : &AST,
+            prec : u8) -> fmt::Result {
+  fmt_binary(f, x, op, y, prec, prec + 1)
+}
+
+/// Prints the tree as infix source that parses back to the same tree,
+/// using only the parentheses that precedence and associativity require.
+/// A `Sum` or `Product` prints as `unflatten` would rebuild it.
+impl fmt::Display for AST {
+  fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      AST::Num(ref x) => write!(f, "{}", x),
+      AST::FNum(x) => { let s = x.to_string();
+                        if s.contains('.') {
+                          write!(f, "{}", s)
+                        } else {
+                          write!(f, "{}.0", s)
+                        } },
+      // Right associative, so only the condition needs parentheses to
+      // hold another conditional
+      AST::Cond(ref c, ref x, ref y) => { c.fmt_prec(f, PREC_BITOR)?;
+                                          write!(f, " ? ")?;
+                                          x.fmt_prec(f, PREC_COND)?;
+                                          write!(f, " : ")?;
+                                          y.fmt_prec(f, PREC_COND) },
+      AST::BitOr(ref x, ref y) => fmt_left(f, x, "|", y, PREC_BITOR),
+      AST::BitAnd(ref x, ref y) => fmt_left(f, x, "&", y, PREC_BITAND),
+      AST::Less(ref x, ref y) => fmt_left(f, x, "<", y, PREC_CMP),
+      AST::Greater(ref x, ref y) => fmt_left(f, x, ">", y, PREC_CMP),
+      AST::LessEq(ref x, ref y) => fmt_left(f, x, "<=", y, PREC_CMP),
+      AST::GreaterEq(ref x, ref y) => fmt_left(f, x, ">=", y, PREC_CMP),
+      AST::Equal(ref x, ref y) => fmt_left(f, x, "==", y, PREC_CMP),
+      AST::NotEqual(ref x, ref y) => fmt_left(f, x, "!=", y, PREC_CMP),
+      AST::Shl(ref x, ref y) => fmt_left(f, x, "<<", y, PREC_SHIFT),
+      AST::Shr(ref x, ref y) => fmt_left(f, x, ">>", y, PREC_SHIFT),
+      AST::Plus(ref x, ref y) => fmt_left(f, x, "+", y, PREC_SUM),
+      AST::Minus(ref x, ref y) => fmt_left(f, x, "-", y, PREC_SUM),
+      AST::Times(ref x, ref y) => fmt_left(f, x, "*", y, PREC_TERM),
+      AST::Divide(ref x, ref y) => fmt_left(f, x, "/", y, PREC_TERM),
+      AST::IntDivide(ref x, ref y) => fmt_left(f, x, "div", y, PREC_TERM),
+      // Inside bars a % before a | would be read as a percent
+      AST::Modulo(ref x, ref y) => { let s = y.to_string();
+                                     let rprec = if s.starts_with('|') {
+                                       PREC_FACTOR + 1
+                                     } else {
+                                       PREC_TERM + 1
+                                     };
+                                     fmt_binary(f, x, "%", y, PREC_TERM, rprec) },
+      AST::Pow(ref x, ref y) => fmt_binary(f, x, "^", y, PREC_FACTOR, PREC_UNARY),
+      AST::Neg(ref x) => { write!(f, "-")?;
+                           x.fmt_prec(f, PREC_UNARY) },
+      AST::Factorial(ref x) => { x.fmt_prec(f, PREC_FACTOR)?;
+                                 write!(f, "!") },
+      AST::Percent(ref x) => { x.fmt_prec(f, PREC_FACTOR)?;
+                               write!(f, "%") },
+      // A | inside the bars would close them, so an or needs parentheses
+      AST::Abs(ref x) => { write!(f, "|")?;
+                           x.fmt_prec(f, PREC_BITAND)?;
+                           write!(f, "|") },
+      AST::Var(ref name) => write!(f, "{}", name),
+      AST::Assign(ref name, ref x) => { write!(f, "{} = ", name)?;
+                                        x.fmt_prec(f, PREC_EXP) },
+      AST::Call(ref name, ref args) => { write!(f, "{}(", name)?;
+                                         for (i, arg) in args.iter().enumerate() {
+                                           if i > 0 {
+                                             write!(f, ", ")?;
+                                           }
+                                           arg.fmt_prec(f, PREC_EXP)?;
+                                         }
+                                         write!(f, ")") },
+      AST::Sum(ref xs) => fmt_chain(f, xs, "+", "0", PREC_SUM),
+      AST::Product(ref xs) => fmt_chain(f, xs, "*", "1", PREC_TERM)
+    }
+  }
+}
+
+/// Which way a chain of operators of the same binding power groups.
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum Assoc {
+  /// `1 - 2 - 3` is `(1 - 2) - 3`
+  Left,
+  /// `2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`
+  Right
+}
+
+/// The binding power and associativity of each binary operator, by its
+/// token, where a higher power binds tighter.  The default is the usual
+/// grammar: `|`, `&`, comparisons, shifts, `+ -`, `* / % mod div`, and
+/// `^`, from 2 up to 9, all left associative but `^`.  Whatever the
+/// table, the conditional is looser than any operator, negation and
+/// unary plus bind at power 8, and juxtaposition like `2x` binds as `*`
+/// does.  Trees always print as if parsed with the default.
+#[derive(Clone,Debug)]
+pub struct PrecedenceTable {
+  ops : Vec<(Token, u8, Assoc)>
+}
+
+impl PrecedenceTable {
+  /// Gives the binary operator `tok` a new binding power and
+  /// associativity.  A token that is not a binary operator is ignored.
+  /// Setting `Token::Caret` to `Assoc::Left` at its usual power 9, for
+  /// instance, makes `2^3^2` and `2**3**2` mean `(2^3)^2`, which is 64
+  /// rather than 512.
+  pub fn set(&mut self, tok : Token, power : u8, assoc : Assoc) {
+    if binary_node(&tok).is_none() {
+      return;
+    }
+    self.ops.retain(|op| op.0 != tok);
+    self.ops.push((tok, power, assoc));
+  }
+
+  /// The binding power and associativity of `tok`, if it is a binary
+  /// operator.
+  pub fn get(&self, tok : &Token) -> Option<(u8, Assoc)> {
+    self.ops.iter().find(|op| op.0 == *tok).map(|op| (op.1, op.2))
+  }
+}
+
+impl Default for PrecedenceTable {
+  fn default() -> PrecedenceTable {
+    let ops = vec![
+      (Token::Pipe, PREC_BITOR, Assoc::Left),
+      (Token::Amp, PREC_BITAND, Assoc::Left),
+      (Token::Less, PREC_CMP, Assoc::Left),
+      (Token::Greater, PREC_CMP, Assoc::Left),
+      (Token::LessEq, PREC_CMP, Assoc::Left),
+      (Token::GreaterEq, PREC_CMP, Assoc::Left),
+      (Token::EqEq, PREC_CMP, Assoc::Left),
+      (Token::NotEq, PREC_CMP, Assoc::Left),
+      (Token::Shl, PREC_SHIFT, Assoc::Left),
+      (Token::Shr, PREC_SHIFT, Assoc::Left),
+      (Token::Plus, PREC_SUM, Assoc::Left),
+      (Token::Minus, PREC_SUM, Assoc::Left),
+      (Token::Times, PREC_TERM, Assoc::Left),
+      (Token::Divide, PREC_TERM, Assoc::Left),
+      (Token::Percent, PREC_TERM, Assoc::Left),
+      (Token::Mod, PREC_TERM, Assoc::Left),
+      (Token::Div, PREC_TERM, Assoc::Left),
+      (Token::Caret, PREC_POWER, Assoc::Right)
+    ];
+    PrecedenceTable { ops }
+  }
+}
+
+// Builds the node for a binary operator from its operands
+type BinaryNode = fn(Box<AST>, Box<AST>) -> AST;
+
+// The node a binary operator token builds
+fn binary_node(tok : &Token) -> Option<BinaryNode> {
+  Some(match *tok {
+    Token::Pipe => AST::BitOr,
+    Token::Amp => AST::BitAnd,
+    Token::Less => AST::Less,
+    Token::Greater => AST::Greater,
+    Token::LessEq => AST::LessEq,
+    Token::GreaterEq => AST::GreaterEq,
+    Token::EqEq => AST::Equal,
+    Token::NotEq => AST::NotEqual,
+    Token::Shl => AST::Shl,
+    Token::Shr => AST::Shr,
+    Token::Plus => AST::Plus,
+    Token::Minus => AST::Minus,
+    Token::Times => AST::Times,
+    Token::Divide => AST::Divide,
+    Token::Percent | Token::Mod => AST::Modulo,
+    Token::Div => AST::IntDivide,
+    Token::Caret => AST::Pow,
+    _ => return None
+  })
+}
+
+/// How deeply the parser may recurse, which is once for each level of
+/// parentheses, arguments, or conditionals, each unary operator, as in
+/// `--x`, and each right associative one, as in `2^3^4`.  This keeps
+/// any input, however long, from overflowing the stack.
+pub const MAX_NESTING : usize = 128;
+
+/// The deepest tree the parser builds, as `depth` measures it.  A chain
+/// of left associative or postfix operators like `1+2+3` does not nest,
+/// but it does make the tree one deeper for each operator.  This is the
+/// default `max_depth` of `EvalConfig`, so anything that parses can be
+/// evaluated.
+pub const MAX_DEPTH : usize = 1000;
+
+pub struct Parser<'a> {
+  tok : Token,
+  // Where tok starts, for error reporting
+  span : Span,
+  // The line the token before tok was on
+  prev_line : usize,
+  // Whether the token before tok was a name or a ), which a number can
+  // follow as a factor multiplying it
+  after_name : bool,
+  // How deeply productions have recursed, as counted by nested
+  nesting : usize,
+  // How many absolute value bars are open inside the innermost
+  // parentheses
+  bars : usize,
+  // How many parentheses and conditionals are open, waiting for their )
+  // or :
+  open : usize,
+  // Whether the end of a line can end a statement
+  newlines : bool,
+  table : PrecedenceTable,
+  lex : &'a mut Lexer
+}
+
+impl<'a> Parser<'a> {
+  pub fn new(lex : &'a mut Lexer) -> Result<Parser<'a>, ParseError> {
+    Parser::with_precedence(lex, PrecedenceTable::default())
+  }
+
+  /// Creates a parser that gives binary operators the binding powers in
+  /// `table` rather than the usual ones.
+  pub fn with_precedence(lex : &'a mut Lexer,
+                         table : PrecedenceTable) -> Result<Parser<'a>, ParseError> {
+    let tok = lex.get_token()?;
+    Ok(Parser {
+      tok,
+      span : lex.span(),
+      prev_line : lex.span().line,
+      after_name : false,
+      nesting : 0,
+      bars : 0,
+      open : 0,
+      newlines : false,
+      table,
+      lex
+    })
+  }
+
+  /// True if the input has run out, so there is no statement to parse.
+  pub fn at_end(&self) -> bool {
+    self.tok == Token::Eof
+  }
+
+  fn get_token(&mut self) -> Result<(), ParseError> {
+    self.prev_line = self.span.line;
+    self.after_name = matches!(self.tok, Token::Ident(_) | Token::RParen);
+    self.tok = self.lex.get_token()?;
+    self.span = self.lex.span();
+    Ok(())
+  }
+
+  /// Makes the end of a line end a statement as a `;` would, wherever
+  /// the statement could be complete, for interactive input.  After an
+  /// operator or inside parentheses the statement still goes on to the
+  /// next line.
+  pub fn newline_terminates(&mut self) {
+    self.newlines = true;
+  }
+
+  // Eat the last token of an operand.  When the end of a line can end a
+  // statement and nothing is left open, a line ending here ends the
+  // statement, and tok becomes a stand-in ; without reading further.
+  fn end_operand(&mut self, t : Token) -> Result<(), ParseError> {
+    if self.newlines && self.open == 0 && self.bars == 0 && self.tok == t
+       && self.lex.at_line_end() {
+      self.tok = Token::Semi;
+      return Ok(());
+    }
+    self.eat(t)
+  }
+
+  fn eat(&mut self, t : Token) -> Result<(), ParseError> {
+    if self.tok == t {
+      self.get_token()
+    } else if self.at_end() {
+      Err(ParseError::UnexpectedEof)
+    } else {
+      Err(ParseError::UnexpectedToken { expected : t, found : self.tok.clone(),
+                                        span : self.span })
+    }
+  }
+
+  /********************************************************************
+   * GRAMMAR PRODUCTIONS
+   * x_ productions are hacks to make the grammar right recursive
+   * and therefore suitable for recursive descent parsing
+   */
+  /// Starting production. Use this as entry into the parser.  The
+  /// statement must be followed by a `;` or the end of input.
+  pub fn program(&mut self) -> Result<AST, ParseError> {
+    let a = self.statement()?;
+    self.semi(a)
+  }
+
+  /// Parses `;`-terminated statements until the input is exhausted,
+  /// meaning that nothing but whitespace follows a `;` on its line.  The
+  /// end of input terminates the last statement even without a `;`.  A
+  /// line-oriented reader like stdin therefore yields one batch of
+  /// statements per line, without blocking to read the next one.
+  pub fn statements(&mut self) -> Result<Vec<AST>, ParseError> {
+    let mut stmts = Vec::new();
+    loop {
+      stmts.push(self.statement()?);
+      if self.at_end() || self.tok == Token::Semi && self.lex.at_line_end() {
+        return Ok(stmts);
+      }
+      self.eat(Token::Semi)?;
+    }
+  }
+
+  /// Checks that nothing is left after the statements parsed so far but
+  /// the `;` ending the last one.  Call this after `program` or
+  /// `statements`.
+  pub fn finish(&mut self) -> Result<(), ParseError> {
+    if self.tok == Token::Semi {
+      self.get_token()?;
+    }
+    if !self.at_end() {
+      return Err(ParseError::TrailingTokens(self.tok.clone(), self.span));
+    }
+    Ok(())
+  }
+
+  // An assignment looks like an expression until the `=`, so parse an
+  // expression and reinterpret a bare variable as the assignment target
+  fn statement(&mut self) -> Result<AST, ParseError> {
+    let e = self.exp()?;
+    match (e, &self.tok) {
+      (AST::Var(name), &Token::Equals) => { self.eat(Token::Equals)?;
+                                             let v = self.exp()?;
+                                             Ok(AST::Assign(name, Box::new(v))) },
+      (e, _) => { Ok(e) }
+    }
+  }
+
+  // Run a production one level deeper, refusing to go past MAX_NESTING
+  fn nested<T, F>(&mut self, f : F) -> Result<T, ParseError>
+    where F : FnOnce(&mut Parser<'a>) -> Result<T, ParseError> {
+    if self.nesting == MAX_NESTING {
+      return Err(ParseError::DepthLimitExceeded(self.span));
+    }
+    self.nesting += 1;
+    let rc = f(self);
+    self.nesting -= 1;
+    rc
+  }
+
+  fn exp(&mut self) -> Result<AST, ParseError> {
+    self.nested(Parser::cond)
+  }
+
+  // Run a production with no bars open, as inside parentheses, where a
+  // | is always an or
+  fn unbarred<T, F>(&mut self, f : F) -> Result<T, ParseError>
+    where F : FnOnce(&mut Parser<'a>) -> Result<T, ParseError> {
+    let bars = mem::replace(&mut self.bars, 0);
+    self.open += 1;
+    let rc = f(self);
+    self.open -= 1;
+    self.bars = bars;
+    rc
+  }
+
+  // Run a production that starts at a ( with no bars open, and eat the
+  // ) after it.  Running out of input first leaves the ( unclosed.
+  fn parenthesized<T, F>(&mut self, f : F) -> Result<T, ParseError>
+    where F : FnOnce(&mut Parser<'a>) -> Result<T, ParseError> {
+    let open = self.span;
+    let rc = self.unbarred(f).and_then(|rc| self.end_operand(Token::RParen).map(|_| rc));
+    match rc {
+      Err(ParseError::UnexpectedEof) => Err(ParseError::UnclosedParen(open)),
+      rc => rc
+    }
+  }
+
+  // The conditional operator is right associative, so `a ? b : c ? d : e`
+  // chooses between b and the whole of `c ? d : e`
+  fn cond(&mut self) -> Result<AST, ParseError> {
+    let c = self.binary(0)?;
+    if self.tok != Token::Question {
+      return Ok(c);
+    }
+    self.eat(Token::Question)?;
+    self.open += 1;
+    let a = self.exp();
+    self.open -= 1;
+    let a = a?;
+    self.eat(Token::Colon)?;
+    let b = self.exp()?;
+    Ok(AST::Cond(Box::new(c), Box::new(a), Box::new(b)))
+  }
+
+  // Binary operators by precedence climbing: an operand, then as many
+  // operators as bind at least as tightly as min
+  fn binary(&mut self, min : u8) -> Result<AST, ParseError> {
+    let a = self.unary()?;
+    self.binary_(a, min)
+  }
+
+  // The operators that bind at least as tightly as min are taken in a
+  // loop, so only the right operand of each recurses
+  fn binary_(&mut self, mut a : AST, min : u8) -> Result<AST, ParseError> {
+    // How deep a is, once there is an operator to chain onto it
+    let mut deep = None;
+    loop {
+      // Juxtaposition multiplies, as in `2(3+4)`, `3pi`, or `(2)3`.  The
+      // right side must be a name, call, or parenthesis on the same line,
+      // or a number after a name or ), so that `2+3 4+5`, or two
+      // statements on their own lines, are still missing the ; between
+      // them.  It cannot be negated, and a name directly followed by `(`
+      // is still a call, so `x(2)` calls x rather than multiplying by it.
+      let implicit = match self.tok {
+        Token::Ident(_) | Token::LParen => true,
+        Token::Num(_) => self.after_name,
+        _ => false
+      } && self.span.line == self.prev_line;
+      let tok = if implicit { Token::Times } else { self.tok.clone() };
+      let (power, assoc) = match self.table.get(&tok) {
+        // Inside bars a | closes them instead
+        Some(_) if tok == Token::Pipe && self.bars > 0 => return Ok(a),
+        Some(p) => p,
+        None => return Ok(a)
+      };
+      if power < min {
+        return Ok(a);
+      }
+      let next = match assoc {
+        Assoc::Left => power.saturating_add(1),
+        Assoc::Right => power
+      };
+      let span = self.span;
+      let b = if implicit {
+        let f = self.factor()?;
+        self.nested(|p| p.binary_(f, next))?
+      } else {
+        self.get_token()?;
+        self.nested(|p| p.binary(next))?
+      };
+      let d = deep.unwrap_or_else(|| depth(&a)).max(depth(&b)) + 1;
+      if d > MAX_DEPTH {
+        return Err(ParseError::DepthLimitExceeded(span));
+      }
+      deep = Some(d);
+      let node = binary_node(&tok).ok_or_else(|| ParseError::BadFactor(tok.clone(), span))?;
+      a = node(Box::new(a), Box::new(b));
+    }
+  }
+
+  // Negation binds looser than exponentiation, so -2^2 is -(2^2).  A
+  // unary plus binds the same way but leaves its operand as it is.
+  fn unary(&mut self) -> Result<AST, ParseError> {
+    match self.tok {
+      Token::Minus => { self.eat(Token::Minus)?;
+                        let f = self.nested(|p| p.binary(PREC_UNARY))?;
+                        Ok(AST::Neg(Box::new(f))) },
+      Token::Plus => { self.eat(Token::Plus)?;
+                       self.nested(|p| p.binary(PREC_UNARY)) },
+      _ => { self.factor() }
+    }
+  }
+
+  // Postfix operators bind tighter than anything else
+  fn factor(&mut self) -> Result<AST, ParseError> {
+    let f = self.primary()?;
+    self.factor_(f)
+  }
+
+  fn factor_(&mut self, mut f : AST) -> Result<AST, ParseError> {
+    let mut deep = None;
+    loop {
+      let node : fn(Box<AST>) -> AST = if self.at_percent()? {
+        AST::Percent
+      } else if self.tok == Token::Bang {
+        AST::Factorial
+      } else {
+        return Ok(f);
+      };
+      let d = deep.unwrap_or_else(|| depth(&f)) + 1;
+      if d > MAX_DEPTH {
+        return Err(ParseError::DepthLimitExceeded(self.span));
+      }
+      deep = Some(d);
+      let t = self.tok.clone();
+      self.end_operand(t)?;
+      f = node(Box::new(f));
+    }
+  }
+
+  // A % is modulo when an operand follows, and otherwise a percent.
+  // Inside bars a | after it closes them instead.
+  fn at_percent(&mut self) -> Result<bool, ParseError> {
+    if self.tok != Token::Percent {
+      return Ok(false);
+    }
+    if self.newlines && self.open == 0 && self.bars == 0 && self.lex.at_line_end() {
+      return Ok(true);
+    }
+    match self.lex.peek() {
+      Ok(&Token::Num(_)) | Ok(&Token::Ident(_)) | Ok(&Token::LParen)
+      | Ok(&Token::Minus) | Ok(&Token::Plus) => Ok(false),
+      Ok(&Token::Pipe) => Ok(self.bars > 0),
+      Ok(_) => Ok(true),
+      Err(e) => Err(e.into())
+    }
+  }
+
+  fn primary(&mut self) -> Result<AST, ParseError> {
+    let tok = self.tok.clone();  // Make the borrow checker stop complaining
+    match tok {
+      Token::Num(ref x) if x.contains('.') =>
+                           { let n = x.parse::<f64>()
+                               .map_err(|_| ParseError::BadFactor(tok.clone(), self.span))?;
+                             self.end_operand(tok.clone())?;
+                             Ok(AST::FNum(n)) } ,
+      Token::Num(ref x) => { let n = int_digits(x)
+                               .ok_or_else(|| ParseError::BadFactor(tok.clone(), self.span))?;
+                             self.end_operand(tok.clone())?;
+                             Ok(AST::Num(n)) } ,
+      // pi and e are reserved: they become float literals here, so a
+      // variable of either name can never be assigned or read.  A call
+      // has its ( on the same line as its name.
+      Token::Ident(ref x) => { self.end_operand(tok.clone())?;
+                               if self.tok == Token::LParen && self.span.line == self.prev_line {
+                                 let args = self.parenthesized(Parser::args)?;
+                                 Ok(AST::Call(x.clone(), args))
+                               } else if x == "pi" {
+                                 Ok(AST::FNum(consts::PI))
+                               } else if x == "e" {
+                                 Ok(AST::FNum(consts::E))
+                               } else {
+                                 Ok(AST::Var(x.clone()))
+                               } } ,
+      Token::LParen => { self.parenthesized(|p| { p.eat(Token::LParen)?;
+                                                  p.exp() }) } ,
+      // A | where an operand belongs opens bars, and the next | that
+      // is not inside parentheses closes them, so `|1 + |-3||` nests
+      Token::Pipe => { self.eat(Token::Pipe)?;
+                       self.bars += 1;
+                       let rc = self.exp();
+                       self.bars -= 1;
+                       let rc = rc?;
+                       self.end_operand(Token::Pipe)?;
+                       Ok(AST::Abs(Box::new(rc))) } ,
+      Token::Eof => Err(ParseError::UnexpectedEof),
+      _ => { Err(ParseError::BadFactor(tok, self.span)) }
+    }
+  }
+
+  // Parenthesized, comma-separated argument list of a function call, up
+  // to but not including the )
+  fn args(&mut self) -> Result<Vec<AST>, ParseError> {
+    let mut args = Vec::new();
+    self.eat(Token::LParen)?;
+    if self.tok != Token::RParen {
+      args.push(self.exp()?);
+      while self.tok == Token::Comma {
+        self.eat(Token::Comma)?;
+        args.push(self.exp()?);
+      }
+    }
+    Ok(args)
+  }
+
+  // Terminal production.  Ends parsing.
+  fn semi(&mut self, a : AST) -> Result<AST, ParseError> {
+    if self.tok != Token::Semi && !self.at_end() {
+      return Err(ParseError::UnexpectedToken { expected : Token::Semi, found : self.tok.clone(),
+                                               span : self.span });
+    }
+    Ok(a)
+  }
+}
+
+/// Skips the rest of a statement that failed to parse with `e`, up to
+/// and including its `;`, so that parsing can start again with the next
+/// one.  Nothing is skipped if the error was at the `;` itself.
+pub fn skip_statement(lexer : &mut Lexer, e : &ParseError) {
+  match *e {
+    ParseError::UnexpectedToken { found : Token::Semi, .. } | ParseError::BadFactor(Token::Semi, _) =>
+      return,
+    _ => ()
+  }
+  loop {
+    match lexer.get_token() {
+      Ok(Token::Semi) | Ok(Token::Eof) | Err(LexError::Read(_)) => return,
+      _ => ()
+    }
+  }
+}
+
+// Integer literals are decimal, 0x-prefixed hex, or 0b-prefixed binary,
+// and come out as decimal digits.  Whether one fits is up to the number
+// type it is evaluated in, so decimal literals are kept as written and
+// may be any size, while hex and binary ones must fit in a u128.
+pub fn int_digits(s : &str) -> Option<String> {
+  if s.starts_with("0x") || s.starts_with("0X") {
+    u128::from_str_radix(&s[2..], 16).ok().map(|n| n.to_string())
+  } else if s.starts_with("0b") || s.starts_with("0B") {
+    u128::from_str_radix(&s[2..], 2).ok().map(|n| n.to_string())
+  } else if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+    Some(s.to_owned())
+  } else {
+    None
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use {CalcError, checked_parse_all, eval, eval_f64, eval_with, eval_rpn, from_sexpr, parse, to_rpn};
+  use interpreter::{EvalConfig, EvalError, evaluate_iterative};
+
+  fn num(n : &str) -> Box<AST> {
+    Box::new(AST::Num(n.to_owned()))
+  }
+
+  fn var(name : &str) -> Box<AST> {
+    Box::new(AST::Var(name.to_owned()))
+  }
+
+  // What Parser::program makes of the input
+  fn program(input : &str) -> Result<AST, ParseError> {
+    let mut lexer = Lexer::from_string(input);
+    Parser::new(&mut lexer)?.program()
+  }
+
+  #[test]
+  fn precedence_and_associativity() {
+    assert_eq!(program("1 + 2 * 3"),
+               Ok(AST::Plus(num("1"), Box::new(AST::Times(num("2"), num("3"))))));
+    assert_eq!(program("1 - 2 - 3"),
+               Ok(AST::Minus(Box::new(AST::Minus(num("1"), num("2"))), num("3"))));
+    assert_eq!(program("(1 + 2) / 3"),
+               Ok(AST::Divide(Box::new(AST::Plus(num("1"), num("2"))), num("3"))));
+  }
+
+  #[test]
+  fn malformed_input_is_an_error() {
+    let at = |col| Span { line : 1, col };
+    assert_eq!(program("1 +"), Err(ParseError::UnexpectedEof));
+    assert_eq!(program("(2"), Err(ParseError::UnclosedParen(at(1))));
+    assert_eq!(program("2 * )"), Err(ParseError::BadFactor(Token::RParen, at(5))));
+    assert_eq!(program("(1 2)"), Err(ParseError::UnexpectedToken {
+      expected : Token::RParen, found : Token::Num("2".to_owned()), span : at(4)
+    }));
+    assert_eq!(program("1 )"), Err(ParseError::UnexpectedToken {
+      expected : Token::Semi, found : Token::RParen, span : at(3)
+    }));
+  }
+
+  #[test]
+  fn percent_is_a_hundredth() {
+    assert_eq!(eval_f64("50%").unwrap(), 0.5);
+    assert_eq!(eval_f64("200 * 10%").unwrap(), 20.0);
+    assert_eq!(eval_f64("(50%) - 1").unwrap(), -0.5);
+    assert_eq!(eval_f64("|10%|").unwrap(), 0.1);
+    assert_eq!(parse("50%!"), Ok(AST::Factorial(Box::new(AST::Percent(num("50"))))));
+  }
+
+  #[test]
+  fn percent_before_an_operand_is_modulo() {
+    assert_eq!(parse("7 % 3"), Ok(AST::Modulo(num("7"), num("3"))));
+    assert_eq!(parse("7 % -3"), Ok(AST::Modulo(num("7"), Box::new(AST::Neg(num("3"))))));
+    assert_eq!(parse("7 % +3"), Ok(AST::Modulo(num("7"), num("3"))));
+    assert_eq!(parse("7 % |x|"), Ok(AST::Modulo(num("7"), Box::new(AST::Abs(var("x"))))));
+    assert_eq!(eval("7 % -3").unwrap(), 1);
+    assert_eq!(eval_f64("7 % -3").unwrap(), 1.0);
+    assert_eq!(eval("-7 % |-3|").unwrap(), -1);
+  }
+
+  #[test]
+  fn percent_prints_so_it_parses_back() {
+    for input in &["(50%) - 1", "(50%) + 1", "(50%) | 1", "(1 + 50%) - 2", "|50%|", "|7 % (|x|)|", "7 % -3"] {
+      let a = parse(input).unwrap();
+      assert_eq!(a.to_string(), *input);
+      assert_eq!(parse(&a.to_string()), Ok(a));
+    }
+  }
+
+  #[test]
+  fn juxtaposition_multiplies() {
+    assert_eq!(eval("2(3+4)").unwrap(), 14);
+    assert_eq!(parse("2x"), Ok(AST::Times(num("2"), var("x"))));
+    assert_eq!(parse("3pi"), Ok(AST::Times(num("3"), Box::new(AST::FNum(consts::PI)))));
+    assert_eq!(parse("(1)(2)"), Ok(AST::Times(num("1"), num("2"))));
+    assert_eq!(parse("2x^2"),
+               Ok(AST::Times(num("2"), Box::new(AST::Pow(var("x"), num("2"))))));
+  }
+
+  #[test]
+  fn name_before_paren_is_a_call() {
+    assert_eq!(parse("sqrt(4)"), Ok(AST::Call("sqrt".to_owned(), vec![AST::Num("4".to_owned())])));
+    assert_eq!(eval("sqrt(4)").unwrap(), 2);
+  }
+
+  #[test]
+  fn number_after_a_name_or_paren_multiplies() {
+    assert_eq!(parse("(2)3"), Ok(AST::Times(num("2"), num("3"))));
+    assert_eq!(eval("(2)3").unwrap(), 6);
+    assert_eq!(parse("x 2"), Ok(AST::Times(var("x"), num("2"))));
+    assert_eq!(parse("2x 3"), Ok(AST::Times(Box::new(AST::Times(num("2"), var("x"))), num("3"))));
+    assert_eq!(eval("max(1, 2) 5").unwrap(), 10);
+    assert_eq!(eval("(1 + 2)4^2").unwrap(), 48);
+  }
+
+  #[test]
+  fn juxtaposed_number_is_a_missing_separator() {
+    let span = Span { line : 1, col : 5 };
+    assert_eq!(parse("2+3 4+5"),
+               Err(ParseError::UnexpectedToken { expected : Token::Semi, found : Token::Num("4".to_owned()), span }));
+    assert!(parse("2 3").is_err());
+    assert!(parse("x 2 3").is_err());
+    assert!(parse("|2| 3").is_err());
+    assert!(parse("3! 2").is_err());
+    let span = Span { line : 2, col : 1 };
+    assert_eq!(checked_parse_all("(x)\n2"),
+               Err(ParseError::TrailingTokens(Token::Num("2".to_owned()), span)));
+  }
+
+  #[test]
+  fn juxtaposition_stops_at_a_newline() {
+    let span = Span { line : 2, col : 1 };
+    assert_eq!(checked_parse_all("x\n(y)"),
+               Err(ParseError::TrailingTokens(Token::LParen, span)));
+    assert_eq!(checked_parse_all("x;\n(y)"), Ok(vec![*var("x"), *var("y")]));
+  }
+
+  #[test]
+  fn div_and_mod_are_operators() {
+    assert_eq!(parse("7 div 2"), Ok(AST::IntDivide(num("7"), num("2"))));
+    assert_eq!(parse("7 mod 2"), Ok(AST::Modulo(num("7"), num("2"))));
+    assert_eq!(eval("7 div 2").ok(), Some(3));
+    assert_eq!(eval("-7 div 2").ok(), Some(-3));
+    assert_eq!(eval("7 mod 2").ok(), Some(1));
+    assert_eq!(eval_f64("7.5 div 2").ok(), Some(3.0));
+    assert_eq!(eval_f64("-7.5 div 2").ok(), Some(-3.0));
+    assert!(eval("1 div 0").is_err());
+    let a = parse("(1 + 7) div 2 * x").unwrap();
+    assert_eq!(a.to_string(), "(1 + 7) div 2 * x");
+    assert_eq!(parse(&a.to_string()), Ok(a.clone()));
+    assert_eq!(from_sexpr(&a.to_sexpr()), Ok(a));
+    let a = parse("7 div 2").unwrap();
+    assert_eq!(a.to_sexpr(), "(div 7 2)");
+    let rpn : Vec<String> = to_rpn(&a).iter().map(Token::to_string).collect();
+    assert_eq!(eval_rpn(&rpn.join(" ")).ok(), Some(3));
+    assert!(parse("div = 1").is_err());
+    assert!(parse("mod").is_err());
+  }
+
+  #[test]
+  fn chains_do_not_count_as_nesting() {
+    let sum = vec!["1"; 300].join("+");
+    assert_eq!(eval(&sum).ok(), Some(300));
+    let product = vec!["2"; 300].join(" * ");
+    assert!(parse(&product).is_ok());
+    assert!(parse(&format!("3{}", "!".repeat(300))).is_ok());
+    assert_eq!(depth(&parse(&format!("3{}", "%".repeat(300))).unwrap()), 301);
+  }
+
+  #[test]
+  fn nesting_is_limited() {
+    let parens = format!("{}1{}", "(".repeat(MAX_NESTING), ")".repeat(MAX_NESTING));
+    assert!(matches!(parse(&parens), Err(ParseError::DepthLimitExceeded(_))));
+    let parens = format!("{}1{}", "(".repeat(100), ")".repeat(100));
+    assert_eq!(eval(&parens).ok(), Some(1));
+    let negated = format!("{}1", "-".repeat(MAX_NESTING));
+    assert!(matches!(parse(&negated), Err(ParseError::DepthLimitExceeded(_))));
+    let powers = vec!["1"; MAX_NESTING + 1].join("^");
+    assert!(matches!(parse(&powers), Err(ParseError::DepthLimitExceeded(_))));
+  }
+
+  #[test]
+  fn trees_are_limited_in_depth() {
+    let sum = vec!["1"; MAX_DEPTH].join("+");
+    assert_eq!(depth(&parse(&sum).unwrap()), MAX_DEPTH);
+    assert_eq!(eval(&sum).ok(), Some(MAX_DEPTH as i32));
+    let sum = vec!["1"; MAX_DEPTH + 1].join("+");
+    assert!(matches!(parse(&sum), Err(ParseError::DepthLimitExceeded(_))));
+    let factorials = format!("3{}", "!".repeat(MAX_DEPTH));
+    assert!(matches!(parse(&factorials), Err(ParseError::DepthLimitExceeded(_))));
+  }
+
+  #[test]
+  fn power_is_right_associative() {
+    assert_eq!(parse("2 ^ 3 ^ 2"),
+               Ok(AST::Pow(num("2"), Box::new(AST::Pow(num("3"), num("2"))))));
+    assert_eq!(eval("2 ^ 3 ^ 2").ok(), Some(512));
+    assert_eq!(eval("2 * 3 ^ 2").ok(), Some(18));
+    assert_eq!(eval("(2 ^ 3) ^ 2").ok(), Some(64));
+    assert_eq!(eval("-2 ^ 2").ok(), Some(-4));
+    assert_eq!(eval("2 ^ 0").ok(), Some(1));
+    assert!(matches!(eval("2 ^ -1"), Err(CalcError::Eval(EvalError::NegativeExponent))));
+  }
+
+  #[test]
+  fn modulo_binds_like_times() {
+    assert_eq!(parse("1 + 7 % 3"),
+               Ok(AST::Plus(num("1"), Box::new(AST::Modulo(num("7"), num("3"))))));
+    assert_eq!(parse("7 % 3 * 2"),
+               Ok(AST::Times(Box::new(AST::Modulo(num("7"), num("3"))), num("2"))));
+    assert_eq!(eval("7 % 3 * 2").ok(), Some(2));
+    assert_eq!(eval("-7 % 3").ok(), Some(-1));
+    assert_eq!(eval("7 % 7").ok(), Some(0));
+    assert!(matches!(eval("7 % 0"), Err(CalcError::Eval(EvalError::DivideByZero))));
+  }
+
+  #[test]
+  fn unary_minus() {
+    assert_eq!(parse("-x"), Ok(AST::Neg(var("x"))));
+    assert_eq!(parse("3*-2"), Ok(AST::Times(num("3"), Box::new(AST::Neg(num("2"))))));
+    assert_eq!(eval("-5;").ok(), Some(-5));
+    assert_eq!(eval("3*-2;").ok(), Some(-6));
+    assert_eq!(eval("-(1+2);").ok(), Some(-3));
+    assert_eq!(eval("--3").ok(), Some(3));
+    assert_eq!(eval("2 - -3").ok(), Some(5));
+    assert_eq!(eval("-2^2").ok(), Some(-4));
+    assert_eq!(eval("2^-0").ok(), Some(1));
+  }
+
+  #[test]
+  fn unary_plus() {
+    assert_eq!(parse("+5"), Ok(*num("5")));
+    assert_eq!(parse("+x"), Ok(*var("x")));
+    assert_eq!(eval("+5").ok(), Some(5));
+    assert_eq!(eval("+(2+3)").ok(), Some(5));
+    assert_eq!(eval("+-+3").ok(), Some(-3));
+    assert_eq!(eval("2 + +3").ok(), Some(5));
+    assert_eq!(eval("2 * +-3").ok(), Some(-6));
+    assert_eq!(parse("+"), Err(ParseError::UnexpectedEof));
+  }
+
+  // What Parser::statements makes of the input
+  fn statements(input : &str) -> Result<Vec<AST>, ParseError> {
+    let mut lexer = Lexer::from_string(input);
+    Parser::new(&mut lexer)?.statements()
+  }
+
+  #[test]
+  fn statements_on_one_line() {
+    assert_eq!(statements("1+2; 3*4; 10-1;"), Ok(vec![
+      AST::Plus(num("1"), num("2")),
+      AST::Times(num("3"), num("4")),
+      AST::Minus(num("10"), num("1"))
+    ]));
+    assert_eq!(statements("1; 2"), Ok(vec![*num("1"), *num("2")]));
+    assert_eq!(statements("1;   # done"), Ok(vec![*num("1")]));
+    assert_eq!(statements("1; +"), Err(ParseError::UnexpectedEof));
+  }
+
+  #[test]
+  fn statements_stop_at_the_end_of_a_line() {
+    let mut lexer = Lexer::from_string("1; 2;\n3;\n");
+    let mut parser = Parser::new(&mut lexer).unwrap();
+    assert_eq!(parser.statements(), Ok(vec![*num("1"), *num("2")]));
+    let mut parser = Parser::new(&mut lexer).unwrap();
+    assert_eq!(parser.statements(), Ok(vec![*num("3")]));
+    assert!(Parser::new(&mut lexer).unwrap().at_end());
+  }
+
+  #[test]
+  fn hex_literals_evaluate() {
+    assert_eq!(eval("0xFF;").ok(), Some(255));
+    assert_eq!(eval("0xfF + 0x0").ok(), Some(255));
+    assert_eq!(eval("0x7fffffff").ok(), Some(i32::MAX));
+    assert!(matches!(eval("0x80000000"), Err(CalcError::Eval(EvalError::Overflow))));
+    assert_eq!(int_digits("0XaBc"), Some("2748".to_owned()));
+  }
+
+  #[test]
+  fn binary_literals_evaluate() {
+    assert_eq!(eval("0b1111;").ok(), Some(15));
+    assert_eq!(eval("0B1010").ok(), Some(10));
+    assert_eq!(eval("0b0").ok(), Some(0));
+    assert!(matches!(eval("0b2"), Err(CalcError::Lex(LexError::InvalidLiteral { .. }))));
+    assert!(matches!(eval("0b12"), Err(CalcError::Lex(LexError::UnexpectedChar { found : '2', .. }))));
+    assert!(matches!(eval("0b"), Err(CalcError::Lex(LexError::InvalidLiteral { .. }))));
+  }
+
+  #[test]
+  fn display_adds_only_the_parentheses_needed() {
+    let shown = |input : &str| parse(input).unwrap().to_string();
+    assert_eq!(shown("(1+2)*3"), "(1 + 2) * 3");
+    assert_eq!(shown("((1 + 2) + 3)"), "1 + 2 + 3");
+    assert_eq!(shown("1 + (2 + 3)"), "1 + (2 + 3)");
+    assert_eq!(shown("1 - (2 - 3)"), "1 - (2 - 3)");
+    assert_eq!(shown("2 ^ (3 ^ 2)"), "2 ^ 3 ^ 2");
+    assert_eq!(shown("(2 ^ 3) ^ 2"), "(2 ^ 3) ^ 2");
+    assert_eq!(shown("(-2) ^ 2"), "(-2) ^ 2");
+    assert_eq!(shown("-(2 ^ 2)"), "-2 ^ 2");
+  }
+
+  #[test]
+  fn display_parses_back_to_the_same_tree() {
+    let inputs = [
+      "1 + 2 * 3", "(1 + 2) * 3", "10 - (4 - 3)", "2 ^ 3 ^ 2", "(2 ^ 3) ^ 2", "-(1 + 2)",
+      "--3", "-2 ^ 2", "(-2) ^ 2", "7 % 3 * 2", "7 % (3 * 2)", "x = 2 * y",
+      "1 < 2 == 3 >= 4", "1 | 2 & 3", "(1 | 2) & 3", "1 << 2 + 3", "(1 << 2) + 3",
+      "1 ? 2 : 3 ? 4 : 5", "(1 ? 2 : 3) ? 4 : 5", "max(1, 2 + 3) * 4!", "(2 + 3)!",
+      "|1 - 5| * 2", "1.5 * 2", "3 - -2", "-2147483648", "10 div 3 mod 2"
+    ];
+    for input in inputs.iter() {
+      let a = parse(input).unwrap();
+      let b = parse(&a.to_string());
+      assert_eq!(b.as_ref(), Ok(&a), "{} printed as {}", input, a);
+      let mut env = HashMap::new();
+      env.insert("y".to_owned(), 5);
+      assert_eq!(eval_with(input, &env).ok(), eval_with(&a.to_string(), &env).ok());
+    }
+  }
+
+  #[test]
+  fn pi_and_e_are_float_constants() {
+    assert_eq!(eval_f64("pi").map(|x| format!("{:.5}", x)).ok(), Some("3.14159".to_owned()));
+    assert_eq!(eval_f64("pi * 2").ok(), Some(consts::PI * 2.0));
+    assert_eq!(eval_f64("e + 1").ok(), Some(consts::E + 1.0));
+    // Only the names themselves, and they cannot be assigned
+    assert_eq!(parse("pie"), Ok(AST::Var("pie".to_owned())));
+    assert!(parse("pi = 3").is_err());
+  }
+
+  #[test]
+  fn errors_carry_their_position() {
+    let span = |input : &str| parse(input).unwrap_err().span();
+    assert_eq!(span("1 +\n* 2"), Some(Span { line : 2, col : 1 }));
+    assert_eq!(span("1 + )"), Some(Span { line : 1, col : 5 }));
+    assert_eq!(span("1 +\n  2 $"), Some(Span { line : 2, col : 5 }));
+    assert_eq!(span("\n\n  @"), Some(Span { line : 3, col : 3 }));
+    // Columns count characters, not bytes
+    assert_eq!(span("é + )"), Some(Span { line : 1, col : 5 }));
+  }
+
+  #[test]
+  fn bars_take_the_absolute_value() {
+    let abs = |x| AST::Abs(Box::new(x));
+    assert_eq!(parse("|-5|"), Ok(abs(AST::Neg(num("5")))));
+    assert_eq!(eval("|-5|").ok(), Some(5));
+    assert_eq!(eval("|5|").ok(), Some(5));
+    // A | where an operand belongs opens bars, and elsewhere closes them
+    assert_eq!(parse("|1 + |(-3)||"), Ok(abs(AST::Plus(num("1"), Box::new(abs(AST::Neg(num("3"))))))));
+    assert_eq!(eval("|1 + |-3||").ok(), Some(4));
+    assert_eq!(eval("||-2| - 5|").ok(), Some(3));
+    // Outside bars, or between them with parentheses, | is still or
+    assert_eq!(parse("|2| | 1"), Ok(AST::BitOr(Box::new(abs(*num("2"))), num("1"))));
+    assert_eq!(eval("|(1 | 2)|").ok(), Some(3));
+    assert_eq!(parse("|3"), Err(ParseError::UnexpectedEof));
+    assert_eq!(parse("|1 - |2|"), Err(ParseError::UnexpectedEof));
+  }
+
+  fn with_table(input : &str, table : PrecedenceTable) -> Result<AST, ParseError> {
+    let mut lexer = Lexer::from_string(input);
+    Parser::with_precedence(&mut lexer, table)?.program()
+  }
+
+  #[test]
+  fn precedence_can_be_changed() {
+    let mut table = PrecedenceTable::default();
+    assert_eq!(table.get(&Token::Plus), Some((6, Assoc::Left)));
+    assert_eq!(table.get(&Token::Times), Some((7, Assoc::Left)));
+    assert_eq!(with_table("2 + 3 * 4", table.clone()).map(|a| a.to_string()), Ok("2 + 3 * 4".to_owned()));
+    table.set(Token::Plus, 7, Assoc::Left);
+    table.set(Token::Times, 6, Assoc::Left);
+    let a = with_table("2 + 3 * 4", table.clone()).unwrap();
+    assert_eq!(a, AST::Times(Box::new(AST::Plus(num("2"), num("3"))), num("4")));
+    assert_eq!(evaluate_iterative(&a, &mut HashMap::new(), &EvalConfig::default()), Ok(20));
+    // Trees print with the default precedence
+    assert_eq!(a.to_string(), "(2 + 3) * 4");
+  }
+
+  #[test]
+  fn associativity_can_be_changed() {
+    let mut table = PrecedenceTable::default();
+    table.set(Token::Caret, 9, Assoc::Left);
+    assert_eq!(with_table("2 ^ 3 ^ 2", table.clone()).map(|a| a.to_string()), Ok("(2 ^ 3) ^ 2".to_owned()));
+    table.set(Token::Minus, 6, Assoc::Right);
+    assert_eq!(with_table("1 - 2 - 3", table).map(|a| a.to_string()), Ok("1 - (2 - 3)".to_owned()));
+  }
+
+  #[test]
+  fn only_binary_operators_have_precedence() {
+    let mut table = PrecedenceTable::default();
+    table.set(Token::LParen, 3, Assoc::Left);
+    table.set(Token::Question, 3, Assoc::Left);
+    assert_eq!(table.get(&Token::LParen), None);
+    assert_eq!(table.get(&Token::Question), None);
+    assert_eq!(table.get(&Token::Num("1".to_owned())), None);
+  }
+
+  #[test]
+  fn errors_print_as_messages() {
+    let span = Span { line : 1, col : 1 };
+    let e = ParseError::UnexpectedToken { expected : Token::RParen, found : Token::Plus, span };
+    assert_eq!(e.to_string(), "expected ')', found '+'");
+    assert_eq!(ParseError::UnexpectedEof.to_string(), "unexpected end of input");
+    assert_eq!(ParseError::BadFactor(Token::Times, span).to_string(), "expected an operand, found '*'");
+    assert_eq!(ParseError::TrailingTokens(Token::RParen, span).to_string(), "unexpected ')' after the statement");
+    assert_eq!(ParseError::DepthLimitExceeded(span).to_string(), "expression nested too deeply");
+    assert_eq!(ParseError::UnclosedParen(span).to_string(), "expected ')' before the end of input");
+    let e = LexError::UnexpectedChar { found : '$', span };
+    assert_eq!(ParseError::Lex(e.clone()).to_string(), e.to_string());
+  }
+
+  #[test]
+  fn power_associativity_can_be_configured() {
+    let value = |input : &str, table| {
+      let a = with_table(input, table).unwrap();
+      evaluate_iterative::<i32>(&a, &mut HashMap::new(), &EvalConfig::default())
+    };
+    let default = PrecedenceTable::default();
+    assert_eq!(default.get(&Token::Caret), Some((9, Assoc::Right)));
+    assert_eq!(value("2^3^2", default.clone()), Ok(512));
+    assert_eq!(value("2**3**2", default.clone()), Ok(512));
+    let mut left = default;
+    left.set(Token::Caret, 9, Assoc::Left);
+    assert_eq!(value("2^3^2", left.clone()), Ok(64));
+    assert_eq!(value("2**3**2", left.clone()), Ok(64));
+    // Other operators are unaffected
+    assert_eq!(value("2 * 3 ^ 2 ^ 1 - 1", left), Ok(17));
+  }
+
+  #[test]
+  fn unclosed_parentheses_are_reported_where_they_open() {
+    let unclosed = |line, col| Err(ParseError::UnclosedParen(Span { line, col }));
+    assert_eq!(parse("(1 + 2"), unclosed(1, 1));
+    assert_eq!(parse("((1)"), unclosed(1, 1));
+    assert_eq!(parse("f(1, 2"), unclosed(1, 2));
+    assert_eq!(parse("|(1"), unclosed(1, 2));
+    assert_eq!(parse("1 +\n  (2 *\n3"), unclosed(2, 3));
+    assert_eq!(parse("(1 + 2) * 3"), Ok(AST::Times(Box::new(AST::Plus(num("1"), num("2"))), num("3"))));
+  }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+
+use interpreter::{EvalConfig, EvalError, evaluate_iterative};
+use parser::AST;
+
+
+/**********************************************************************
+ * TABLES AND PLOTS
+ */
+
+/// Evaluates the tree once for each value of `var` from `start` to
+/// `end` inclusive, counting by `step`, which may be negative.  Each
+/// value is paired with its result, so a point where evaluation fails,
+/// like a division by zero, is an `Err` in its own row.  Any other
+/// variable is undefined.  The table is empty if `step` is zero or
+/// leads away from `end`.
+pub fn tabulate(a : &AST, var : &str, start : i32, end : i32,
+                step : i32) -> Vec<(i32, Result<i32, EvalError>)> {
+  let config = EvalConfig::default();
+  let mut rows = Vec::new();
+  let mut x = start;
+  while step != 0 && (if step > 0 { x <= end } else { x >= end }) {
+    let mut env = HashMap::new();
+    env.insert(var.to_owned(), x);
+    rows.push((x, evaluate_iterative(a, &mut env, &config)));
+    x = match x.checked_add(step) {
+      Some(x) => x,
+      None => break
+    };
+  }
+  rows
+}
+
+// Where v falls among n evenly spaced cells from lo to hi, if anywhere
+fn cell(v : f64, lo : f64, hi : f64, n : usize) -> Option<usize> {
+  let t = if n == 1 { 0.0 } else { (v - lo) / (hi - lo) * (n - 1) as f64 };
+  let i = t.round();
+  if i >= 0.0 && i < n as f64 {
+    Some(i as usize)
+  } else {
+    None
+  }
+}
+
+/// Draws the tree as a function of `var` on a grid of `height` lines of
+/// `width` characters each, with x increasing to the right and y
+/// upward.  Each column is evaluated once in floating point, at an x
+/// spread evenly across `xrange`, and gets a `*` in the row nearest its
+/// y.  Points that fail to evaluate or fall outside `yrange` are left
+/// blank.  A grid with no rows or no columns is the empty string.
+pub fn plot_ascii(a : &AST, var : &str, xrange : (f64, f64), yrange : (f64, f64),
+                  width : usize, height : usize) -> String {
+  if width == 0 || height == 0 {
+    return String::new();
+  }
+  let config = EvalConfig::default();
+  // The row of each column's point, counting down from the top
+  let points : Vec<Option<usize>> = (0..width).map(|col| {
+    let t = if width == 1 { 0.0 } else { col as f64 / (width - 1) as f64 };
+    let x = xrange.0 + (xrange.1 - xrange.0) * t;
+    let mut env = HashMap::new();
+    env.insert(var.to_owned(), x);
+    let y = evaluate_iterative::<f64>(a, &mut env, &config).ok().filter(|y| y.is_finite())?;
+    cell(y, yrange.0, yrange.1, height).map(|row| height - 1 - row)
+  }).collect();
+
+  let mut s = String::new();
+  for row in 0..height {
+    s.extend(points.iter().map(|&p| if p == Some(row) { '*' } else { ' ' }));
+    s.push('\n');
+  }
+  s
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  #[test]
+  fn plots_a_line() {
+    let a = parse("x").unwrap();
+    assert_eq!(plot_ascii(&a, "x", (0.0, 2.0), (0.0, 2.0), 3, 3), "  *\n * \n*  \n");
+  }
+
+  #[test]
+  fn leaves_failures_and_outliers_blank() {
+    let a = parse("1 / x").unwrap();
+    assert_eq!(plot_ascii(&a, "x", (0.0, 1.0), (0.0, 1.0), 2, 1), " *\n");
+    let a = parse("x * 10").unwrap();
+    assert_eq!(plot_ascii(&a, "x", (0.0, 1.0), (0.0, 1.0), 2, 2), "  \n* \n");
+  }
+
+  #[test]
+  fn empty_grid_is_empty() {
+    let a = parse("x").unwrap();
+    assert_eq!(plot_ascii(&a, "x", (0.0, 1.0), (0.0, 1.0), 4, 0), "");
+    assert_eq!(plot_ascii(&a, "x", (0.0, 1.0), (0.0, 1.0), 0, 4), "");
+    assert_eq!(plot_ascii(&a, "x", (0.0, 1.0), (0.0, 1.0), 1, 1), "*\n");
+  }
+
+  #[test]
+  fn tabulates_each_point() {
+    let a = parse("100 / x").unwrap();
+    assert_eq!(tabulate(&a, "x", -2, 2, 1), vec![(-2, Ok(-50)), (-1, Ok(-100)),
+                                                 (0, Err(EvalError::DivideByZero)),
+                                                 (1, Ok(100)), (2, Ok(50))]);
+    let a = parse("x * x").unwrap();
+    assert_eq!(tabulate(&a, "x", 3, -3, -3), vec![(3, Ok(9)), (0, Ok(0)), (-3, Ok(9))]);
+    assert_eq!(tabulate(&a, "x", 0, 5, 2), vec![(0, Ok(0)), (2, Ok(4)), (4, Ok(16))]);
+    let a = parse("x + y").unwrap();
+    assert_eq!(tabulate(&a, "x", 1, 1, 1), vec![(1, Err(EvalError::UndefinedVar("y".to_owned())))]);
+  }
+
+  #[test]
+  fn empty_tables() {
+    let a = parse("x").unwrap();
+    assert_eq!(tabulate(&a, "x", 0, 5, 0), vec![]);
+    assert_eq!(tabulate(&a, "x", 0, 5, -1), vec![]);
+    assert_eq!(tabulate(&a, "x", 5, 0, 1), vec![]);
+    // Stepping past the end of i32 stops the table rather than wrapping
+    assert_eq!(tabulate(&a, "x", i32::MAX - 1, i32::MAX, 2), vec![(i32::MAX - 1, Ok(i32::MAX - 1))]);
+  }
+}
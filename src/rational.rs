@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use num_rational::Rational64;
+use num_traits::{CheckedAdd, CheckedDiv, CheckedMul, CheckedSub, One, Zero};
+
+use interpreter::{EvalConfig, EvalError, evaluate_iterative};
+use number::Number;
+use parser::AST;
+
+
+/**********************************************************************
+ * RATIONALS
+ */
+
+/// Evaluates the tree in a fresh environment with exact fractions of
+/// 64-bit integers, so `1/3 + 1/3` is `2/3`.  Results are always in
+/// lowest terms, and print that way.
+pub fn evaluate_rational(a : &AST) -> Result<Rational64, EvalError> {
+  evaluate_iterative(a, &mut HashMap::new(), &EvalConfig::default())
+}
+
+// Raise to a power by repeated squaring, giving up on overflow
+fn checked_pow(mut b : Rational64, mut e : u32) -> Option<Rational64> {
+  let mut acc = Rational64::one();
+  while e > 0 {
+    if e & 1 == 1 {
+      acc = acc.checked_mul(&b)?;
+    }
+    e >>= 1;
+    if e > 0 {
+      b = b.checked_mul(&b)?;
+    }
+  }
+  Some(acc)
+}
+
+// The floor and ceiling of x, which has a positive denominator as every
+// Rational64 does.  Their own versions add the denominator to the
+// numerator first, which can overflow near the ends of the range.
+fn floor(x : &Rational64) -> Rational64 {
+  Rational64::from_integer(x.numer().div_euclid(*x.denom()))
+}
+
+fn ceil(x : &Rational64) -> Result<Rational64, EvalError> {
+  let q = x.numer().div_euclid(*x.denom());
+  if x.is_integer() {
+    return Ok(Rational64::from_integer(q));
+  }
+  q.checked_add(1).map(Rational64::from_integer).ok_or(EvalError::Overflow)
+}
+
+// The integer value of x, for the operations that only make sense on
+// whole numbers
+fn integral(x : &Rational64) -> Result<i64, EvalError> {
+  if !x.is_integer() {
+    return Err(EvalError::DomainError);
+  }
+  Ok(x.to_integer())
+}
+
+// Division is exact, so every DivMode gives the same quotient
+impl Number for Rational64 {
+  fn from_f64(x : f64) -> Result<Rational64, EvalError> {
+    Rational64::approximate_float(x).ok_or(EvalError::Overflow)
+  }
+
+  fn from_bool(b : bool) -> Rational64 {
+    Rational64::from_integer(b as i64)
+  }
+
+  fn add(self, y : Rational64) -> Result<Rational64, EvalError> {
+    self.checked_add(&y).ok_or(EvalError::Overflow)
+  }
+
+  fn sub(self, y : Rational64) -> Result<Rational64, EvalError> {
+    self.checked_sub(&y).ok_or(EvalError::Overflow)
+  }
+
+  fn mul(self, y : Rational64) -> Result<Rational64, EvalError> {
+    self.checked_mul(&y).ok_or(EvalError::Overflow)
+  }
+
+  fn div(self, y : Rational64) -> Result<Rational64, EvalError> {
+    if y.is_zero() {
+      return Err(EvalError::DivideByZero);
+    }
+    self.checked_div(&y).ok_or(EvalError::Overflow)
+  }
+
+  fn div_floor(self, y : Rational64) -> Result<Rational64, EvalError> {
+    Number::div(self, y)
+  }
+
+  fn div_ceil(self, y : Rational64) -> Result<Rational64, EvalError> {
+    Number::div(self, y)
+  }
+
+  // What is left after taking away the whole number of times y goes
+  // into self, truncated toward zero as with integers
+  fn rem(self, y : Rational64) -> Result<Rational64, EvalError> {
+    let q = Number::div(self, y)?.trunc();
+    self.checked_sub(&q.checked_mul(&y).ok_or(EvalError::Overflow)?).ok_or(EvalError::Overflow)
+  }
+
+  // A fractional exponent would usually give an irrational result, but
+  // a negative one is fine
+  fn pow(self, e : Rational64) -> Result<Rational64, EvalError> {
+    let e = integral(&e)?;
+    let b = if e < 0 {
+      if self.is_zero() {
+        return Err(EvalError::DivideByZero);
+      }
+      // The sign moves to the numerator, and the most negative one has
+      // no positive counterpart
+      if *self.numer() == i64::MIN {
+        return Err(EvalError::Overflow);
+      }
+      self.recip()
+    } else {
+      self
+    };
+    let e = u32::try_from(e.unsigned_abs()).map_err(|_| EvalError::Overflow)?;
+    checked_pow(b, e).ok_or(EvalError::Overflow)
+  }
+
+  fn neg(self) -> Result<Rational64, EvalError> {
+    Rational64::zero().checked_sub(&self).ok_or(EvalError::Overflow)
+  }
+
+  fn factorial(self) -> Result<Rational64, EvalError> {
+    integral(&self)?.factorial().map(Rational64::from_integer)
+  }
+
+  fn call(name : &str, args : &[Rational64], config : &EvalConfig) -> Result<Rational64, EvalError> {
+    match (name, args) {
+      // Only a fraction of two perfect squares has a rational root
+      ("sqrt", &[x]) => {
+        let root = |n : i64| -> Result<i64, EvalError> {
+          let r = i64::call("sqrt", &[n], config)?;
+          if r * r != n {
+            return Err(EvalError::DomainError);
+          }
+          Ok(r)
+        };
+        Ok(Rational64::new(root(*x.numer())?, root(*x.denom())?))
+      },
+      ("abs", &[x]) if x < Rational64::zero() => x.neg(),
+      ("abs", &[x]) => Ok(x),
+      ("signum", &[x]) | ("sign", &[x]) => Ok(Rational64::from_integer(x.numer().signum())),
+      ("pow", &[b, e]) => Number::pow(b, e),
+      // A negative remainder is one |y| short
+      ("emod", &[x, y]) => match Number::rem(x, y)? {
+        r if r >= Rational64::zero() => Ok(r),
+        r if y < Rational64::zero() => Number::sub(r, y),
+        r => Number::add(r, y)
+      },
+      ("floor", &[x]) => Ok(floor(&x)),
+      ("ceil", &[x]) => ceil(&x),
+      // Halfway cases round away from zero, as with floats
+      ("round", &[x]) => Ok(x.round()),
+      ("trunc", &[x]) => Ok(x.trunc()),
+      ("min", &[x, ref rest @ ..]) => Ok(rest.iter().fold(x, |a, &b| a.min(b))),
+      ("max", &[x, ref rest @ ..]) => Ok(rest.iter().fold(x, |a, &b| a.max(b))),
+      ("clamp", &[_, lo, hi]) if lo > hi => Err(EvalError::DomainError),
+      ("clamp", &[x, lo, hi]) => Ok(x.clamp(lo, hi)),
+      // rand gives whole numbers, as for integers, and isqrt takes them
+      ("gcd", _) | ("lcm", _) | ("rand", _) | ("isqrt", _) => {
+        let ints = args.iter().map(integral).collect::<Result<Vec<_>, _>>()?;
+        i64::call(name, &ints, config).map(Rational64::from_integer)
+      },
+      ("sqrt", _) | ("abs", _) | ("signum", _) | ("sign", _) | ("pow", _) | ("emod", _) | ("min", _)
+      | ("max", _) | ("clamp", _)
+      | ("floor", _) | ("ceil", _) | ("round", _) | ("trunc", _) =>
+        Err(EvalError::ArityMismatch(name.to_owned())),
+      _ => Err(EvalError::UnknownFunction(name.to_owned()))
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  fn eval(input : &str) -> Result<Rational64, EvalError> {
+    evaluate_rational(&parse(input).unwrap())
+  }
+
+  fn ratio(n : i64, d : i64) -> Result<Rational64, EvalError> {
+    Ok(Rational64::new(n, d))
+  }
+
+  #[test]
+  fn fractions_add_exactly() {
+    assert_eq!(eval("1/2 + 1/3"), ratio(5, 6));
+    assert_eq!(eval("1/3 + 1/3 + 1/3"), ratio(1, 1));
+    assert_eq!(eval("3/4 - 1/4"), ratio(1, 2));
+    assert_eq!(eval("(2/3) * (9/4)"), ratio(3, 2));
+  }
+
+  #[test]
+  fn results_print_reduced() {
+    assert_eq!(eval("1/3 + 1/3").unwrap().to_string(), "2/3");
+    assert_eq!(eval("1/2 + 1/3").unwrap().to_string(), "5/6");
+    assert_eq!(eval("2/4").unwrap().to_string(), "1/2");
+    assert_eq!(eval("6/3").unwrap().to_string(), "2");
+    assert_eq!(eval("-4/6").unwrap().to_string(), "-2/3");
+  }
+
+  #[test]
+  fn dividing_by_zero_is_an_error() {
+    assert_eq!(eval("1/0"), Err(EvalError::DivideByZero));
+    assert_eq!(eval("1/(1/2 - 1/2)"), Err(EvalError::DivideByZero));
+  }
+
+  #[test]
+  fn negative_exponent_takes_the_reciprocal() {
+    assert_eq!(eval("(2/3)^-2"), ratio(9, 4));
+    assert_eq!(eval("(-2)^-3"), ratio(-1, 8));
+    assert_eq!(eval("0^-1"), Err(EvalError::DivideByZero));
+  }
+
+  #[test]
+  fn rounding() {
+    assert_eq!(eval("floor(-7/2)"), ratio(-4, 1));
+    assert_eq!(eval("ceil(-7/2)"), ratio(-3, 1));
+    assert_eq!(eval("round(5/2)"), ratio(3, 1));
+    assert_eq!(eval("round(-5/2)"), ratio(-3, 1));
+    assert_eq!(eval("trunc(-7/2)"), ratio(-3, 1));
+    assert_eq!(eval("floor(4)"), ratio(4, 1));
+    assert_eq!(eval("ceil(4)"), ratio(4, 1));
+  }
+
+  #[test]
+  fn rounding_near_the_bounds() {
+    assert_eq!(eval("floor(-9223372036854775807/2)"), ratio(-4611686018427387904, 1));
+    assert_eq!(eval("floor(-9223372036854775808/3)"), ratio(-3074457345618258603, 1));
+    assert_eq!(eval("ceil(9223372036854775807/2)"), ratio(4611686018427387904, 1));
+    assert_eq!(eval("ceil(-9223372036854775808/3)"), ratio(-3074457345618258602, 1));
+    assert_eq!(eval("floor(-9223372036854775808)"), ratio(i64::MIN, 1));
+    assert_eq!(eval("ceil(9223372036854775807)"), ratio(i64::MAX, 1));
+  }
+
+  #[test]
+  fn reciprocal_of_most_negative_overflows() {
+    assert_eq!(eval("(-9223372036854775808)^-1"), Err(EvalError::Overflow));
+    assert_eq!(eval("(-9223372036854775807)^-1"), ratio(-1, 9223372036854775807));
+  }
+}
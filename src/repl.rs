@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+
+use lexer::{Lexer, LexError, Span};
+use parser::{Parser, ParseError, AST};
+use interpreter::{EvalConfig, EvalError, evaluate_f64, evaluate_iterative};
+use format::{Base, DEFAULT_PRECISION, format_float, format_result, format_timing, render_error};
+use {CalcError, parse};
+
+// Holds the result of the last successful statement
+const ANS : &str = "ans";
+
+
+/**********************************************************************
+ * REPL
+ */
+
+/// How the REPL prompts for input and presents results.
+#[derive(Clone,Debug)]
+pub struct ReplConfig {
+  /// Written before reading each line, `> ` by default
+  pub prompt : String,
+  /// Follow each result with how long its line took to parse and the
+  /// statement took to evaluate
+  pub time : bool,
+  /// The radix integer results and the history are printed in
+  pub base : Base,
+  /// How many decimals float results are rounded to
+  pub precision : usize
+}
+
+impl Default for ReplConfig {
+  fn default() -> ReplConfig {
+    ReplConfig {
+      prompt : "> ".to_owned(),
+      time : false,
+      base : Base::default(),
+      precision : DEFAULT_PRECISION
+    }
+  }
+}
+
+/// Prompts for, evaluates, and prints statements from `input` until it
+/// is exhausted.  Errors are reported on `output` and do not end the
+/// session; variables persist from one line to the next.  A line may
+/// hold several statements separated by `;`, and the last needs no `;`
+/// if the line ends where it could.  `ans` is the previous result, or 0
+/// before there is one, and cannot be assigned.  Like other variables
+/// it holds an integer, so a float result is truncated toward zero.
+///
+/// A line starting with `:` is a command rather than input:
+/// `:parse <expr>` prints the expression as it was parsed, and
+/// `:ast <expr>` prints its tree, without evaluating either.
+/// `:history` lists every statement evaluated so far with its result,
+/// numbered from 1, and a line `!n` evaluates the nth of them again.
+pub fn run_repl<R : BufRead + 'static, W : Write>(input : R, output : W) -> io::Result<()> {
+  run_repl_with(input, output, &ReplConfig::default())
+}
+
+/// Like `run_repl`, presenting results as `config` says.
+pub fn run_repl_with<R : BufRead + 'static, W : Write>(input : R, mut output : W,
+                                                       config : &ReplConfig) -> io::Result<()> {
+  let mut lexer = Lexer::from_reader(input);
+  let mut session = Session::new(config);
+
+  loop {
+    write!(output, "{}", config.prompt)?;
+    output.flush()?;
+
+    // Just pressing enter prompts again
+    if lexer.skip_blank_line() {
+      continue;
+    }
+
+    if let Some(command) = lexer.take_command(':') {
+      session.command(&command, &mut output)?;
+      continue;
+    }
+    if let Some(n) = lexer.take_command('!') {
+      session.rerun(&n, &mut output)?;
+      continue;
+    }
+
+    let start = Instant::now();
+    let statements = match Parser::new(&mut lexer) {
+      Ok(ref parser) if parser.at_end() => break,
+      Ok(mut parser) => { parser.newline_terminates();
+                          parser.statements() },
+      Err(e) => Err(e)
+    };
+    let parse_time = start.elapsed();
+    match statements {
+      Ok(statements) => {
+        for expression in statements {
+          session.run(&expression, parse_time, &mut output)?;
+        }
+      },
+      // The input ran out partway through a statement
+      Err(ParseError::UnexpectedEof) => break,
+      // Nothing more can be read after this
+      Err(e @ ParseError::Lex(LexError::Read(_))) => { writeln!(output, "Error: {}", e)?;
+                                                       break },
+      // Nothing else on the line runs, since it may depend on what failed
+      Err(e) => { let line = e.span().and_then(|span| lexer.source_line(span.line));
+                  writeln!(output, "{}", syntax_error(&e, line))?;
+                  lexer.skip_line(); }
+    }
+  }
+
+  writeln!(output)
+}
+
+// Everything the REPL remembers from one line to the next
+struct Session {
+  env : HashMap<String, i32>,
+  config : EvalConfig,
+  // Each statement evaluated, as it prints, with its result as it was
+  // shown
+  history : Vec<(String, Result<String, CalcError>)>,
+  // How results are shown
+  shown : ReplConfig
+}
+
+impl Session {
+  fn new(shown : &ReplConfig) -> Session {
+    let mut env = HashMap::new();
+    env.insert(ANS.to_owned(), 0);
+    Session {
+      env,
+      config : EvalConfig::default(),
+      history : Vec::new(),
+      shown : shown.clone()
+    }
+  }
+
+  // Evaluate a statement that took parse_time to parse, print its
+  // result, and record it in the history
+  fn run<W : Write>(&mut self, expression : &AST, parse_time : Duration,
+                    output : &mut W) -> io::Result<()> {
+    let start = Instant::now();
+    let result = self.evaluate(expression);
+    let eval_time = start.elapsed();
+    match result {
+      Ok((ref shown, n)) if self.shown.time => { writeln!(output, "{} {}", shown, format_timing(parse_time, eval_time))?;
+                                                 self.env.insert(ANS.to_owned(), n); },
+      Ok((ref shown, n)) => { writeln!(output, "{}", shown)?;
+                              self.env.insert(ANS.to_owned(), n); },
+      Err(ref e) => writeln!(output, "Error: {}", e)?
+    }
+    self.history.push((expression.to_string(), result.map(|(shown, _)| shown).map_err(CalcError::from)));
+    Ok(())
+  }
+
+  // The result of a statement as printed, and as stored in ans
+  fn evaluate(&mut self, expression : &AST) -> Result<(String, i32), EvalError> {
+    if let AST::Assign(ref name, _) = *expression {
+      if name == ANS {
+        return Err(EvalError::ReadOnlyVar(name.clone()));
+      }
+    }
+    if expression.has_float() {
+      let n = evaluate_f64(expression, &mut self.env, &self.config)?;
+      Ok((format_float(n, self.shown.precision), n as i32))
+    } else {
+      let n = evaluate_iterative(expression, &mut self.env, &self.config)?;
+      Ok((format_result(n, self.shown.base), n))
+    }
+  }
+
+  // Evaluate the statement numbered n in the history once more
+  fn rerun<W : Write>(&mut self, n : &str, output : &mut W) -> io::Result<()> {
+    let entry = n.parse::<usize>().ok()
+      .and_then(|n| n.checked_sub(1))
+      .and_then(|i| self.history.get(i));
+    let start = Instant::now();
+    let expression = match entry.map(|entry| parse(&entry.0)) {
+      Some(Ok(a)) => a,
+      _ => return writeln!(output, "Error: no statement !{} in the history", n)
+    };
+    self.run(&expression, start.elapsed(), output)
+  }
+
+  // Carry out a line of the form `:<name> <expr>`, given without the `:`
+  fn command<W : Write>(&self, command : &str, output : &mut W) -> io::Result<()> {
+    let (name, expr) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    if name == "history" {
+      for (i, (statement, result)) in self.history.iter().enumerate() {
+        match *result {
+          Ok(ref shown) => writeln!(output, "{}: {} => {}", i + 1, statement, shown)?,
+          Err(ref e) => writeln!(output, "{}: {} => Error: {}", i + 1, statement, e)?
+        }
+      }
+      return Ok(());
+    }
+    match (name, parse(expr)) {
+      ("parse", Ok(a)) => writeln!(output, "{}", a),
+      ("ast", Ok(a)) => writeln!(output, "{:?}", a),
+      ("parse", Err(e)) | ("ast", Err(e)) => writeln!(output, "{}", syntax_error(&e, Some(expr))),
+      _ => writeln!(output, "Commands:\n  :parse <expr>  print the expression as parsed\n  :ast <expr>    print its syntax tree\n  :history       list the statements so far\n  !n             evaluate statement n again")
+    }
+  }
+}
+
+// Report a parse error, under the line it was found in if that is known
+fn syntax_error(e : &ParseError, line : Option<&str>) -> String {
+  let msg = format!("Syntax error: {}", e);
+  match (e.span(), line) {
+    (Some(span), Some(line)) => render_error(line, Span { line : 1, ..span }, &msg),
+    _ => msg
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // What the REPL prints for the input, prompts and all
+  fn repl(input : &str) -> String {
+    let mut output = Vec::new();
+    run_repl(io::Cursor::new(input.to_owned()), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn reads_until_eof() {
+    assert_eq!(repl("1 + 2\n\n2 * 3\n"), "> 3\n> > 6\n> \n");
+    assert_eq!(repl(""), "> \n");
+  }
+
+  #[test]
+  fn errors_do_not_end_the_session() {
+    assert_eq!(repl("1 / 0\n4\n"), "> Error: division by zero\n> 4\n> \n");
+  }
+
+  // What the REPL prints when configured so
+  fn repl_with(input : &str, config : &ReplConfig) -> String {
+    let mut output = Vec::new();
+    run_repl_with(io::Cursor::new(input.to_owned()), &mut output, config).unwrap();
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn prints_in_the_configured_base() {
+    let config = ReplConfig { base : Base::Hex, prompt : String::new(), ..ReplConfig::default() };
+    assert_eq!(repl_with("255\n-16\n1.5\n:history\n", &config),
+               "0xff\n-0x10\n1.5\n1: 255 => 0xff\n2: -16 => -0x10\n3: 1.5 => 1.5\n\n");
+  }
+
+  #[test]
+  fn rounds_floats_to_the_configured_precision() {
+    let config = ReplConfig { precision : 2, prompt : String::new(), ..ReplConfig::default() };
+    assert_eq!(repl_with("pi\n1 / 3.0\n2.5\n", &config), "3.14\n0.33\n2.5\n\n");
+    assert_eq!(repl("pi\n"), "> 3.141593\n> \n");
+  }
+
+  #[test]
+  fn syntax_error_skips_the_rest_of_the_line() {
+    assert_eq!(repl("1 ) 5\n7\n"),
+               "> 1 ) 5\n  ^\nSyntax error: expected ';', found ')'\n> 7\n> \n");
+    let out = repl("x = 1 ) x = 99\nx\n");
+    assert!(!out.contains("99\n>"), "{}", out);
+    assert!(out.ends_with("> Error: undefined variable 'x'\n> \n"), "{}", out);
+  }
+
+  #[test]
+  fn blank_lines_only_prompt_again() {
+    assert_eq!(repl("\n"), "> > \n");
+    assert_eq!(repl("   \t\n\n1 + 1\n"), "> > > 2\n> \n");
+    assert_eq!(repl("# nothing\n3\n"), "> > 3\n> \n");
+    // Without a final newline too
+    assert_eq!(repl("\n  "), "> > > \n");
+  }
+
+  #[test]
+  fn ans_is_the_previous_result() {
+    assert_eq!(repl("ans\n2 + 3\nans * 2\n"), "> 0\n> 5\n> 10\n> \n");
+    // Errors leave it as it was, and floats are truncated
+    assert_eq!(repl("4\n1 / 0\nans\n2.9\nans\n"),
+               "> 4\n> Error: division by zero\n> 4\n> 2.9\n> 2\n> \n");
+    assert_eq!(repl("7\nans = 1\nans\n"),
+               "> 7\n> Error: variable 'ans' cannot be assigned\n> 7\n> \n");
+  }
+
+  #[test]
+  fn parse_and_ast_print_without_evaluating() {
+    assert_eq!(repl(":parse 1+2*3\n"), "> 1 + 2 * 3\n> \n");
+    assert_eq!(repl(":parse (1+2)*(3)\n"), "> (1 + 2) * 3\n> \n");
+    assert_eq!(repl(":parse x = 1/0\nx\n"), "> x = 1 / 0\n> Error: undefined variable 'x'\n> \n");
+    assert_eq!(repl(":ast 1+2\n"), "> Plus(Num(\"1\"), Num(\"2\"))\n> \n");
+    assert_eq!(repl(":parse 1 +\n"), "> Syntax error: unexpected end of input\n> \n");
+  }
+
+  #[test]
+  fn unknown_commands_print_help() {
+    let out = repl(":nope\n");
+    assert!(out.starts_with("> Commands:\n  :parse <expr>"), "{}", out);
+    assert!(out.contains(":history") && out.contains("!n"), "{}", out);
+    assert_eq!(repl(":\n"), out);
+  }
+
+  #[test]
+  fn history_lists_and_reruns_statements() {
+    let config = ReplConfig { prompt : String::new(), ..ReplConfig::default() };
+    assert_eq!(repl_with("x = 2\nx*3; 1/0\n:history\n", &config),
+               "2\n6\nError: division by zero\n\
+                1: x = 2 => 2\n2: x * 3 => 6\n3: 1 / 0 => Error: division by zero\n\n");
+    // Reruns see variables as they are now, and join the history
+    assert_eq!(repl_with("x = 2\nx * 3\nx = 5\n!2\n:history\n", &config),
+               "2\n6\n5\n15\n1: x = 2 => 2\n2: x * 3 => 6\n3: x = 5 => 5\n4: x * 3 => 15\n\n");
+    assert_eq!(repl_with(":history\n", &config), "\n");
+  }
+
+  #[test]
+  fn rerunning_what_is_not_there_is_an_error() {
+    let config = ReplConfig { prompt : String::new(), ..ReplConfig::default() };
+    assert_eq!(repl_with("1\n!2\n!0\n!x\n!1\n", &config),
+               "1\nError: no statement !2 in the history\nError: no statement !0 in the history\n\
+                Error: no statement !x in the history\n1\n\n");
+  }
+
+  #[test]
+  fn the_end_of_a_line_ends_a_statement() {
+    assert_eq!(repl("2 + 3\n"), "> 5\n> \n");
+    assert_eq!(repl("2 + 3;\n"), "> 5\n> \n");
+    assert_eq!(repl("1; 2\n3\n"), "> 1\n2\n> 3\n> \n");
+    // Unless the statement could not end there
+    assert_eq!(repl("1 +\n2\n"), "> 3\n> \n");
+    assert_eq!(repl("(1\n+ 2)\n4\n"), "> 3\n> 4\n> \n");
+  }
+
+  #[test]
+  fn unclosed_parenthesis_at_the_end_of_input() {
+    assert_eq!(repl("(1 + 2\n"), "> (1 + 2\n^\nSyntax error: expected ')' before the end of input\n> \n");
+    assert_eq!(repl("(1 +\n2)\n"), "> 3\n> \n");
+  }
+
+  #[test]
+  fn prompts_with_the_configured_prompt() {
+    let config = ReplConfig { prompt : "calc] ".to_owned(), ..ReplConfig::default() };
+    let mut output = Vec::new();
+    run_repl_with(io::Cursor::new("1 + 2\n\n3 $ 4\n".to_owned()), &mut output, &config).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.matches("calc] ").count(), 4, "{}", output);
+    assert!(output.starts_with("calc] 3\ncalc] calc] "), "{}", output);
+    assert!(output.ends_with("calc] \n"), "{}", output);
+    assert!(!output.contains("> "), "{}", output);
+    assert_eq!(ReplConfig::default().prompt, "> ");
+  }
+
+  #[test]
+  fn history_shows_results_as_they_printed() {
+    let config = ReplConfig { precision : 2, prompt : String::new(), ..ReplConfig::default() };
+    assert_eq!(repl_with("1.5\npi\n7 / 2\n:history\n", &config),
+               "1.5\n3.14\n3\n1: 1.5 => 1.5\n2: 3.141592653589793 => 3.14\n3: 7 / 2 => 3\n\n");
+  }
+}
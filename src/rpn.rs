@@ -0,0 +1,206 @@
+use CalcError;
+use interpreter::EvalError;
+use lexer::{Lexer, Token};
+use number::Number;
+use parser::{AST, ParseError, int_digits};
+
+
+/**********************************************************************
+ * REVERSE POLISH NOTATION
+ */
+
+/// Evaluates postfix input like `3 4 +`, where each operator pops its
+/// operands off a stack and pushes its result.  The conditional is
+/// written `c a b ?`.  The input must leave exactly one value on the
+/// stack.
+pub fn eval_rpn(input : &str) -> Result<i32, CalcError> {
+  let mut lexer = Lexer::from_string(input);
+  let mut stack : Vec<i32> = Vec::new();
+
+  while !lexer.at_eof() {
+    let tok = lexer.get_token()?;
+    let op : fn(i32, i32) -> Result<i32, EvalError> = match tok {
+      Token::Num(ref x) => {
+        let n = int_digits(x).ok_or_else(|| ParseError::BadFactor(tok.clone(), lexer.span()))?;
+        stack.push(n.parse().map_err(|_| EvalError::Overflow)?);
+        continue;
+      },
+      // Without a tree to skip over, both branches are already
+      // evaluated by the time `?` picks one
+      Token::Question => {
+        let y = stack.pop().ok_or(EvalError::NotEnoughOperands)?;
+        let x = stack.pop().ok_or(EvalError::NotEnoughOperands)?;
+        let c = stack.pop().ok_or(EvalError::NotEnoughOperands)?;
+        stack.push(if c != 0 { x } else { y });
+        continue;
+      },
+      Token::Bang => {
+        let x = stack.pop().ok_or(EvalError::NotEnoughOperands)?;
+        stack.push(x.factorial()?);
+        continue;
+      },
+      Token::Plus => Number::add,
+      Token::Minus => Number::sub,
+      Token::Times => Number::mul,
+      Token::Divide | Token::Div => Number::div,
+      Token::Percent => Number::rem,
+      Token::Caret => Number::pow,
+      Token::Amp => Number::bitand,
+      Token::Pipe => Number::bitor,
+      Token::Less => Number::less,
+      Token::Greater => Number::greater,
+      Token::LessEq => Number::less_eq,
+      Token::GreaterEq => Number::greater_eq,
+      Token::EqEq => Number::equal,
+      Token::NotEq => Number::not_equal,
+      Token::Shl => Number::shl,
+      Token::Shr => Number::shr,
+      tok => return Err(ParseError::BadFactor(tok, lexer.span()).into())
+    };
+    let y = stack.pop().ok_or(EvalError::NotEnoughOperands)?;
+    let x = stack.pop().ok_or(EvalError::NotEnoughOperands)?;
+    stack.push(op(x, y)?);
+  }
+
+  match stack.len() {
+    0 => Err(EvalError::NotEnoughOperands.into()),
+    1 => Ok(stack[0]),
+    _ => Err(EvalError::TooManyOperands.into())
+  }
+}
+
+/// Flattens a tree into the equivalent postfix token sequence, so that
+/// `(1+2)*3` becomes `1 2 + 3 *`.  Negation has no postfix token of its
+/// own and is written as subtraction from zero, and a percent as a
+/// division by 100.  Variables, calls, and
+/// assignments come out as `x`, `args.. f`, and `value x =`, which
+/// `eval_rpn` does not accept, and absolute value as a call to `abs`.
+pub fn to_rpn(a : &AST) -> Vec<Token> {
+  let mut out = Vec::new();
+  push_rpn(a, &mut out);
+  out
+}
+
+fn push_rpn(a : &AST, out : &mut Vec<Token>) {
+  match *a {
+    AST::Num(ref x) => out.push(Token::Num(x.clone())),
+    AST::FNum(_) => out.push(Token::Num(a.to_string())),
+    AST::Var(ref name) => out.push(Token::Ident(name.clone())),
+    AST::Plus(ref x, ref y) => push_binary(x, y, Token::Plus, out),
+    AST::Minus(ref x, ref y) => push_binary(x, y, Token::Minus, out),
+    AST::Times(ref x, ref y) => push_binary(x, y, Token::Times, out),
+    AST::Divide(ref x, ref y) => push_binary(x, y, Token::Divide, out),
+    AST::IntDivide(ref x, ref y) => push_binary(x, y, Token::Div, out),
+    AST::Modulo(ref x, ref y) => push_binary(x, y, Token::Percent, out),
+    AST::Pow(ref x, ref y) => push_binary(x, y, Token::Caret, out),
+    AST::BitAnd(ref x, ref y) => push_binary(x, y, Token::Amp, out),
+    AST::BitOr(ref x, ref y) => push_binary(x, y, Token::Pipe, out),
+    AST::Less(ref x, ref y) => push_binary(x, y, Token::Less, out),
+    AST::Greater(ref x, ref y) => push_binary(x, y, Token::Greater, out),
+    AST::LessEq(ref x, ref y) => push_binary(x, y, Token::LessEq, out),
+    AST::GreaterEq(ref x, ref y) => push_binary(x, y, Token::GreaterEq, out),
+    AST::Equal(ref x, ref y) => push_binary(x, y, Token::EqEq, out),
+    AST::NotEqual(ref x, ref y) => push_binary(x, y, Token::NotEq, out),
+    AST::Shl(ref x, ref y) => push_binary(x, y, Token::Shl, out),
+    AST::Shr(ref x, ref y) => push_binary(x, y, Token::Shr, out),
+    AST::Neg(ref x) => { out.push(Token::Num("0".to_owned()));
+                         push_rpn(x, out);
+                         out.push(Token::Minus) },
+    AST::Factorial(ref x) => { push_rpn(x, out);
+                               out.push(Token::Bang) },
+    AST::Percent(ref x) => { push_rpn(x, out);
+                             out.push(Token::Num("100".to_owned()));
+                             out.push(Token::Divide) },
+    AST::Abs(ref x) => { push_rpn(x, out);
+                         out.push(Token::Ident("abs".to_owned())) },
+    AST::Assign(ref name, ref x) => { push_rpn(x, out);
+                                      out.push(Token::Ident(name.clone()));
+                                      out.push(Token::Equals) },
+    AST::Cond(ref c, ref x, ref y) => { push_rpn(c, out);
+                                        push_binary(x, y, Token::Question, out) },
+    AST::Call(ref name, ref args) => { for arg in args {
+                                         push_rpn(arg, out);
+                                       }
+                                       out.push(Token::Ident(name.clone())) },
+    AST::Sum(ref xs) => push_chain(xs, Token::Plus, "0", out),
+    AST::Product(ref xs) => push_chain(xs, Token::Times, "1", out)
+  }
+}
+
+// Push a chain of op over xs as nested binary nodes would be pushed, or
+// the literal empty if there are none
+fn push_chain(xs : &[AST], op : Token, empty : &str, out : &mut Vec<Token>) {
+  match xs.split_first() {
+    Some((first, rest)) => { push_rpn(first, out);
+                             for x in rest {
+                               push_rpn(x, out);
+                               out.push(op.clone());
+                             } },
+    None => out.push(Token::Num(empty.to_owned()))
+  }
+}
+
+fn push_binary(x : &AST, y : &AST, op : Token, out : &mut Vec<Token>) {
+  push_rpn(x, out);
+  push_rpn(y, out);
+  out.push(op);
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {eval, parse};
+
+  fn rpn_error(input : &str) -> Option<EvalError> {
+    match eval_rpn(input) {
+      Err(CalcError::Eval(e)) => Some(e),
+      _ => None
+    }
+  }
+
+  #[test]
+  fn evaluates_postfix() {
+    assert_eq!(eval_rpn("3 4 +").ok(), Some(7));
+    assert_eq!(eval_rpn("5 1 2 + 4 * + 3 -").ok(), Some(14));
+    assert_eq!(eval_rpn("10 3 -").ok(), Some(7));
+    assert_eq!(eval_rpn("2 3 ^ 3 ! -").ok(), Some(2));
+    assert_eq!(eval_rpn("1 5 6 ?").ok(), Some(5));
+    assert_eq!(eval_rpn("42").ok(), Some(42));
+  }
+
+  #[test]
+  fn operands_must_balance() {
+    assert_eq!(rpn_error(""), Some(EvalError::NotEnoughOperands));
+    assert_eq!(rpn_error("+"), Some(EvalError::NotEnoughOperands));
+    assert_eq!(rpn_error("1 +"), Some(EvalError::NotEnoughOperands));
+    assert_eq!(rpn_error("1 2"), Some(EvalError::TooManyOperands));
+    assert_eq!(rpn_error("1 0 /"), Some(EvalError::DivideByZero));
+    assert!(matches!(eval_rpn("1 2 ("), Err(CalcError::Parse(_))));
+  }
+
+  // The postfix form of the input, spelled out
+  fn rpn(input : &str) -> String {
+    let tokens : Vec<String> = to_rpn(&parse(input).unwrap()).iter().map(Token::to_string).collect();
+    tokens.join(" ")
+  }
+
+  #[test]
+  fn flattens_trees_to_postfix() {
+    assert_eq!(to_rpn(&parse("(1+2)*3").unwrap()),
+               vec![Token::Num("1".to_owned()), Token::Num("2".to_owned()), Token::Plus,
+                    Token::Num("3".to_owned()), Token::Times]);
+    assert_eq!(rpn("1 + 2 * 3"), "1 2 3 * +");
+    assert_eq!(rpn("1 - 2 - 3"), "1 2 - 3 -");
+    assert_eq!(rpn("2 ^ 3 ^ 2"), "2 3 2 ^ ^");
+    assert_eq!(rpn("1 < 2 & 3 | 4"), "1 2 < 3 & 4 |");
+    assert_eq!(rpn("-x!"), "0 x ! -");
+  }
+
+  #[test]
+  fn postfix_evaluates_as_the_tree_does() {
+    for input in &["1 + 2 * 3", "(1 + 2) * 3", "2 ^ 3 ^ 2", "-7 % 3", "1 ? 2 : 3", "7 div 2 << 1"] {
+      assert_eq!(eval_rpn(&rpn(input)).ok(), eval(input).ok(), "{}", input);
+    }
+  }
+}
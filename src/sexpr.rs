@@ -0,0 +1,223 @@
+use std::f64::consts;
+
+use lexer::{Lexer, Span, Token};
+use parser::{AST, MAX_NESTING, ParseError, int_digits};
+use transform::unflatten;
+
+
+/**********************************************************************
+ * S-EXPRESSIONS
+ */
+
+impl AST {
+  /// Renders the tree as an S-expression, so `1 + 2*3` becomes
+  /// `(+ 1 (* 2 3))`.  Each operator is written as its infix token
+  /// ahead of its operands: negation is `(- x)`, a percent `(% x)`,
+  /// absolute value `(| x)`, a conditional `(? c a b)`, and an
+  /// assignment `(= x value)`.  A call is `(f args..)`.  A `Sum` or
+  /// `Product` is written as `unflatten` would rebuild it.
+  pub fn to_sexpr(&self) -> String {
+    let head = match *self {
+      AST::Num(ref x) | AST::Var(ref x) => return x.clone(),
+      AST::FNum(_) => return self.to_string(),
+      AST::Assign(ref name, ref x) => return format!("(= {} {})", name, x.to_sexpr()),
+      AST::Sum(_) | AST::Product(_) => return unflatten(self).to_sexpr(),
+      AST::Plus(..) => "+",
+      AST::Minus(..) | AST::Neg(_) => "-",
+      AST::Times(..) => "*",
+      AST::Divide(..) => "/",
+      AST::IntDivide(..) => "div",
+      AST::Modulo(..) | AST::Percent(_) => "%",
+      AST::Pow(..) => "^",
+      AST::BitAnd(..) => "&",
+      AST::BitOr(..) | AST::Abs(_) => "|",
+      AST::Less(..) => "<",
+      AST::Greater(..) => ">",
+      AST::LessEq(..) => "<=",
+      AST::GreaterEq(..) => ">=",
+      AST::Equal(..) => "==",
+      AST::NotEqual(..) => "!=",
+      AST::Shl(..) => "<<",
+      AST::Shr(..) => ">>",
+      AST::Factorial(_) => "!",
+      AST::Cond(..) => "?",
+      AST::Call(ref name, _) => name
+    };
+    let mut s = format!("({}", head);
+    for x in self.children() {
+      s.push(' ');
+      s.push_str(&x.to_sexpr());
+    }
+    s.push(')');
+    s
+  }
+}
+
+/// Reads an S-expression in the form `AST::to_sexpr` writes back into a
+/// tree.  Atoms are read as tokens of infix input, so `0x1f`, `1_000`,
+/// and `pi` mean the same here, and a `-` just before a literal makes
+/// it negative.
+pub fn from_sexpr(input : &str) -> Result<AST, ParseError> {
+  let mut lexer = Lexer::from_string(input);
+  let a = read(&mut lexer, 0)?;
+  if !lexer.at_eof() {
+    let tok = lexer.get_token()?;
+    return Err(ParseError::TrailingTokens(tok, lexer.span()));
+  }
+  Ok(a)
+}
+
+// The next token, before which the input may not end
+fn token(lexer : &mut Lexer) -> Result<Token, ParseError> {
+  match lexer.get_token()? {
+    Token::Eof => Err(ParseError::UnexpectedEof),
+    tok => Ok(tok)
+  }
+}
+
+// An atom or a whole list, nested `depth` lists deep
+fn read(lexer : &mut Lexer, depth : usize) -> Result<AST, ParseError> {
+  let tok = token(lexer)?;
+  match tok {
+    Token::Num(_) => literal(&tok, lexer.span(), false),
+    Token::Minus => match token(lexer)? {
+      tok @ Token::Num(_) => literal(&tok, lexer.span(), true),
+      tok => Err(ParseError::BadFactor(tok, lexer.span()))
+    },
+    Token::Ident(ref x) if x == "pi" => Ok(AST::FNum(consts::PI)),
+    Token::Ident(ref x) if x == "e" => Ok(AST::FNum(consts::E)),
+    Token::Ident(x) => Ok(AST::Var(x)),
+    Token::LParen if depth == MAX_NESTING => Err(ParseError::DepthLimitExceeded(lexer.span())),
+    Token::LParen => list(lexer, depth + 1),
+    tok => Err(ParseError::BadFactor(tok, lexer.span()))
+  }
+}
+
+// A number token, as primary reads it
+fn literal(tok : &Token, span : Span, negative : bool) -> Result<AST, ParseError> {
+  let bad = || ParseError::BadFactor(tok.clone(), span);
+  let x = match *tok {
+    Token::Num(ref x) => x,
+    _ => return Err(bad())
+  };
+  if x.contains('.') {
+    let n = x.parse::<f64>().map_err(|_| bad())?;
+    Ok(AST::FNum(if negative { -n } else { n }))
+  } else {
+    let n = int_digits(x).ok_or_else(bad)?;
+    Ok(AST::Num(if negative { format!("-{}", n) } else { n }))
+  }
+}
+
+// The rest of a list once its ( has been read
+fn list(lexer : &mut Lexer, depth : usize) -> Result<AST, ParseError> {
+  let arg = |lexer : &mut Lexer| read(lexer, depth).map(Box::new);
+  let head = token(lexer)?;
+  let a = match head {
+    Token::Ident(name) => { let mut args = Vec::new();
+                            while *lexer.peek()? != Token::RParen {
+                              args.push(read(lexer, depth)?);
+                            }
+                            AST::Call(name, args) },
+    // One operand is a negation and two a subtraction, and likewise for
+    // percent and modulo, and absolute value and or
+    Token::Minus => { let x = arg(lexer)?;
+                      if *lexer.peek()? == Token::RParen {
+                        AST::Neg(x)
+                      } else {
+                        AST::Minus(x, arg(lexer)?)
+                      } },
+    Token::Pipe => { let x = arg(lexer)?;
+                     if *lexer.peek()? == Token::RParen {
+                       AST::Abs(x)
+                     } else {
+                       AST::BitOr(x, arg(lexer)?)
+                     } },
+    Token::Percent => { let x = arg(lexer)?;
+                        if *lexer.peek()? == Token::RParen {
+                          AST::Percent(x)
+                        } else {
+                          AST::Modulo(x, arg(lexer)?)
+                        } },
+    Token::Bang => AST::Factorial(arg(lexer)?),
+    Token::Question => { let c = arg(lexer)?;
+                         let x = arg(lexer)?;
+                         AST::Cond(c, x, arg(lexer)?) },
+    Token::Equals => match token(lexer)? {
+      Token::Ident(name) => AST::Assign(name, arg(lexer)?),
+      tok => return Err(ParseError::BadFactor(tok, lexer.span()))
+    },
+    tok => { let node : fn(Box<AST>, Box<AST>) -> AST = match tok {
+               Token::Plus => AST::Plus,
+               Token::Times => AST::Times,
+               Token::Divide => AST::Divide,
+               Token::Div => AST::IntDivide,
+               Token::Caret => AST::Pow,
+               Token::Amp => AST::BitAnd,
+               Token::Less => AST::Less,
+               Token::Greater => AST::Greater,
+               Token::LessEq => AST::LessEq,
+               Token::GreaterEq => AST::GreaterEq,
+               Token::EqEq => AST::Equal,
+               Token::NotEq => AST::NotEqual,
+               Token::Shl => AST::Shl,
+               Token::Shr => AST::Shr,
+               tok => return Err(ParseError::BadFactor(tok, lexer.span()))
+             };
+             let x = arg(lexer)?;
+             node(x, arg(lexer)?) }
+  };
+  match token(lexer)? {
+    Token::RParen => Ok(a),
+    tok => Err(ParseError::UnexpectedToken { expected : Token::RParen, found : tok,
+                                             span : lexer.span() })
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {eval, parse};
+
+  fn sexpr(input : &str) -> String {
+    parse(input).unwrap().to_sexpr()
+  }
+
+  #[test]
+  fn writes_prefix_lists() {
+    assert_eq!(sexpr("1 + 2*3"), "(+ 1 (* 2 3))");
+    assert_eq!(sexpr("(1 - 2) - 3"), "(- (- 1 2) 3)");
+    assert_eq!(sexpr("-x % 4"), "(% (- x) 4)");
+    assert_eq!(sexpr("|a| | 5%"), "(| (| a) (% 5))");
+    assert_eq!(sexpr("y = c ? max(1, 2) : 3!"), "(= y (? c (max 1 2) (! 3)))");
+    assert_eq!(sexpr("1 << 2 <= 3 != 0"), "(!= (<= (<< 1 2) 3) 0)");
+  }
+
+  #[test]
+  fn reads_back_what_it_writes() {
+    for input in &["1 + 2 * 3", "(1 - 2) - 3", "2 ^ 3 ^ 2", "-7 % 3", "1 < 2 ? 10 div 3 : 4",
+                   "|-5| | 2 & 3", "max(1, 2, 3) * 4!", "8 >> 1 == 4", "x = 1 >= 2"] {
+      let s = sexpr(input);
+      let text = from_sexpr(&s).unwrap().to_string();
+      assert_eq!(eval(&text).ok(), eval(input).ok(), "{} as {}", input, s);
+    }
+    assert_eq!(from_sexpr("(+ 1 (* 2 3))"), parse("1 + 2 * 3"));
+    assert_eq!(from_sexpr("(- 1 -2)"),
+               Ok(AST::Minus(Box::new(AST::Num("1".to_owned())), Box::new(AST::Num("-2".to_owned())))));
+    assert_eq!(from_sexpr("(* pi 0x10)"), Ok(AST::Times(Box::new(AST::FNum(consts::PI)),
+                                                         Box::new(AST::Num("16".to_owned())))));
+  }
+
+  #[test]
+  fn malformed_lists_are_errors() {
+    assert_eq!(from_sexpr("(+ 1"), Err(ParseError::UnexpectedEof));
+    assert_eq!(from_sexpr(""), Err(ParseError::UnexpectedEof));
+    assert!(matches!(from_sexpr("(+ 1 2 3)"), Err(ParseError::UnexpectedToken { .. })));
+    assert!(matches!(from_sexpr("(+ 1 2) 3"), Err(ParseError::TrailingTokens(..))));
+    assert!(matches!(from_sexpr("(; 1 2)"), Err(ParseError::BadFactor(..))));
+    assert!(matches!(from_sexpr("(= 1 2)"), Err(ParseError::BadFactor(..))));
+    let deep = format!("{}1{}", "(- ".repeat(MAX_NESTING + 1), ")".repeat(MAX_NESTING + 1));
+    assert!(matches!(from_sexpr(&deep), Err(ParseError::DepthLimitExceeded(_))));
+  }
+}
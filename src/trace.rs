@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use analysis::depth;
+use interpreter::{EvalConfig, EvalError, evaluate};
+use parser::AST;
+
+
+/**********************************************************************
+ * TRACING
+ */
+
+/// Evaluates the tree like `evaluate` in a fresh environment, writing a
+/// line to `out` for each operation as it is reduced, such as
+/// `3 * 4 = 12`, innermost first.  The last line is the result.  A
+/// conditional only shows the reductions of the branch it takes.
+/// Failing to write the trace does not stop evaluation.
+pub fn evaluate_traced<W : Write>(a : &AST, out : &mut W) -> Result<i32, EvalError> {
+  let config = EvalConfig::default();
+  if depth(a) > config.max_depth {
+    return Err(EvalError::DepthLimitExceeded);
+  }
+  let v = trace(a, &mut HashMap::new(), &config, out)?;
+  let _ = writeln!(out, "{}", v);
+  Ok(v)
+}
+
+fn trace<W : Write>(a : &AST, env : &mut HashMap<String, i32>, config : &EvalConfig,
+                    out : &mut W) -> Result<i32, EvalError> {
+  match *a {
+    AST::Num(_) | AST::FNum(_) | AST::Var(_) => evaluate(a, env, config),
+    // A negative literal is read whole, with no negation to show
+    AST::Neg(ref x) if matches!(**x, AST::Num(_)) => evaluate(a, env, config),
+    AST::Cond(ref c, ref x, ref y) => if trace(c, env, config, out)? != 0 {
+                                        trace(x, env, config, out)
+                                      } else {
+                                        trace(y, env, config, out)
+                                      },
+    // Reduce the operands to literals, then show the operation on them
+    _ => {
+      let reduced = a.try_map_children(|x| {
+        trace(x, env, config, out).map(|v| AST::Num(v.to_string()))
+      })?;
+      let v = evaluate(&reduced, env, config)?;
+      let _ = writeln!(out, "{} = {}", reduced, v);
+      Ok(v)
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parse;
+
+  // The result of the input and the trace it writes
+  fn traced(input : &str) -> (Result<i32, EvalError>, String) {
+    let mut out = Vec::new();
+    let v = evaluate_traced(&parse(input).unwrap(), &mut out);
+    (v, String::from_utf8(out).unwrap())
+  }
+
+  #[test]
+  fn reductions_come_innermost_first() {
+    assert_eq!(traced("2 + 3 * 4"), (Ok(14), "3 * 4 = 12\n2 + 12 = 14\n14\n".to_owned()));
+    assert_eq!(traced("(1 - 5) * 2"), (Ok(-8), "1 - 5 = -4\n-4 * 2 = -8\n-8\n".to_owned()));
+    assert_eq!(traced("-3 + max(1, 2)"), (Ok(-1), "max(1, 2) = 2\n-3 + 2 = -1\n-1\n".to_owned()));
+    assert_eq!(traced("7"), (Ok(7), "7\n".to_owned()));
+  }
+
+  #[test]
+  fn only_the_branch_taken_is_traced() {
+    assert_eq!(traced("1 < 2 ? 2 * 3 : 4 * 5"),
+               (Ok(6), "1 < 2 = 1\n2 * 3 = 6\n6\n".to_owned()));
+  }
+
+  #[test]
+  fn errors_end_the_trace() {
+    assert_eq!(traced("1 + 2 * 3 / 0"), (Err(EvalError::DivideByZero), "2 * 3 = 6\n".to_owned()));
+  }
+}
@@ -0,0 +1,376 @@
+use interpreter::EvalError;
+use parser::AST;
+
+
+/**********************************************************************
+ * TRANSFORMS
+ */
+
+/// Replace every use of the variable `name` with a copy of `value`.  The
+/// target of an assignment is a name rather than a use, so it is left
+/// alone, though the assigned expression is still substituted into.
+pub fn substitute(a : &AST, name : &str, value : &AST) -> AST {
+  match *a {
+    AST::Var(ref x) if x == name => value.clone(),
+    _ => a.map_children(|x| substitute(x, name, value))
+  }
+}
+
+// True if the variable `name` appears anywhere in the tree
+fn mentions(a : &AST, name : &str) -> bool {
+  match *a {
+    AST::Var(ref x) => x == name,
+    _ => a.children().into_iter().any(|x| mentions(x, name))
+  }
+}
+
+fn num(n : &str) -> Box<AST> {
+  Box::new(AST::Num(n.to_owned()))
+}
+
+/// The derivative of the tree with respect to the variable `var`, by
+/// the sum, product, quotient, and power rules, without simplifying.
+/// An assignment differentiates as its value, and a conditional as
+/// whichever branch it takes.  A power is only differentiable when
+/// `var` does not appear in its exponent, and operations with no
+/// derivative, like `%` or calls, give a `DomainError`.
+pub fn derivative(a : &AST, var : &str) -> Result<AST, EvalError> {
+  let d = |x : &AST| derivative(x, var).map(Box::new);
+  Ok(match *a {
+    AST::Num(_) | AST::FNum(_) => AST::Num("0".to_owned()),
+    AST::Var(ref x) => AST::Num(if x == var { "1" } else { "0" }.to_owned()),
+    AST::Plus(ref x, ref y) => AST::Plus(d(x)?, d(y)?),
+    AST::Minus(ref x, ref y) => AST::Minus(d(x)?, d(y)?),
+    AST::Neg(ref x) => AST::Neg(d(x)?),
+    AST::Percent(ref x) => AST::Percent(d(x)?),
+    // (uv)' = u'v + uv'
+    AST::Times(ref x, ref y) => AST::Plus(Box::new(AST::Times(d(x)?, y.clone())),
+                                          Box::new(AST::Times(x.clone(), d(y)?))),
+    // (u/v)' = (u'v - uv') / v^2
+    AST::Divide(ref x, ref y) => {
+      let top = AST::Minus(Box::new(AST::Times(d(x)?, y.clone())),
+                           Box::new(AST::Times(x.clone(), d(y)?)));
+      AST::Divide(Box::new(top), Box::new(AST::Pow(y.clone(), num("2"))))
+    },
+    // (u^n)' = n * u^(n - 1) * u'
+    AST::Pow(ref x, ref n) if !mentions(n, var) => {
+      let lower = AST::Pow(x.clone(), Box::new(AST::Minus(n.clone(), num("1"))));
+      AST::Times(Box::new(AST::Times(n.clone(), Box::new(lower))), d(x)?)
+    },
+    AST::Sum(ref xs) => AST::Sum(xs.iter().map(|x| derivative(x, var)).collect::<Result<_, _>>()?),
+    // Each factor's derivative times all the other factors
+    AST::Product(ref xs) => {
+      let mut terms = Vec::new();
+      for i in 0..xs.len() {
+        let mut factors = xs.clone();
+        factors[i] = derivative(&xs[i], var)?;
+        terms.push(AST::Product(factors));
+      }
+      AST::Sum(terms)
+    },
+    AST::Assign(_, ref x) => *d(x)?,
+    AST::Cond(ref c, ref x, ref y) => AST::Cond(c.clone(), d(x)?, d(y)?),
+    _ => return Err(EvalError::DomainError)
+  })
+}
+
+// True if the tree is the integer literal n
+fn is_num(a : &AST, n : i32) -> bool {
+  match *a {
+    AST::Num(ref x) => x.parse() == Ok(n),
+    _ => false
+  }
+}
+
+/// Rewrite `x + 0`, `0 + x`, `x - 0`, `x * 1`, and `1 * x` to `x`, `0 - x`
+/// to `-x`, and `x * 0` and `0 * x` to `0`, innermost first, so that none
+/// are left and simplifying again changes nothing.  Unlike `fold` this
+/// works on trees with variables, though `x * 0` drops x even where
+/// evaluating it would fail or assign.
+pub fn simplify(a : AST) -> AST {
+  simplified(&a)
+}
+
+fn simplified(a : &AST) -> AST {
+  match a.map_children(simplified) {
+    AST::Plus(x, y) if is_num(&y, 0) => *x,
+    AST::Plus(x, y) if is_num(&x, 0) => *y,
+    AST::Minus(x, y) if is_num(&y, 0) => *x,
+    AST::Minus(x, y) if is_num(&x, 0) => AST::Neg(y),
+    AST::Times(x, y) if is_num(&x, 0) || is_num(&y, 0) => AST::Num("0".to_owned()),
+    AST::Times(x, y) if is_num(&y, 1) => *x,
+    AST::Times(x, y) if is_num(&x, 1) => *y,
+    a => a
+  }
+}
+
+/// Put the operands of every chain of `+`, and of every chain of `*`,
+/// into one canonical order, so trees that differ only in how such
+/// chains are ordered or grouped become equal: `1 + 2 + 3`, `3 + (1 + 2)`,
+/// and `2 + 3 + 1` all normalize to `1 + 2 + 3`.  A chain is rebuilt
+/// left to right, as it would have been parsed.  Reordering can change
+/// whether an integer sum overflows partway, or the order that
+/// assignments inside the chain happen in.
+pub fn normalize(a : AST) -> AST {
+  normalized(&a)
+}
+
+fn normalized(a : &AST) -> AST {
+  match *a {
+    AST::Plus(..) => chain(a, &|a| match *a {
+      AST::Plus(ref x, ref y) => Some((&**x, &**y)),
+      _ => None
+    }, AST::Plus),
+    AST::Times(..) => chain(a, &|a| match *a {
+      AST::Times(ref x, ref y) => Some((&**x, &**y)),
+      _ => None
+    }, AST::Times),
+    _ => a.map_children(normalized)
+  }
+}
+
+// Normalize the operands of the chain of one operator starting at a,
+// which split tells apart from anything else, and rebuild it sorted
+fn chain(a : &AST, split : &dyn Fn(&AST) -> Option<(&AST, &AST)>,
+         node : fn(Box<AST>, Box<AST>) -> AST) -> AST {
+  let mut operands = Vec::new();
+  let mut pending = vec![a];
+  while let Some(x) = pending.pop() {
+    match split(x) {
+      Some((x, y)) => { pending.push(y);
+                        pending.push(x); },
+      None => operands.push(normalized(x))
+    }
+  }
+  // The debug form spells out the whole tree, so it orders any two
+  // different trees consistently
+  operands.sort_by_cached_key(|x| format!("{:?}", x));
+  let mut operands = operands.into_iter();
+  match operands.next() {
+    Some(first) => operands.fold(first, |x, y| node(Box::new(x), Box::new(y))),
+    // Not so long as split took a apart, as it does where this is used
+    None => a.clone()
+  }
+}
+
+/// Rewrite every chain of `+` and `-` as one `Sum` of its terms, each
+/// negated if it is subtracted, and every chain of `*` as one `Product`,
+/// so `1 - 2 + 3` becomes `Sum([1, Neg(2), 3])`.  Parentheses don't end
+/// a chain, so `1 - (2 - 3)` has the terms 1, -2, and 3.  Negating a
+/// term can overflow where subtracting it would not, as for the most
+/// negative integer.
+pub fn flatten(a : &AST) -> AST {
+  match *a {
+    AST::Plus(..) | AST::Minus(..) => AST::Sum(terms(a)),
+    AST::Times(..) => AST::Product(factors(a)),
+    _ => a.map_children(flatten)
+  }
+}
+
+// The flattened terms of the chain of + and - starting at a
+fn terms(a : &AST) -> Vec<AST> {
+  let mut terms = Vec::new();
+  let mut pending = vec![(a, false)];
+  while let Some((x, negated)) = pending.pop() {
+    match *x {
+      AST::Plus(ref x, ref y) => { pending.push((y, negated));
+                                   pending.push((x, negated)); },
+      AST::Minus(ref x, ref y) => { pending.push((y, !negated));
+                                    pending.push((x, negated)); },
+      _ if negated => terms.push(AST::Neg(Box::new(flatten(x)))),
+      _ => terms.push(flatten(x))
+    }
+  }
+  terms
+}
+
+// The flattened factors of the chain of * starting at a
+fn factors(a : &AST) -> Vec<AST> {
+  let mut factors = Vec::new();
+  let mut pending = vec![a];
+  while let Some(x) = pending.pop() {
+    match *x {
+      AST::Times(ref x, ref y) => { pending.push(y);
+                                    pending.push(x); },
+      _ => factors.push(flatten(x))
+    }
+  }
+  factors
+}
+
+/// Rewrite every `Sum` and `Product` as a chain of binary `+` or `*`
+/// that groups to the left, as the parser builds them, and an empty one
+/// as its literal 0 or 1.  Subtracted terms stay negated, so
+/// `flatten` and then `unflatten` turn `1 - 2` into `1 + -2`, which
+/// evaluates the same.
+pub fn unflatten(a : &AST) -> AST {
+  match *a {
+    AST::Sum(ref xs) => rebuild(xs, "0", AST::Plus),
+    AST::Product(ref xs) => rebuild(xs, "1", AST::Times),
+    _ => a.map_children(unflatten)
+  }
+}
+
+fn rebuild(xs : &[AST], empty : &str, node : fn(Box<AST>, Box<AST>) -> AST) -> AST {
+  let mut xs = xs.iter().map(unflatten);
+  let first = xs.next().unwrap_or_else(|| AST::Num(empty.to_owned()));
+  xs.fold(first, |x, y| node(Box::new(x), Box::new(y)))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use super::*;
+  use interpreter::{EvalConfig, evaluate, evaluate_iterative, evaluate_memoized};
+  use parse;
+
+  fn value(a : &AST) -> Result<i32, EvalError> {
+    evaluate_iterative(a, &mut HashMap::new(), &EvalConfig::default())
+  }
+
+  #[test]
+  fn substitute_replaces_every_use() {
+    let a = substitute(&parse("x * x").unwrap(), "x", &parse("1 + 2").unwrap());
+    assert_eq!(a, parse("(1 + 2) * (1 + 2)").unwrap());
+    assert_eq!(value(&a), Ok(9));
+    // Other variables, and assignment targets, stay
+    let a = substitute(&parse("x = x + y").unwrap(), "x", &parse("2").unwrap());
+    assert_eq!(a, parse("x = 2 + y").unwrap());
+    let a = parse("max(y, 3)").unwrap();
+    assert_eq!(substitute(&a, "x", &parse("2").unwrap()), a);
+  }
+
+  // The derivative in x of the input, evaluated at x
+  fn slope(input : &str, x : f64) -> Result<f64, EvalError> {
+    let d = derivative(&parse(input).unwrap(), "x")?;
+    let mut env = HashMap::new();
+    env.insert("x".to_owned(), x);
+    evaluate_iterative(&d, &mut env, &EvalConfig::default())
+  }
+
+  #[test]
+  fn derivatives_evaluate_to_the_slope() {
+    assert_eq!(slope("x * x", 3.0), Ok(6.0));
+    assert_eq!(slope("x * x", -1.5), Ok(-3.0));
+    assert_eq!(slope("3 * x^3 - 2 * x + 5", 2.0), Ok(34.0));
+    assert_eq!(slope("3 * x^3 - 2 * x + 5", -1.0), Ok(7.0));
+    assert_eq!(slope("x / (x + 1)", 1.0), Ok(0.25));
+    assert_eq!(slope("-x + y", 4.0), Ok(-1.0));
+    assert_eq!(slope("42", 4.0), Ok(0.0));
+  }
+
+  #[test]
+  fn derivatives_are_not_simplified() {
+    assert_eq!(derivative(&parse("x * x").unwrap(), "x"), Ok(parse("1 * x + x * 1").unwrap()));
+    assert_eq!(derivative(&parse("x + y").unwrap(), "y"), Ok(parse("0 + 1").unwrap()));
+  }
+
+  #[test]
+  fn some_operations_have_no_derivative() {
+    assert_eq!(slope("2 ^ x", 1.0), Err(EvalError::DomainError));
+    assert_eq!(slope("x % 2", 1.0), Err(EvalError::DomainError));
+    assert_eq!(slope("sin(x)", 1.0), Err(EvalError::DomainError));
+    // A power is, so long as x is not in its exponent
+    assert_eq!(slope("x ^ 2", 3.0), Ok(6.0));
+  }
+
+  fn simple(input : &str) -> String {
+    simplify(parse(input).unwrap()).to_string()
+  }
+
+  #[test]
+  fn simplify_applies_each_identity() {
+    assert_eq!(simple("x + 0"), "x");
+    assert_eq!(simple("0 + x"), "x");
+    assert_eq!(simple("x - 0"), "x");
+    assert_eq!(simple("0 - x"), "-x");
+    assert_eq!(simple("x * 1"), "x");
+    assert_eq!(simple("1 * x"), "x");
+    assert_eq!(simple("x * 0"), "0");
+    assert_eq!(simple("0 * x"), "0");
+    assert_eq!(simple("(x + 0) * 1"), "x");
+    assert_eq!(simple("x - 1 + y * 2"), "x - 1 + y * 2");
+  }
+
+  #[test]
+  fn simplify_is_idempotent() {
+    for input in &["(x + 0) * 1", "0 - (y * 1 + 0)", "(0 * x + 1) * (z - 0)", "f(x * 1, 0 + y)",
+                   "1 * 1 + 0 * 0"] {
+      let once = simplify(parse(input).unwrap());
+      assert_eq!(simplify(once.clone()), once, "{}", input);
+    }
+    assert_eq!(simple("(0 * x + 1) * (z - 0)"), "z");
+    assert_eq!(simple("0 - (y * 1 + 0)"), "-y");
+    // Cleaning up a derivative
+    let d = derivative(&parse("x * x").unwrap(), "x").unwrap();
+    assert_eq!(simplify(d).to_string(), "x + x");
+  }
+
+  #[test]
+  fn normalize_orders_commutative_chains() {
+    let normal = |input : &str| normalize(parse(input).unwrap());
+    assert_eq!(normal("1 + 2 + 3"), normal("3 + 1 + 2"));
+    assert_eq!(normal("3 + (1 + 2)"), parse("1 + 2 + 3").unwrap());
+    assert_eq!(normal("2 + 3 + 1"), parse("1 + 2 + 3").unwrap());
+    assert_eq!(normal("y * x * 2"), normal("2 * (x * y)"));
+    // Inside other nodes too
+    assert_eq!(normal("f(b + a) - (z * y)"), normal("f(a + b) - (y * z)"));
+    // Only + and * commute
+    assert_ne!(normal("1 - 2"), normal("2 - 1"));
+    assert_ne!(normal("x / y"), normal("y / x"));
+    assert_ne!(normal("1 + 2 * 3"), normal("2 + 1 * 3"));
+    let a = normal("c + b * a + 1");
+    assert_eq!(normalize(a.clone()), a);
+  }
+
+  fn flat(input : &str) -> AST {
+    flatten(&parse(input).unwrap())
+  }
+
+  #[test]
+  fn flatten_gathers_chains() {
+    let n = |x : &str| AST::Num(x.to_owned());
+    let neg = |x : &str| AST::Neg(Box::new(n(x)));
+    assert_eq!(flat("1 - 2 + 3"), AST::Sum(vec![n("1"), neg("2"), n("3")]));
+    assert_eq!(flat("1 - (2 - 3)"), AST::Sum(vec![n("1"), neg("2"), n("3")]));
+    assert_eq!(flat("1 - 2 - 3"), AST::Sum(vec![n("1"), neg("2"), neg("3")]));
+    assert_eq!(flat("2 * 3 * 4"), AST::Product(vec![n("2"), n("3"), n("4")]));
+    assert_eq!(flat("1 + 2 * 3 * 4 - 5"),
+               AST::Sum(vec![n("1"), AST::Product(vec![n("2"), n("3"), n("4")]), neg("5")]));
+    assert_eq!(flat("-(1 + 2)"), AST::Neg(Box::new(AST::Sum(vec![n("1"), n("2")]))));
+    assert_eq!(flat("max(1 + 2, 3)"),
+               AST::Call("max".to_owned(), vec![AST::Sum(vec![n("1"), n("2")]), n("3")]));
+    assert_eq!(flat("7"), n("7"));
+  }
+
+  #[test]
+  fn flattening_preserves_values() {
+    for input in &["1 - 2 + 3", "1 - (2 - 3) - 4", "2 * 3 * 4 - 5 * 6", "(1 + 2) * (3 - 4) * 5",
+                   "10 - 2 * 3 ^ 2 + 7 % 4", "1 < 2 ? 3 - 4 : 5 * 6", "-(1 - 2) * 3"] {
+      let a = parse(input).unwrap();
+      let f = flatten(&a);
+      assert_eq!(value(&f), value(&a), "{}", input);
+      let config = EvalConfig::default();
+      assert_eq!(evaluate::<i32>(&f, &mut HashMap::new(), &config), value(&a), "{}", input);
+      assert_eq!(evaluate_memoized::<i32>(&f, &mut HashMap::new(), &config), value(&a), "{}", input);
+      assert_eq!(value(&unflatten(&f)), value(&a), "{}", input);
+    }
+    // Without subtraction, the round trip gives back the original tree
+    for input in &["1 + 2 + 3", "1 + 2 * 3 * 4 + 5", "(1 + 2) * 3"] {
+      let a = parse(input).unwrap();
+      assert_eq!(unflatten(&flatten(&a)), a, "{}", input);
+    }
+    assert_eq!(unflatten(&flat("1 - 2")).to_string(), parse("1 + -2").unwrap().to_string());
+  }
+
+  #[test]
+  fn empty_chains_are_their_identities() {
+    assert_eq!(value(&AST::Sum(vec![])), Ok(0));
+    assert_eq!(value(&AST::Product(vec![])), Ok(1));
+    assert_eq!(value(&AST::Sum(vec![AST::Num("5".to_owned())])), Ok(5));
+    assert_eq!(unflatten(&AST::Sum(vec![])), AST::Num("0".to_owned()));
+    assert_eq!(unflatten(&AST::Product(vec![])), AST::Num("1".to_owned()));
+  }
+}
@@ -0,0 +1,222 @@
+//! Runs the calc binary the way a shell would and checks what it prints
+//! and how it exits.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{self, Command, Output, Stdio};
+
+// Run calc with args and the given stdin
+fn calc(args : &[&str], input : &str) -> Output {
+  let mut child = Command::new(env!("CARGO_BIN_EXE_calc"))
+    .args(args)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("could not run calc");
+  // calc may exit without reading, as on a usage error, closing the pipe
+  let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+  child.wait_with_output().unwrap()
+}
+
+fn stdout(output : &Output) -> String {
+  String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output : &Output) -> String {
+  String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// What calc --expr prints for expr, followed by any other args
+fn expr(expr : &str, args : &[&str]) -> String {
+  let mut all = vec!["--expr", expr];
+  all.extend_from_slice(args);
+  let output = calc(&all, "");
+  assert!(output.status.success(), "{}", stderr(&output));
+  stdout(&output)
+}
+
+#[test]
+fn expr_evaluates_and_exits() {
+  assert_eq!(expr("1 + 2 * 3", &[]), "7\n");
+  assert_eq!(expr("x = 4; ", &[]), "4\n");
+}
+
+#[test]
+fn expr_evaluates_floats() {
+  assert_eq!(expr("1.5 * 2", &[]), "3\n");
+  assert_eq!(expr("pi * 2", &[]), "6.283185\n");
+  assert_eq!(expr("200 * 10%", &[]), "20\n");
+  assert_eq!(expr("sin(0)", &[]), "0\n");
+  assert_eq!(expr("7 / 2.0", &[]), "3.5\n");
+}
+
+#[test]
+fn expr_rounds_to_precision() {
+  assert_eq!(expr("pi", &["--precision", "2"]), "3.14\n");
+  assert_eq!(expr("1 / 3.0", &["--precision", "0"]), "0\n");
+  assert_eq!(expr("2.50", &["--precision", "4"]), "2.5\n");
+  let output = calc(&["--expr", "1", "--precision", "x"], "");
+  assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn expr_error_exits_with_failure() {
+  let output = calc(&["--expr", "1 / 0"], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stderr(&output), "Error: division by zero\n");
+  let output = calc(&["--expr", "1 +"], "");
+  assert_eq!(output.status.code(), Some(1));
+  let output = calc(&["--expr"], "");
+  assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn json_is_refused_in_the_repl() {
+  let output = calc(&["--interactive", "--json"], "1 + 2\n");
+  assert_eq!(output.status.code(), Some(2));
+  assert!(stderr(&output).starts_with("--json cannot be used in the REPL"), "{}", stderr(&output));
+  assert_eq!(stdout(&output), "");
+  let output = calc(&["--batch", "--json"], "1 + 2\n");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "{\"result\": 3}\n");
+}
+
+#[test]
+fn each_statement_prints_its_result() {
+  let output = calc(&["--batch"], "1+2; 3*4; 10-1;\n");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "3\n12\n9\n");
+}
+
+// A file named for the test holding contents, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+  fn new(name : &str, contents : &str) -> TempFile {
+    let path = env::temp_dir().join(format!("calc-{}-{}", process::id(), name));
+    fs::write(&path, contents).unwrap();
+    TempFile(path)
+  }
+
+  fn path(&self) -> &str {
+    self.0.to_str().unwrap()
+  }
+}
+
+impl Drop for TempFile {
+  fn drop(&mut self) {
+    let _ = fs::remove_file(&self.0);
+  }
+}
+
+#[test]
+fn file_evaluates_each_statement() {
+  let file = TempFile::new("statements", "1 + 2;\n3 * ;\nx = 4; x * x;\n");
+  let output = calc(&["--file", file.path()], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "3\n4\n16\n");
+  assert_eq!(stderr(&output), "line 2: Error: expected an operand, found ';'\n");
+}
+
+#[test]
+fn missing_file_is_an_error() {
+  let output = calc(&["--file", "/nonexistent/calc-input"], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert!(stderr(&output).starts_with("Could not open /nonexistent/calc-input"), "{}", stderr(&output));
+  let output = calc(&["--file", "x", "--expr", "1"], "");
+  assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn expr_error_points_at_its_column() {
+  let output = calc(&["--expr", "1 + * 2"], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stderr(&output), "1 + * 2\n    ^\nError: expected an operand, found '*'\n");
+}
+
+// The result and the two durations of a line printed with --time
+fn timed(line : &str) -> (String, u128, u128) {
+  let (result, rest) = line.split_once(" (parse ").expect(line);
+  let (parse, eval) = rest.trim_end_matches("µs)").split_once("µs, eval ").expect(line);
+  (result.to_owned(), parse.parse().expect(line), eval.parse().expect(line))
+}
+
+#[test]
+fn time_reports_each_statement() {
+  let line = expr("2 + 3 * 4", &["--time"]);
+  assert_eq!(timed(line.trim_end()).0, "14");
+  assert_eq!(expr("2 + 3 * 4", &[]), "14\n");
+  let output = calc(&["--batch", "--time"], "1 + 1; 2.5 * 2\n");
+  let lines : Vec<_> = stdout(&output).lines().map(timed).collect();
+  assert_eq!(lines.iter().map(|l| l.0.as_str()).collect::<Vec<_>>(), ["2", "5"]);
+  let output = calc(&["--expr", "6 * 7", "--time", "--json"], "");
+  let out = stdout(&output);
+  assert!(out.starts_with("{\"result\": 42, \"parse_us\": ") && out.contains(", \"eval_us\": "), "{}", out);
+}
+
+#[test]
+fn piped_input_is_read_all_at_once() {
+  let output = calc(&["--batch"], "1+2; 3*4\n");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "3\n12\n");
+  // Without a terminal, batch mode is the default, and prints no prompts
+  let output = calc(&[], "x = 2;\nx * 5\n");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "2\n10\n");
+  let output = calc(&["--batch"], "1 / 0; 7\n");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "7\n");
+  assert_eq!(stderr(&output), "line 1: Error: division by zero\n");
+  let output = calc(&["--batch", "--interactive"], "");
+  assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn prompt_is_only_written_interactively() {
+  let output = calc(&["--interactive", "--prompt", "calc] "], "1 + 2\n");
+  assert!(output.status.success());
+  assert!(stdout(&output).ends_with("calc] 3\ncalc] \n"), "{}", stdout(&output));
+  let output = calc(&["--batch", "--prompt", "calc] "], "1 + 2\n");
+  assert!(output.status.success());
+  assert_eq!(stdout(&output), "3\n");
+  let output = calc(&["--interactive", "--prompt"], "");
+  assert_eq!(output.status.code(), Some(2));
+  assert!(stderr(&output).contains("--prompt needs a value"), "{}", stderr(&output));
+}
+
+#[test]
+fn json_errors_are_objects_on_stdout() {
+  let output = calc(&["--expr", "1 / 0", "--json"], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "{\"error\": \"division by zero\"}\n");
+  assert_eq!(stderr(&output), "");
+  let output = calc(&["--expr", "1 \" 2", "--json"], "");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "{\"error\": \"unexpected character '\\\"'\", \"line\": 1, \"column\": 3}\n");
+  let output = calc(&["--expr", "1 \\ 2", "--json"], "");
+  assert_eq!(stdout(&output), "{\"error\": \"unexpected character '\\\\'\", \"line\": 1, \"column\": 3}\n");
+  // In batch mode each line's result or error is its own object
+  let output = calc(&["--batch", "--json"], "1 + 2;\n1 / 0;\n4\n");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(stdout(&output), "{\"result\": 3}\n{\"error\": \"division by zero\", \"line\": 2}\n{\"result\": 4}\n");
+  assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn base_chooses_how_results_print() {
+  assert_eq!(expr("5 - 10", &["--base", "bin"]), "-0b101\n");
+  assert_eq!(expr("10", &["--base", "bin"]), "0b1010\n");
+  assert_eq!(expr("64", &["--base", "oct"]), "0o100\n");
+  assert_eq!(expr("255", &["--base", "hex"]), "0xff\n");
+  assert_eq!(expr("255", &["--base", "dec"]), "255\n");
+  let output = calc(&["--batch", "--base", "bin"], "3; 2 * 2\n");
+  assert_eq!(stdout(&output), "0b11\n0b100\n");
+  let output = calc(&["--expr", "1", "--base", "ter"], "");
+  assert_eq!(output.status.code(), Some(2));
+  assert!(stderr(&output).contains("unrecognized base: ter"), "{}", stderr(&output));
+  let output = calc(&["--expr", "1", "--base"], "");
+  assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,60 @@
+//! Locks down how parsed trees print.  Each expression in
+//! `fixtures/expressions.txt` is parsed, and its `Display` and `{:?}`
+//! forms, or its error, are compared with the committed golden files.
+//! Run with `UPDATE_GOLDEN=1` to rewrite them after a deliberate change.
+
+extern crate calc;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use calc::{AST, parse};
+
+fn fixture(name : &str) -> PathBuf {
+  PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+}
+
+// Each expression of the fixtures, skipping blank lines and comments
+fn expressions() -> Vec<String> {
+  let input = fs::read_to_string(fixture("expressions.txt")).expect("could not read the expressions");
+  input.lines()
+       .map(str::trim)
+       .filter(|line| !line.is_empty() && !line.starts_with('#'))
+       .map(str::to_owned)
+       .collect()
+}
+
+// Compare one line per expression, `input => shown`, with the golden
+// file, or rewrite it if asked to
+fn check(golden : &str, show : fn(&AST) -> String) {
+  let mut actual = String::new();
+  for input in expressions() {
+    let shown = match parse(&input) {
+      Ok(a) => show(&a),
+      Err(e) => format!("error: {}", e)
+    };
+    actual.push_str(&format!("{} => {}\n", input, shown));
+  }
+  let path = fixture(golden);
+  if env::var_os("UPDATE_GOLDEN").is_some() {
+    fs::write(&path, &actual).expect("could not write the golden file");
+    return;
+  }
+  let expected = fs::read_to_string(&path).expect("could not read the golden file");
+  for (i, (e, a)) in expected.lines().zip(actual.lines()).enumerate() {
+    assert_eq!(e, a, "{} differs at line {}", golden, i + 1);
+  }
+  assert_eq!(expected.lines().count(), actual.lines().count(),
+             "{} has a different number of lines", golden);
+}
+
+#[test]
+fn display_matches_golden() {
+  check("display.golden", |a| a.to_string());
+}
+
+#[test]
+fn debug_matches_golden() {
+  check("debug.golden", |a| format!("{:?}", a));
+}
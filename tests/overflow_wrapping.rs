@@ -0,0 +1,50 @@
+//! With the `overflow-wrapping` feature, integer arithmetic wraps in
+//! two's complement whatever `EvalConfig` says, rather than overflowing.
+
+#![cfg(feature = "overflow-wrapping")]
+
+extern crate calc;
+
+use std::collections::HashMap;
+
+use calc::{EvalConfig, EvalError, OverflowMode, evaluate, fold, parse, AST};
+
+fn eval(input : &str, overflow : OverflowMode) -> Result<i32, EvalError> {
+  let config = EvalConfig { overflow, ..EvalConfig::default() };
+  evaluate(&parse(input).unwrap(), &mut HashMap::new(), &config)
+}
+
+#[test]
+fn wraps_at_max() {
+  assert_eq!(eval("2147483647 + 1", OverflowMode::Error), Ok(i32::MIN));
+  assert_eq!(eval("2147483647 * 2", OverflowMode::Saturate), Ok(-2));
+  assert_eq!(eval("-2147483647 - 2", OverflowMode::Error), Ok(i32::MAX));
+  assert_eq!(eval("-(-2147483647 - 1)", OverflowMode::Error), Ok(i32::MIN));
+  assert_eq!(eval("2 ^ 31", OverflowMode::Error), Ok(i32::MIN));
+}
+
+#[test]
+fn wraps_everything_else_too() {
+  let error = OverflowMode::Error;
+  assert_eq!(eval("-2147483648 / -1", error), Ok(i32::MIN));
+  assert_eq!(eval("-2147483648 % -1", error), Ok(0));
+  assert_eq!(eval("abs(-2147483648)", error), Ok(i32::MIN));
+  assert_eq!(eval("13!", error), Ok(1932053504));
+  assert_eq!(eval("40!", error), Ok(0));
+  assert_eq!(eval("2147483647!", error), Ok(0));
+  assert_eq!(eval("pow(10, 20)", error), Ok(1661992960));
+  assert_eq!(eval("pow(-1, 2147483647)", error), Ok(-1));
+  assert_eq!(eval("lcm(65536, 65537)", error), Ok(65536));
+  assert_eq!(eval("gcd(-2147483648, 0)", error), Ok(i32::MIN));
+  // Other errors are still errors
+  assert_eq!(eval("1 / 0", error), Err(EvalError::DivideByZero));
+  assert_eq!(eval("2 ^ -1", error), Err(EvalError::NegativeExponent));
+  assert_eq!(eval("1 << 32", error), Err(EvalError::InvalidShift));
+  assert_eq!(eval("2147483648", error), Err(EvalError::Overflow));
+}
+
+#[test]
+fn folding_wraps_as_evaluation_does() {
+  assert_eq!(fold(parse("2147483647 + 1").unwrap()), AST::Num("-2147483648".to_owned()));
+  assert_eq!(fold(parse("-2147483648 / -1").unwrap()), AST::Num("-2147483648".to_owned()));
+}